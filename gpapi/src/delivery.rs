@@ -0,0 +1,202 @@
+//! Typed view of the `delivery` response, as returned by `Gpapi::get_download_info`.
+
+use googleplay_protobuf::{AndroidAppDeliveryData, AppFileMetadata, HttpCookie, SplitDeliveryData};
+use serde::{Deserialize, Serialize};
+
+/// Download URLs and metadata for a package: the main APK, its splits and any additional
+/// (OBB) files.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DownloadInfo {
+    pub main_apk: ApkFile,
+    pub splits: Vec<SplitApk>,
+    pub additional_files: Vec<AdditionalFile>,
+    /// Cookies that have to be sent along with every download request.
+    pub download_auth_cookies: Vec<DownloadAuthCookie>,
+}
+
+/// A downloadable APK, either served as-is or gzipped.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApkFile {
+    pub download_url: Option<String>,
+    pub size: Option<i64>,
+    pub gzipped_download_url: Option<String>,
+    pub gzipped_size: Option<i64>,
+    pub sha1: Option<String>,
+    pub sha256: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SplitApk {
+    /// Split name, e.g. `config.arm64_v8a`
+    pub name: Option<String>,
+    pub file: ApkFile,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdditionalFileType {
+    Main,
+    Patch,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AdditionalFile {
+    /// Filename the file is expected under on the device, e.g. `main.1234.com.example.obb`
+    pub filename: String,
+    pub file_type: AdditionalFileType,
+    pub version_code: i32,
+    pub download_url: Option<String>,
+    pub size: Option<i64>,
+    pub gzipped_download_url: Option<String>,
+    pub gzipped_size: Option<i64>,
+    pub sha1: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DownloadAuthCookie {
+    pub name: String,
+    pub value: String,
+}
+
+impl DownloadInfo {
+    pub(crate) fn from_delivery_data(pkg_name: &str, data: AndroidAppDeliveryData) -> Self {
+        DownloadInfo {
+            main_apk: ApkFile {
+                download_url: data.download_url,
+                size: data.download_size,
+                gzipped_download_url: data.compressed_download_url,
+                gzipped_size: data.compressed_size,
+                sha1: data.sha1,
+                sha256: data.sha256,
+            },
+            splits: data
+                .split_delivery_data
+                .into_iter()
+                .map(SplitApk::from)
+                .collect(),
+            additional_files: data
+                .additional_file
+                .into_iter()
+                .filter_map(|file| AdditionalFile::from_metadata(pkg_name, file))
+                .collect(),
+            download_auth_cookies: data
+                .download_auth_cookie
+                .into_iter()
+                .filter_map(DownloadAuthCookie::from_cookie)
+                .collect(),
+        }
+    }
+}
+
+impl From<SplitDeliveryData> for SplitApk {
+    fn from(split: SplitDeliveryData) -> Self {
+        SplitApk {
+            name: split.name,
+            file: ApkFile {
+                download_url: split.download_url,
+                size: split.download_size,
+                gzipped_download_url: split.compressed_download_url,
+                gzipped_size: split.compressed_size,
+                sha1: split.sha1,
+                sha256: split.sha256,
+            },
+        }
+    }
+}
+
+impl AdditionalFile {
+    fn from_metadata(pkg_name: &str, file: AppFileMetadata) -> Option<Self> {
+        let file_type = match file.file_type? {
+            0 => AdditionalFileType::Main,
+            _ => AdditionalFileType::Patch,
+        };
+        let version_code = file.version_code?;
+        let main_patch = match file_type {
+            AdditionalFileType::Main => "main",
+            AdditionalFileType::Patch => "patch",
+        };
+        Some(AdditionalFile {
+            filename: format!("{}.{}.{}.obb", main_patch, version_code, pkg_name),
+            file_type,
+            version_code,
+            download_url: file.download_url,
+            size: file.size,
+            gzipped_download_url: file.compressed_download_url,
+            gzipped_size: file.compressed_size,
+            sha1: file.sha1,
+        })
+    }
+}
+
+impl DownloadAuthCookie {
+    fn from_cookie(cookie: HttpCookie) -> Option<Self> {
+        Some(DownloadAuthCookie {
+            name: cookie.name?,
+            value: cookie.value?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_delivery_data() {
+        let data = AndroidAppDeliveryData {
+            download_url: Some(String::from("https://example.com/base")),
+            download_size: Some(100),
+            sha256: Some(String::from("abc")),
+            split_delivery_data: vec![SplitDeliveryData {
+                name: Some(String::from("config.arm64_v8a")),
+                download_url: Some(String::from("https://example.com/split")),
+                compressed_download_url: Some(String::from("https://example.com/split.gz")),
+                download_size: Some(20),
+                compressed_size: Some(10),
+                ..Default::default()
+            }],
+            additional_file: vec![
+                AppFileMetadata {
+                    file_type: Some(0),
+                    version_code: Some(42),
+                    download_url: Some(String::from("https://example.com/obb")),
+                    size: Some(1000),
+                    ..Default::default()
+                },
+                AppFileMetadata {
+                    file_type: Some(1),
+                    ..Default::default()
+                },
+            ],
+            download_auth_cookie: vec![HttpCookie {
+                name: Some(String::from("MarketDA")),
+                value: Some(String::from("123")),
+            }],
+            ..Default::default()
+        };
+
+        let info = DownloadInfo::from_delivery_data("com.example", data);
+        assert_eq!(
+            info.main_apk.download_url.as_deref(),
+            Some("https://example.com/base")
+        );
+        assert_eq!(info.main_apk.size, Some(100));
+        assert_eq!(info.main_apk.sha256.as_deref(), Some("abc"));
+
+        assert_eq!(info.splits.len(), 1);
+        assert_eq!(info.splits[0].name.as_deref(), Some("config.arm64_v8a"));
+        assert_eq!(info.splits[0].file.gzipped_size, Some(10));
+
+        // Files without a version code can't be named and are skipped
+        assert_eq!(info.additional_files.len(), 1);
+        assert_eq!(info.additional_files[0].filename, "main.42.com.example.obb");
+        assert_eq!(info.additional_files[0].file_type, AdditionalFileType::Main);
+
+        assert_eq!(
+            info.download_auth_cookies,
+            vec![DownloadAuthCookie {
+                name: String::from("MarketDA"),
+                value: String::from("123"),
+            }]
+        );
+    }
+}
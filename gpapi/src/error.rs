@@ -13,11 +13,59 @@ pub enum ErrorKind {
     PermissionDenied,
     InvalidResponse,
     LoginRequired,
+    InvalidSession,
+    /// The requested app or resource does not exist.
+    NotFound,
+    /// The account is not allowed to perform this request.
+    Unauthorized,
+    /// The auth token was rejected and has to be renewed.
+    TokenExpired,
+    /// Too many requests; holds the number of seconds to wait, if the server said so.
+    RateLimited(Option<u64>),
+    /// The app is not compatible with the configured device.
+    IncompatibleDevice,
+    /// The app has to be purchased before it can be downloaded.
+    PaidApp,
+    /// The app is not available in the account's country.
+    GeoRestricted,
+    /// The auth endpoint rejected the oauth or aas token (`Error=BadAuthentication`).
+    BadAuthentication(AuthHints),
+    /// The account has to sign in through a browser first, e.g. to pass a challenge
+    /// (`Error=NeedsBrowser`).
+    NeedsBrowser(AuthHints),
+    /// The account requires device management or has sync disabled
+    /// (`Error=DeviceManagementRequiredOrSyncDisabled`).
+    DeviceManagementRequired(AuthHints),
+    /// Any other `Error=` value returned by the auth endpoint.
+    AuthFailed(String, AuthHints),
+    /// Any other failure reported by the server, with the HTTP status and the error message
+    /// the server asked to display.
+    Server(u16, Option<String>),
     IO(IOError),
     Str(String),
     Other(Box<dyn StdError + Send + Sync>),
 }
 
+/// The `Url` and `ErrorDetail` fields Google sends along with a rejected auth request, pointing
+/// at what the account owner has to do to fix it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuthHints {
+    pub url: Option<String>,
+    pub detail: Option<String>,
+}
+
+impl fmt::Display for AuthHints {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(detail) = &self.detail {
+            write!(f, " ({})", detail)?;
+        }
+        if let Some(url) = &self.url {
+            write!(f, ", see {}", url)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct Error {
     kind: ErrorKind,
@@ -31,6 +79,68 @@ impl Error {
     pub fn kind(&self) -> &ErrorKind {
         &self.kind
     }
+
+    /// Classify a failed Play backend response from its HTTP status, its `Retry-After` header
+    /// and the error message the server asked the client to display, if any. Statuses with a
+    /// single meaning decide on their own; otherwise the message is looked up among the ones
+    /// Play is known to send.
+    pub(crate) fn from_server_response(
+        status: u16,
+        retry_after: Option<&str>,
+        message: Option<&str>,
+    ) -> Error {
+        let kind = match status {
+            401 => ErrorKind::TokenExpired,
+            404 => ErrorKind::NotFound,
+            429 => ErrorKind::RateLimited(retry_after.and_then(|s| s.trim().parse().ok())),
+            451 => ErrorKind::GeoRestricted,
+            _ => match message.and_then(classify_server_message) {
+                Some(kind) => kind,
+                None if status == 403 => ErrorKind::Unauthorized,
+                None => ErrorKind::Server(status, message.map(String::from)),
+            },
+        };
+        Error::new(kind)
+    }
+
+    /// Turn the `Error=` field of an auth form reply into a typed error, keeping Google's
+    /// `Url` and `ErrorDetail` hints.
+    pub(crate) fn from_auth_reply(error: &str, url: Option<&str>, detail: Option<&str>) -> Error {
+        let hints = AuthHints {
+            url: url.map(String::from),
+            detail: detail.map(String::from),
+        };
+        let kind = match error {
+            "BadAuthentication" => ErrorKind::BadAuthentication(hints),
+            "NeedsBrowser" => ErrorKind::NeedsBrowser(hints),
+            "DeviceManagementRequiredOrSyncDisabled" => ErrorKind::DeviceManagementRequired(hints),
+            other => ErrorKind::AuthFailed(other.to_string(), hints),
+        };
+        Error::new(kind)
+    }
+}
+
+/// Match a `displayErrorMessage` against the messages Play is known to send. Only whole messages
+/// are compared, ignoring case, apostrophe style and the final period, so app names or other
+/// text that merely mention a country or a purchase are not misread.
+fn classify_server_message(message: &str) -> Option<ErrorKind> {
+    let message = message
+        .trim()
+        .trim_end_matches('.')
+        .replace('\u{2019}', "'")
+        .to_lowercase();
+    match message.as_str() {
+        "this item is not available in your country"
+        | "this item isn't available in your country" => Some(ErrorKind::GeoRestricted),
+        "your device is not compatible with this version"
+        | "your device isn't compatible with this version"
+        | "this app is not compatible with your device"
+        | "this app isn't compatible with your device" => Some(ErrorKind::IncompatibleDevice),
+        "item not found" | "the item you were attempting to purchase could not be found" => {
+            Some(ErrorKind::NotFound)
+        }
+        _ => None,
+    }
 }
 
 impl From<IOError> for Error {
@@ -43,8 +153,12 @@ impl From<IOError> for Error {
 
 impl From<Box<dyn StdError + Send + Sync>> for Error {
     fn from(err: Box<dyn StdError + Send + Sync>) -> Error {
-        Error {
-            kind: ErrorKind::Other(err),
+        // Most methods box their `Error`s, unwrap those so the kind stays reachable
+        match err.downcast::<Error>() {
+            Ok(err) => *err,
+            Err(err) => Error {
+                kind: ErrorKind::Other(err),
+            },
         }
     }
 }
@@ -87,9 +201,166 @@ impl fmt::Display for Error {
             ErrorKind::PermissionDenied => write!(f, "Cannot create file: permission denied"),
             ErrorKind::InvalidResponse => write!(f, "Invalid response from the remote host"),
             ErrorKind::LoginRequired => write!(f, "Logging in is required for this action"),
+            ErrorKind::InvalidSession => {
+                write!(
+                    f,
+                    "Stored session does not match this client or library version"
+                )
+            }
+            ErrorKind::NotFound => write!(f, "Not found"),
+            ErrorKind::Unauthorized => write!(f, "Not authorized to perform this request"),
+            ErrorKind::TokenExpired => write!(f, "Auth token expired or was rejected"),
+            ErrorKind::RateLimited(Some(secs)) => {
+                write!(f, "Rate limited, retry after {} seconds", secs)
+            }
+            ErrorKind::RateLimited(None) => write!(f, "Rate limited"),
+            ErrorKind::IncompatibleDevice => {
+                write!(f, "App is not compatible with the configured device")
+            }
+            ErrorKind::PaidApp => write!(f, "App has to be purchased first"),
+            ErrorKind::GeoRestricted => write!(f, "App is not available in this country"),
+            ErrorKind::BadAuthentication(hints) => {
+                write!(f, "Google rejected the credentials{}", hints)
+            }
+            ErrorKind::NeedsBrowser(hints) => {
+                write!(f, "Account has to sign in through a browser first{}", hints)
+            }
+            ErrorKind::DeviceManagementRequired(hints) => write!(
+                f,
+                "Account requires device management or has sync disabled{}",
+                hints
+            ),
+            ErrorKind::AuthFailed(error, hints) => {
+                write!(f, "Authentication failed: {}{}", error, hints)
+            }
+            ErrorKind::Server(status, Some(message)) => {
+                write!(f, "Server error (HTTP {}): {}", status, message)
+            }
+            ErrorKind::Server(status, None) => write!(f, "Server error (HTTP {})", status),
             ErrorKind::IO(err) => err.fmt(f),
             ErrorKind::Str(err) => err.fmt(f),
             ErrorKind::Other(err) => err.fmt(f),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_status_codes() {
+        assert!(matches!(
+            Error::from_server_response(404, None, None).kind(),
+            ErrorKind::NotFound
+        ));
+        assert!(matches!(
+            Error::from_server_response(401, None, None).kind(),
+            ErrorKind::TokenExpired
+        ));
+        assert!(matches!(
+            Error::from_server_response(429, Some("30"), None).kind(),
+            ErrorKind::RateLimited(Some(30))
+        ));
+        assert!(matches!(
+            Error::from_server_response(429, Some("Wed, 21 Oct 2015 07:28:00 GMT"), None).kind(),
+            ErrorKind::RateLimited(None)
+        ));
+        assert!(matches!(
+            Error::from_server_response(500, None, None).kind(),
+            ErrorKind::Server(500, None)
+        ));
+    }
+
+    #[test]
+    fn classifies_server_messages() {
+        let kind = |message| Error::from_server_response(200, None, Some(message)).kind;
+        assert!(matches!(
+            kind("Your device is not compatible with this version."),
+            ErrorKind::IncompatibleDevice
+        ));
+        assert!(matches!(
+            kind("This item is not available in your country."),
+            ErrorKind::GeoRestricted
+        ));
+        assert!(matches!(
+            kind("This item isn\u{2019}t available in your country"),
+            ErrorKind::GeoRestricted
+        ));
+        assert!(matches!(kind("Item not found."), ErrorKind::NotFound));
+        assert!(matches!(
+            kind("Something went wrong"),
+            ErrorKind::Server(200, Some(_))
+        ));
+
+        // Messages merely mentioning a keyword are left alone
+        assert!(matches!(
+            kind("Buy more storage to continue"),
+            ErrorKind::Server(200, Some(_))
+        ));
+        assert!(matches!(
+            kind("Country Radio is temporarily unavailable"),
+            ErrorKind::Server(200, Some(_))
+        ));
+        assert!(matches!(
+            Error::from_server_response(403, None, Some("Purchase history unavailable")).kind(),
+            ErrorKind::Unauthorized
+        ));
+    }
+
+    #[test]
+    fn status_outranks_message() {
+        let message = Some("Your device is not compatible with this version.");
+        assert!(matches!(
+            Error::from_server_response(404, None, message).kind(),
+            ErrorKind::NotFound
+        ));
+        assert!(matches!(
+            Error::from_server_response(403, None, message).kind(),
+            ErrorKind::IncompatibleDevice
+        ));
+    }
+
+    #[test]
+    fn classifies_auth_replies() {
+        let err = Error::from_auth_reply(
+            "NeedsBrowser",
+            Some("https://accounts.google.com/signin/continue"),
+            Some("Sign in required"),
+        );
+        match err.kind() {
+            ErrorKind::NeedsBrowser(hints) => {
+                assert_eq!(
+                    hints.url.as_deref(),
+                    Some("https://accounts.google.com/signin/continue")
+                );
+                assert_eq!(hints.detail.as_deref(), Some("Sign in required"));
+            }
+            kind => panic!("unexpected kind {:?}", kind),
+        }
+        assert_eq!(
+            err.to_string(),
+            "Account has to sign in through a browser first (Sign in required), \
+             see https://accounts.google.com/signin/continue"
+        );
+
+        assert!(matches!(
+            Error::from_auth_reply("BadAuthentication", None, None).kind(),
+            ErrorKind::BadAuthentication(_)
+        ));
+        assert!(matches!(
+            Error::from_auth_reply("DeviceManagementRequiredOrSyncDisabled", None, None).kind(),
+            ErrorKind::DeviceManagementRequired(_)
+        ));
+        assert!(matches!(
+            Error::from_auth_reply("ServiceDisabled", None, None).kind(),
+            ErrorKind::AuthFailed(error, _) if error == "ServiceDisabled"
+        ));
+    }
+
+    #[test]
+    fn unwraps_boxed_errors() {
+        let boxed: Box<dyn StdError + Send + Sync> = Box::new(Error::new(ErrorKind::PaidApp));
+        assert!(matches!(Error::from(boxed).kind(), ErrorKind::PaidApp));
+    }
+}
@@ -35,6 +35,13 @@
 //! }
 //! ```
 //!
+//! Logging in takes several round trips. The resulting session can be exported with `session`,
+//! stored (it is serde-serializable), and handed to `restore_session` on a fresh `Gpapi` instead
+//! of calling `login` again.
+//!
+//! While the `aas_token` is set, the auth token is renewed shortly before it expires, and a
+//! request rejected with a 401 is retried once with a renewed token.
+//!
 //! From here, you can get package details, get the info to download a package, or use the library to download it.
 //!
 //! ```rust
@@ -56,22 +63,34 @@
 //! ```
 
 mod consts;
+mod delivery;
 pub mod error;
+mod listing;
+mod reviews;
+mod testing_program;
+pub mod transport;
 
-use bytes::Bytes;
+use percent_encoding::percent_decode_str;
 use prost::Message;
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use reqwest::Url;
 use std::collections::HashMap;
 use std::error::Error;
 use std::io::Cursor;
+use std::sync::{Arc, RwLock};
 
+pub use crate::delivery::{
+    AdditionalFile, AdditionalFileType, ApkFile, DownloadAuthCookie, DownloadInfo, SplitApk,
+};
 use crate::error::{Error as GpapiError, ErrorKind as GpapiErrorKind};
+pub use crate::listing::{AppSummary, Category, Chart, ChartOptions, Page, SearchOptions};
+pub use crate::reviews::{DeveloperReply, Review, ReviewPage, ReviewSort};
+pub use crate::testing_program::TestingProgramStatus;
+use crate::transport::{HttpRequest, HttpResponse, Method, ReqwestTransport, Transport};
 
 use googleplay_protobuf::{
     AcceptTosResponse, AndroidCheckinProto, AndroidCheckinRequest, AndroidCheckinResponse,
     BulkDetailsRequest, BulkDetailsResponse, DetailsResponse, DeviceConfigurationProto,
-    ResponseWrapper, UploadDeviceConfigRequest, UploadDeviceConfigResponse,
+    ResponseWrapper, TestingProgramRequest, UploadDeviceConfigRequest, UploadDeviceConfigResponse,
 };
 
 use serde::{Deserialize, Serialize};
@@ -79,14 +98,50 @@ include!("device_properties.rs");
 
 static DEVICES_ENCODED: &[u8] = include_bytes!("device_properties.bin");
 
-pub type MainAPKDownloadURL = Option<String>;
-pub type SplitsDownloadInfo = Vec<(Option<String>, Option<String>)>;
-pub type AdditionalFilesDownloadInfo = Vec<(Option<String>, Option<String>)>;
-pub type DownloadInfo = (
-    MainAPKDownloadURL,
-    SplitsDownloadInfo,
-    AdditionalFilesDownloadInfo,
-);
+/// Codenames of the devices `Gpapi::new` accepts, e.g. `px_7a`, sorted.
+pub fn device_codenames() -> Vec<String> {
+    let mut codenames: Vec<String> =
+        bincode::deserialize::<HashMap<String, EncodedDeviceProperties>>(DEVICES_ENCODED)
+            .unwrap()
+            .into_keys()
+            .collect();
+    codenames.sort();
+    codenames
+}
+
+/// The state `login` establishes with Google, so a logged-in session can be stored and restored
+/// later without repeating checkin, `uploadDeviceConfig`, `auth` and `toc`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    /// Format version of the snapshot, see `Session::VERSION`.
+    pub version: u32,
+    pub email: String,
+    pub auth_token: String,
+    /// When `auth_token` expires, as seconds since the Unix epoch, if the server said so.
+    #[serde(default)]
+    pub auth_token_expiry: Option<u64>,
+    pub gsf_id: i64,
+    pub device_config_token: Option<String>,
+    pub device_checkin_consistency_token: Option<String>,
+    pub dfe_cookie: Option<String>,
+}
+
+impl Session {
+    /// The snapshot format produced by this version of the library. Snapshots with a different
+    /// version are rejected by `Gpapi::restore_session`.
+    pub const VERSION: u32 = 1;
+}
+
+/// Renew the auth token this many seconds before it expires, so a request never races the
+/// expiry.
+const AUTH_TOKEN_REFRESH_MARGIN_SECS: u64 = 5 * 60;
+
+/// An auth token and when it expires, as seconds since the Unix epoch.
+#[derive(Debug, Clone)]
+struct AuthToken {
+    token: String,
+    expiry: Option<u64>,
+}
 
 #[derive(Debug)]
 pub struct Gpapi {
@@ -95,18 +150,23 @@ pub struct Gpapi {
     device_properties: DeviceProperties,
     email: String,
     aas_token: Option<String>,
-    auth_token: Option<String>,
+    // Behind a lock so requests taking `&self` can renew it
+    auth_token: RwLock<Option<AuthToken>>,
     device_config_token: Option<String>,
     device_checkin_consistency_token: Option<String>,
     tos_token: Option<String>,
     dfe_cookie: Option<String>,
     gsf_id: Option<i64>,
-    client: Box<reqwest::Client>,
+    base_url: String,
+    transport: Arc<dyn Transport>,
 }
 
 impl Gpapi {
     /// Returns a Gpapi struct.
     ///
+    /// # Panics
+    ///
+    /// If `device_codename` is not one of `device_codenames()`.
     pub fn new<S: Into<String>>(device_codename: S, email: S) -> Self {
         Gpapi {
             locale: String::from("en_US"),
@@ -120,13 +180,14 @@ impl Gpapi {
             .to_decoded(),
             email: email.into(),
             aas_token: None,
-            auth_token: None,
+            auth_token: RwLock::new(None),
             device_config_token: None,
             device_checkin_consistency_token: None,
             tos_token: None,
             dfe_cookie: None,
             gsf_id: None,
-            client: Box::new(reqwest::Client::new()),
+            base_url: String::from(consts::defaults::DEFAULT_BASE_URL),
+            transport: Arc::new(ReqwestTransport::default()),
         }
     }
 
@@ -140,6 +201,17 @@ impl Gpapi {
         self.timezone = timezone.into();
     }
 
+    /// Set the base URL of the Play backend, used for the `/fdfe/*`, `/auth` and `/checkin`
+    /// endpoints. Defaults to `https://android.clients.google.com`.
+    pub fn set_base_url<S: Into<String>>(&mut self, base_url: S) {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+    }
+
+    /// Set the transport every request is sent through. Defaults to `ReqwestTransport`.
+    pub fn set_transport<T: Transport + 'static>(&mut self, transport: T) {
+        self.transport = Arc::new(transport);
+    }
+
     /// Set the aas token. This can be requested via `request_aas_token`, and is required for most
     /// other actions.
     pub fn set_aas_token<S: Into<String>>(&mut self, aas_token: S) {
@@ -182,12 +254,11 @@ impl Gpapi {
         );
         headers.insert("app", String::from("com.google.android.gms"));
 
-        let body_bytes = self
+        let res = self
             .execute_request_helper("auth", None, Some(&form_body.into_bytes()), headers, false)
             .await?;
 
-        let reply = parse_form_reply(&std::str::from_utf8(&body_bytes.to_vec()).unwrap());
-        Ok(reply)
+        Ok(parse_auth_reply(&res)?)
     }
 
     /// Get the aas token that has been previously set by either `request_aas_token` or
@@ -214,6 +285,37 @@ impl Gpapi {
         }
     }
 
+    /// Export the current session, or `None` if `login` has not completed yet.
+    pub fn session(&self) -> Option<Session> {
+        Some(Session {
+            version: Session::VERSION,
+            email: self.email.clone(),
+            auth_token: self.auth_token()?,
+            auth_token_expiry: self.auth_token.read().unwrap().as_ref()?.expiry,
+            gsf_id: self.gsf_id?,
+            device_config_token: self.device_config_token.clone(),
+            device_checkin_consistency_token: self.device_checkin_consistency_token.clone(),
+            dfe_cookie: self.dfe_cookie.clone(),
+        })
+    }
+
+    /// Restore a session previously exported with `session`, instead of calling `login`.
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - A snapshot taken from a `Gpapi` logged in with the same email
+    pub fn restore_session(&mut self, session: Session) -> Result<(), GpapiError> {
+        if session.version != Session::VERSION || session.email != self.email {
+            return Err(GpapiError::new(GpapiErrorKind::InvalidSession));
+        }
+        self.set_auth_token(session.auth_token, session.auth_token_expiry);
+        self.gsf_id = Some(session.gsf_id);
+        self.device_config_token = session.device_config_token;
+        self.device_checkin_consistency_token = session.device_checkin_consistency_token;
+        self.dfe_cookie = session.dfe_cookie;
+        Ok(())
+    }
+
     /// Retrieve the download URL(s) and names for a package, given a package ID and optional
     /// version code.
     ///
@@ -225,17 +327,15 @@ impl Gpapi {
     ///
     /// # Returns
     ///
-    /// * An Option<String> to the full APK download URL, followed by a Vec<(Option<String>,
-    /// Option<String>)> which corresponds to a list of download URLs and names for the split APK,
-    /// then followed by another Vec<(Option<String>, Option<String>)> which corresponds to the
-    /// download URLs and filenames for additional files.
+    /// * A `DownloadInfo` describing the main APK, the split APKs and any additional files, with
+    /// their sizes, hashes and gzipped alternatives, and the cookies required to download them.
     pub async fn get_download_info<S: Into<String>>(
         &self,
         pkg_name: S,
         mut version_code: Option<i32>,
     ) -> Result<DownloadInfo, Box<dyn Error + Send + Sync>> {
         let pkg_name = pkg_name.into();
-        if self.auth_token.is_none() {
+        if self.auth_token().is_none() {
             return Err(Box::new(GpapiError::new(GpapiErrorKind::LoginRequired)));
         }
         if version_code.is_none() {
@@ -261,6 +361,10 @@ impl Gpapi {
                         .delivery(&pkg_name, version_code.clone(), &delivery_token)
                         .await;
                 }
+                // Instead of a delivery token, paid apps get a checkout flow
+                if buy_response.checkoutinfo.is_some() {
+                    return Err(Box::new(GpapiError::new(GpapiErrorKind::PaidApp)));
+                }
             }
         }
         Err(Box::new(GpapiError::new(GpapiErrorKind::InvalidApp)))
@@ -274,7 +378,7 @@ impl Gpapi {
     ) -> Result<DownloadInfo, Box<dyn Error + Send + Sync>> {
         let pkg_name = pkg_name.into();
         let delivery_token = delivery_token.into();
-        if self.auth_token.is_none() {
+        if self.auth_token().is_none() {
             return Err(Box::new(GpapiError::new(GpapiErrorKind::LoginRequired)));
         }
         if version_code.is_none() {
@@ -292,30 +396,21 @@ impl Gpapi {
         };
         if let Some(payload) = resp.payload {
             if let Some(delivery_response) = payload.delivery_response {
-                if let Some(app_delivery_data) = delivery_response.app_delivery_data {
-                    let mut splits = Vec::new();
-                    for app_split_delivery_data in app_delivery_data.split_delivery_data {
-                        splits.push((
-                            app_split_delivery_data.name,
-                            app_split_delivery_data.download_url,
-                        ));
+                // Status 1 is success; these are the failures Play reports for a known app
+                match delivery_response.status {
+                    Some(2) => {
+                        return Err(Box::new(GpapiError::new(
+                            GpapiErrorKind::IncompatibleDevice,
+                        )))
                     }
-                    let mut additional_files: Vec<(Option<String>, Option<String>)> = Vec::new();
-                    for additional_file in app_delivery_data.additional_file {
-                        if let Some(file_type) = additional_file.file_type {
-                            if let Some(version_code) = additional_file.version_code {
-                                let main_patch = match file_type {
-                                    0 => "main",
-                                    _ => "patch",
-                                };
-                                let filename =
-                                    format!("{}.{}.{}.obb", main_patch, version_code, pkg_name);
-                                additional_files
-                                    .push((Some(filename), additional_file.download_url));
-                            }
-                        }
-                    }
-                    return Ok((app_delivery_data.download_url, splits, additional_files));
+                    Some(3) => return Err(Box::new(GpapiError::new(GpapiErrorKind::PaidApp))),
+                    _ => {}
+                }
+                if let Some(app_delivery_data) = delivery_response.app_delivery_data {
+                    return Ok(DownloadInfo::from_delivery_data(
+                        &pkg_name,
+                        app_delivery_data,
+                    ));
                 }
             }
         }
@@ -349,7 +444,7 @@ impl Gpapi {
         &self,
         pkg_name: S,
     ) -> Result<Option<DetailsResponse>, Box<dyn Error + Send + Sync>> {
-        if self.auth_token.is_none() {
+        if self.auth_token().is_none() {
             return Err(Box::new(GpapiError::new(GpapiErrorKind::LoginRequired)));
         }
         let mut form_params = HashMap::new();
@@ -377,7 +472,7 @@ impl Gpapi {
         &self,
         pkg_names: &[&str],
     ) -> Result<Option<BulkDetailsResponse>, Box<dyn Error + Send + Sync>> {
-        if self.auth_token.is_none() {
+        if self.auth_token().is_none() {
             return Err(Box::new(GpapiError::new(GpapiErrorKind::LoginRequired)));
         }
         let mut req = BulkDetailsRequest::default();
@@ -401,6 +496,233 @@ impl Gpapi {
         }
     }
 
+    /// Search the Play Store for apps.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The search terms, e.g. `instagram`
+    /// * `options` - The page to fetch and how many results to return; the first page by default
+    ///
+    /// # Returns
+    ///
+    /// * A `Page` of results, with a cursor for the next page if there is one.
+    pub async fn search<S: Into<String>>(
+        &self,
+        query: S,
+        options: SearchOptions,
+    ) -> Result<Page, Box<dyn Error + Send + Sync>> {
+        if self.auth_token().is_none() {
+            return Err(Box::new(GpapiError::new(GpapiErrorKind::LoginRequired)));
+        }
+        let headers = self.get_default_headers()?;
+        let resp = match &options.page {
+            Some(page) => {
+                self.execute_request(&page_endpoint(page)?, None, None, headers)
+                    .await?
+            }
+            None => {
+                let mut params = HashMap::new();
+                // Corpus 3 is apps
+                params.insert("c", String::from("3"));
+                params.insert("q", query.into());
+                self.execute_request("search", Some(params), None, headers)
+                    .await?
+            }
+        };
+        Ok(resp
+            .payload
+            .map(|payload| Page::from_payload(payload, options.limit))
+            .unwrap_or_default())
+    }
+
+    /// List the app categories of the Play Store.
+    pub async fn categories(&self) -> Result<Vec<Category>, Box<dyn Error + Send + Sync>> {
+        if self.auth_token().is_none() {
+            return Err(Box::new(GpapiError::new(GpapiErrorKind::LoginRequired)));
+        }
+        let mut params = HashMap::new();
+        params.insert("c", String::from("3"));
+        let resp = self
+            .execute_request("browse", Some(params), None, self.get_default_headers()?)
+            .await?;
+        Ok(resp
+            .payload
+            .and_then(|payload| payload.browse_response)
+            .map(|browse_response| {
+                browse_response
+                    .category
+                    .into_iter()
+                    .filter_map(Category::from_link)
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Fetch a top chart, for all apps or a single category.
+    ///
+    /// # Arguments
+    ///
+    /// * `chart` - Which chart to fetch, e.g. `Chart::TopFree`
+    /// * `options` - The category, the page to fetch and how many results to return
+    ///
+    /// # Returns
+    ///
+    /// * A `Page` of results in chart order, with a cursor for the next page if there is one.
+    pub async fn top_chart(
+        &self,
+        chart: Chart,
+        options: ChartOptions,
+    ) -> Result<Page, Box<dyn Error + Send + Sync>> {
+        if self.auth_token().is_none() {
+            return Err(Box::new(GpapiError::new(GpapiErrorKind::LoginRequired)));
+        }
+        let headers = self.get_default_headers()?;
+        let resp = match &options.page {
+            Some(page) => {
+                self.execute_request(&page_endpoint(page)?, None, None, headers)
+                    .await?
+            }
+            None => {
+                let mut params = HashMap::new();
+                params.insert("c", String::from("3"));
+                params.insert(
+                    "cat",
+                    options
+                        .category
+                        .clone()
+                        .unwrap_or_else(|| String::from("APPLICATION")),
+                );
+                params.insert("ctr", String::from(chart.list_id()));
+                self.execute_request("list", Some(params), None, headers)
+                    .await?
+            }
+        };
+        Ok(resp
+            .payload
+            .map(|payload| Page::from_payload(payload, options.limit))
+            .unwrap_or_default())
+    }
+
+    /// Fetch a page of user reviews for a package.
+    ///
+    /// # Arguments
+    ///
+    /// * `pkg_name` - A string type specifying the package's app ID, e.g. `com.instagram.android`
+    /// * `sort` - The order to return reviews in
+    /// * `filter_by_device` - Only return reviews written on devices like the configured one
+    /// * `rating` - Only return reviews with this many stars, from 1 to 5
+    /// * `offset` - Number of reviews to skip, e.g. the `next_offset` of the previous page
+    pub async fn reviews<S: Into<String>>(
+        &self,
+        pkg_name: S,
+        sort: ReviewSort,
+        filter_by_device: bool,
+        rating: Option<u8>,
+        offset: usize,
+    ) -> Result<ReviewPage, Box<dyn Error + Send + Sync>> {
+        if self.auth_token().is_none() {
+            return Err(Box::new(GpapiError::new(GpapiErrorKind::LoginRequired)));
+        }
+        let mut params = HashMap::new();
+        params.insert("doc", pkg_name.into());
+        params.insert("sort", String::from(sort.param()));
+        params.insert("o", offset.to_string());
+        params.insert("n", reviews::REVIEWS_PAGE_SIZE.to_string());
+        if filter_by_device {
+            params.insert("dfil", String::from("1"));
+        }
+        if let Some(rating) = rating {
+            params.insert("rating", rating.to_string());
+        }
+        let resp = self
+            .execute_request("rev", Some(params), None, self.get_default_headers()?)
+            .await?;
+        Ok(resp
+            .payload
+            .and_then(|payload| payload.review_response)
+            .map(|review_response| ReviewPage::from_response(review_response, offset))
+            .unwrap_or_default())
+    }
+
+    /// Whether a package runs a testing program, and whether this account is enrolled in it.
+    ///
+    /// # Arguments
+    ///
+    /// * `pkg_name` - A string type specifying the package's app ID, e.g. `com.instagram.android`
+    pub async fn testing_program_status<S: Into<String>>(
+        &self,
+        pkg_name: S,
+    ) -> Result<TestingProgramStatus, Box<dyn Error + Send + Sync>> {
+        let pkg_name = pkg_name.into();
+        match self.details(pkg_name.as_str()).await? {
+            Some(details) => Ok(TestingProgramStatus::from_details(&details)),
+            None => Err(Box::new(GpapiError::new(GpapiErrorKind::NotFound))),
+        }
+    }
+
+    /// Enroll this account in a package's testing program, so it is served the app's test
+    /// builds. Enrollment can take a few minutes to apply to `details` and downloads.
+    ///
+    /// # Arguments
+    ///
+    /// * `pkg_name` - A string type specifying the package's app ID, e.g. `com.instagram.android`
+    ///
+    /// # Returns
+    ///
+    /// * Whether the server confirmed the enrollment.
+    pub async fn join_testing_program<S: Into<String>>(
+        &self,
+        pkg_name: S,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        self.testing_program_request(pkg_name.into(), true).await
+    }
+
+    /// Leave a package's testing program, going back to the app's production builds.
+    ///
+    /// # Arguments
+    ///
+    /// * `pkg_name` - A string type specifying the package's app ID, e.g. `com.instagram.android`
+    ///
+    /// # Returns
+    ///
+    /// * Whether the server confirmed leaving the program.
+    pub async fn leave_testing_program<S: Into<String>>(
+        &self,
+        pkg_name: S,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        self.testing_program_request(pkg_name.into(), false).await
+    }
+
+    async fn testing_program_request(
+        &self,
+        pkg_name: String,
+        subscribe: bool,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        if self.auth_token().is_none() {
+            return Err(Box::new(GpapiError::new(GpapiErrorKind::LoginRequired)));
+        }
+        let req = TestingProgramRequest {
+            package_name: Some(pkg_name),
+            subscribe: Some(subscribe),
+        };
+        let mut headers = self.get_default_headers()?;
+        headers.insert("content-type", String::from("application/x-protobuf"));
+
+        let resp = self
+            .execute_request(
+                "apps/testingProgram",
+                None,
+                Some(&req.encode_to_vec()),
+                headers,
+            )
+            .await?;
+        Ok(testing_program::confirmed(
+            resp.payload
+                .and_then(|payload| payload.testing_program_response),
+            subscribe,
+        ))
+    }
+
     async fn checkin(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
         let checkin = self.device_properties.android_checkin.clone();
 
@@ -447,10 +769,17 @@ impl Gpapi {
     ) -> Result<AndroidCheckinResponse, Box<dyn Error + Send + Sync>> {
         auth_headers.insert("content-type", String::from("application/x-protobuf"));
         auth_headers.insert("host", String::from("android.clients.google.com"));
-        let bytes = self
+        let res = self
             .execute_request_helper("checkin", None, Some(msg), auth_headers, false)
             .await?;
-        let resp = AndroidCheckinResponse::decode(&mut Cursor::new(bytes))?;
+        if !is_success(res.status) {
+            return Err(Box::new(GpapiError::from_server_response(
+                res.status,
+                res.header("retry-after"),
+                None,
+            )));
+        }
+        let resp = AndroidCheckinResponse::decode(&mut Cursor::new(res.body))?;
         Ok(resp)
     }
 
@@ -464,8 +793,8 @@ impl Gpapi {
         &self,
         headers: &mut HashMap<&str, String>,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        if let Some(auth_token) = &self.auth_token {
-            headers.insert("Authorization", format!("Bearer {}", auth_token.clone()));
+        if let Some(auth_token) = self.auth_token() {
+            headers.insert("Authorization", format!("Bearer {}", auth_token));
         }
 
         let build = self
@@ -633,7 +962,7 @@ impl Gpapi {
         }
     }
 
-    async fn request_auth_token(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+    async fn request_auth_token(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
         let form_params = {
             let mut params = HashMap::new();
             self.append_default_auth_params(&mut params);
@@ -669,12 +998,51 @@ impl Gpapi {
             headers
         };
 
-        let bytes = self
+        let res = self
             .execute_request_helper("auth", Some(form_params), Some(&[]), headers, false)
             .await?;
 
-        let reply = parse_form_reply(&std::str::from_utf8(&bytes.to_vec()).unwrap());
-        self.auth_token = reply.get("auth").map(|a| a.clone());
+        let mut reply = parse_auth_reply(&res)?;
+        let auth_token = reply
+            .remove("auth")
+            .ok_or(Box::new(GpapiError::new(GpapiErrorKind::Authentication)))?;
+        let expiry = reply.get("expiry").and_then(|expiry| expiry.parse().ok());
+        self.set_auth_token(auth_token, expiry);
+        Ok(())
+    }
+
+    fn auth_token(&self) -> Option<String> {
+        self.auth_token
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|auth_token| auth_token.token.clone())
+    }
+
+    fn set_auth_token(&self, token: String, expiry: Option<u64>) {
+        *self.auth_token.write().unwrap() = Some(AuthToken { token, expiry });
+    }
+
+    /// Whether the auth token expires within `AUTH_TOKEN_REFRESH_MARGIN_SECS`.
+    fn auth_token_expiring(&self) -> bool {
+        match self.auth_token.read().unwrap().as_ref() {
+            Some(AuthToken {
+                expiry: Some(expiry),
+                ..
+            }) => now_secs() + AUTH_TOKEN_REFRESH_MARGIN_SECS >= *expiry,
+            _ => false,
+        }
+    }
+
+    /// Renew the auth token with the aas token and put the new one into `headers`.
+    async fn refresh_auth_token(
+        &self,
+        headers: &mut HashMap<&str, String>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.request_auth_token().await?;
+        if let Some(auth_token) = self.auth_token() {
+            headers.insert("Authorization", format!("Bearer {}", auth_token));
+        }
         Ok(())
     }
 
@@ -732,17 +1100,54 @@ impl Gpapi {
     /// Lower level Play Store request, used by APIs but exposed for specialized
     /// requests. Returns a `ResponseWrapper` which depending on the request
     /// populates different fields/values.
+    ///
+    /// Failures are reported as the matching `ErrorKind`, based on the HTTP status and the
+    /// error message the server sends in its `ServerCommands`.
     async fn execute_request(
         &self,
         endpoint: &str,
         query: Option<HashMap<&str, String>>,
         msg: Option<&[u8]>,
-        headers: HashMap<&str, String>,
+        mut headers: HashMap<&str, String>,
     ) -> Result<ResponseWrapper, Box<dyn Error + Send + Sync>> {
-        let bytes = self
-            .execute_request_helper(endpoint, query, msg, headers, true)
+        // Only authorized requests can be retried with a renewed token, and renewing needs the
+        // aas token
+        let can_refresh = self.aas_token.is_some() && headers.contains_key("Authorization");
+        if can_refresh && self.auth_token_expiring() {
+            self.refresh_auth_token(&mut headers).await?;
+        }
+        let mut res = self
+            .execute_request_helper(endpoint, query.clone(), msg, headers.clone(), true)
             .await?;
-        let resp = ResponseWrapper::decode(&mut Cursor::new(bytes))?;
+        if can_refresh && res.status == 401 {
+            self.refresh_auth_token(&mut headers).await?;
+            res = self
+                .execute_request_helper(endpoint, query, msg, headers, true)
+                .await?;
+        }
+        // Error replies usually still carry a `ResponseWrapper`, so decode before checking
+        let resp = ResponseWrapper::decode(&mut Cursor::new(&res.body));
+        let message = resp
+            .as_ref()
+            .ok()
+            .and_then(|resp| resp.commands.as_ref())
+            .and_then(|commands| commands.display_error_message.clone());
+
+        if !is_success(res.status) {
+            return Err(Box::new(GpapiError::from_server_response(
+                res.status,
+                res.header("retry-after"),
+                message.as_deref(),
+            )));
+        }
+        let resp = resp?;
+        if let (None, Some(message)) = (&resp.payload, &message) {
+            return Err(Box::new(GpapiError::from_server_response(
+                res.status,
+                None,
+                Some(message),
+            )));
+        }
         Ok(resp)
     }
 
@@ -803,19 +1208,11 @@ impl Gpapi {
         msg: Option<&[u8]>,
         headers: HashMap<&str, String>,
         fdfe: bool,
-    ) -> Result<Bytes, Box<dyn Error + Send + Sync>> {
+    ) -> Result<HttpResponse, Box<dyn Error + Send + Sync>> {
         let mut url = if fdfe {
-            Url::parse(&format!(
-                "{}/fdfe/{}",
-                consts::defaults::DEFAULT_BASE_URL,
-                endpoint
-            ))?
+            Url::parse(&format!("{}/fdfe/{}", self.base_url, endpoint))?
         } else {
-            Url::parse(&format!(
-                "{}/{}",
-                consts::defaults::DEFAULT_BASE_URL,
-                endpoint
-            ))?
+            Url::parse(&format!("{}/{}", self.base_url, endpoint))?
         };
 
         if let Some(query) = query {
@@ -825,48 +1222,101 @@ impl Gpapi {
             }
         }
 
-        let mut reqwest_headers = HeaderMap::new();
-        for (key, val) in headers {
-            reqwest_headers.insert(
-                HeaderName::from_bytes(key.as_bytes())?,
-                HeaderValue::from_str(&val)?,
-            );
-        }
-
-        let res = {
-            if let Some(msg) = msg {
-                (*self.client)
-                    .post(url)
-                    .headers(reqwest_headers)
-                    .body(msg.to_owned())
-                    .send()
-                    .await?
+        let req = HttpRequest {
+            method: if msg.is_some() {
+                Method::Post
             } else {
-                (*self.client)
-                    .get(url)
-                    .headers(reqwest_headers)
-                    .send()
-                    .await?
-            }
+                Method::Get
+            },
+            url: url.into(),
+            headers: headers
+                .into_iter()
+                .map(|(key, val)| (key.to_string(), val))
+                .collect(),
+            body: msg.map(|msg| msg.to_owned()),
         };
 
-        Ok(res.bytes().await?)
+        self.transport.send(req).await
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// `SystemTime::now` panics on wasm32-unknown-unknown
+#[cfg(target_arch = "wasm32")]
+fn now_secs() -> u64 {
+    (js_sys::Date::now() / 1000.0) as u64
+}
+
+/// Turn a next page URL handed out by the server back into an fdfe endpoint. Cursors come back
+/// from callers, so the cursor is resolved like a URL below `/fdfe/` (which also resolves dot
+/// segments, percent-encoded or not) and anything that ends up outside of it, or has a segment
+/// that decodes to a path separator or a dot segment, is rejected.
+fn page_endpoint(page: &str) -> Result<String, GpapiError> {
+    let invalid = || GpapiError::from("Invalid page cursor");
+    let relative = page.trim_start_matches('/');
+    let relative = relative.strip_prefix("fdfe/").unwrap_or(relative);
+    let base = Url::parse("https://fdfe.invalid/fdfe/").map_err(|_| invalid())?;
+    let url = base.join(relative).map_err(|_| invalid())?;
+    if url.origin() != base.origin() {
+        return Err(invalid());
+    }
+    let path = url.path().strip_prefix("/fdfe/").ok_or_else(invalid)?;
+    let valid_segment = |segment: &str| {
+        let decoded = percent_decode_str(segment).decode_utf8_lossy();
+        !matches!(&*decoded, "" | "." | "..") && !decoded.contains(['/', '\\'])
+    };
+    if !path.split('/').all(valid_segment) {
+        return Err(invalid());
+    }
+    Ok(match url.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_string(),
+    })
+}
+
+fn is_success(status: u16) -> bool {
+    (200..300).contains(&status)
+}
+
 fn parse_form_reply(data: &str) -> HashMap<String, String> {
     let mut form_resp = HashMap::new();
-    let lines: Vec<&str> = data.split_terminator('\n').collect();
-    for line in lines.iter() {
-        let kv: Vec<&str> = line.split_terminator('=').collect();
-        form_resp.insert(
-            String::from(kv[0]).to_lowercase(),
-            String::from(kv[1..].join("=")),
-        );
+    for line in data.lines().filter(|line| !line.is_empty()) {
+        let (key, value) = line.split_once('=').unwrap_or((line, ""));
+        form_resp.insert(key.to_lowercase(), String::from(value));
     }
     form_resp
 }
 
+/// Parse a reply from the auth endpoint. Google answers failures with an `Error=` field (and
+/// usually a 403), which is turned into a typed error carrying its `Url` and `ErrorDetail`.
+fn parse_auth_reply(res: &HttpResponse) -> Result<HashMap<String, String>, GpapiError> {
+    let body = std::str::from_utf8(&res.body)
+        .map_err(|_| GpapiError::new(GpapiErrorKind::InvalidResponse))?;
+    let reply = parse_form_reply(body);
+    if let Some(error) = reply.get("error") {
+        return Err(GpapiError::from_auth_reply(
+            error,
+            reply.get("url").map(String::as_str),
+            reply.get("errordetail").map(String::as_str),
+        ));
+    }
+    if !is_success(res.status) {
+        return Err(GpapiError::from_server_response(
+            res.status,
+            res.header("retry-after"),
+            None,
+        ));
+    }
+    Ok(reply)
+}
+
 #[derive(Debug, Clone)]
 struct AuthRequest {
     params: HashMap<String, String>,
@@ -999,6 +1449,13 @@ impl BuildConfiguration {
 mod tests {
     use super::*;
 
+    #[test]
+    fn lists_device_codenames() {
+        let codenames = device_codenames();
+        assert!(codenames.iter().any(|codename| codename == "px_7a"));
+        assert!(codenames.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
     #[test]
     fn parse_form() {
         let form_reply = "FOO=BAR\nbaz=qux";
@@ -1009,6 +1466,451 @@ mod tests {
         assert_eq!(expected_reply, parsed_form_reply);
     }
 
+    pub(crate) mod fake {
+        use super::*;
+        use crate::transport::{HttpRequest, HttpResponse};
+        use bytes::Bytes;
+        use std::fmt;
+        use std::sync::Mutex;
+
+        type Handler = Box<dyn Fn(&HttpRequest) -> HttpResponse + Send + Sync>;
+
+        /// A stand-in for the Play backend that answers every request through `handler` and
+        /// records what it was sent.
+        #[derive(Clone)]
+        pub struct FakeTransport {
+            handler: Arc<Handler>,
+            pub requests: Arc<Mutex<Vec<HttpRequest>>>,
+        }
+
+        impl FakeTransport {
+            pub fn new<F>(handler: F) -> Self
+            where
+                F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+            {
+                FakeTransport {
+                    handler: Arc::new(Box::new(handler)),
+                    requests: Arc::new(Mutex::new(Vec::new())),
+                }
+            }
+        }
+
+        impl fmt::Debug for FakeTransport {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.debug_struct("FakeTransport").finish()
+            }
+        }
+
+        #[async_trait::async_trait]
+        impl Transport for FakeTransport {
+            async fn send(
+                &self,
+                req: HttpRequest,
+            ) -> Result<HttpResponse, Box<dyn Error + Send + Sync>> {
+                let res = (self.handler)(&req);
+                self.requests.lock().unwrap().push(req);
+                Ok(res)
+            }
+        }
+
+        pub fn respond(status: u16, body: Vec<u8>) -> HttpResponse {
+            HttpResponse {
+                status,
+                headers: HashMap::new(),
+                body: Bytes::from(body),
+            }
+        }
+
+        pub fn with_header(mut res: HttpResponse, name: &str, value: &str) -> HttpResponse {
+            res.headers
+                .insert(name.to_ascii_lowercase(), value.to_string());
+            res
+        }
+
+        pub fn encode<M: Message>(msg: &M) -> Vec<u8> {
+            msg.encode_to_vec()
+        }
+    }
+
+    mod transport {
+        use super::fake::{encode, respond, FakeTransport};
+        use super::*;
+        use crate::transport::Method;
+        use googleplay_protobuf::{Payload, ResponseWrapper};
+
+        fn logged_in_api(transport: FakeTransport) -> Gpapi {
+            let mut api = Gpapi::new("px_7a", "user@example.com");
+            api.set_base_url("http://127.0.0.1:8080/");
+            api.set_transport(transport);
+            api.set_auth_token(String::from("token"), None);
+            api
+        }
+
+        #[tokio::test]
+        async fn fdfe_requests_use_base_url() {
+            let transport = FakeTransport::new(|_| {
+                let wrapper = ResponseWrapper {
+                    payload: Some(Payload {
+                        details_response: Some(DetailsResponse::default()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                };
+                respond(200, encode(&wrapper))
+            });
+            let api = logged_in_api(transport.clone());
+
+            let details = api.details("com.example").await.unwrap();
+            assert!(details.is_some());
+
+            let requests = transport.requests.lock().unwrap();
+            assert_eq!(requests.len(), 1);
+            assert_eq!(requests[0].method, Method::Get);
+            assert_eq!(
+                requests[0].url,
+                "http://127.0.0.1:8080/fdfe/details?doc=com.example"
+            );
+            assert!(requests[0]
+                .headers
+                .iter()
+                .any(|(k, v)| k == "Authorization" && v == "Bearer token"));
+        }
+
+        #[tokio::test]
+        async fn auth_requests_use_base_url() {
+            let transport = FakeTransport::new(|_| respond(200, b"Token=aas_et/abc\n".to_vec()));
+            let mut api = Gpapi::new("px_7a", "user@example.com");
+            api.set_base_url("http://127.0.0.1:8080");
+            api.set_transport(transport.clone());
+
+            api.request_aas_token("oauth2_4/xyz").await.unwrap();
+            assert_eq!(api.get_aas_token(), Some("aas_et/abc"));
+
+            let requests = transport.requests.lock().unwrap();
+            assert_eq!(requests[0].method, Method::Post);
+            assert_eq!(requests[0].url, "http://127.0.0.1:8080/auth");
+        }
+    }
+
+    mod errors {
+        use super::fake::{encode, respond, with_header, FakeTransport};
+        use super::*;
+        use googleplay_protobuf::{ResponseWrapper, ServerCommands};
+
+        async fn details_error(transport: FakeTransport) -> GpapiError {
+            let mut api = Gpapi::new("px_7a", "user@example.com");
+            api.set_transport(transport);
+            api.set_auth_token(String::from("token"), None);
+            GpapiError::from(api.details("com.example").await.unwrap_err())
+        }
+
+        fn server_message(message: &str) -> Vec<u8> {
+            encode(&ResponseWrapper {
+                commands: Some(ServerCommands {
+                    display_error_message: Some(String::from(message)),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+        }
+
+        #[tokio::test]
+        async fn maps_http_status() {
+            let err = details_error(FakeTransport::new(|_| respond(404, Vec::new()))).await;
+            assert!(matches!(err.kind(), GpapiErrorKind::NotFound));
+
+            let err = details_error(FakeTransport::new(|_| {
+                with_header(respond(429, Vec::new()), "Retry-After", "120")
+            }))
+            .await;
+            assert!(matches!(err.kind(), GpapiErrorKind::RateLimited(Some(120))));
+        }
+
+        #[tokio::test]
+        async fn maps_server_commands() {
+            let err = details_error(FakeTransport::new(|_| {
+                respond(
+                    200,
+                    server_message("This item is not available in your country."),
+                )
+            }))
+            .await;
+            assert!(matches!(err.kind(), GpapiErrorKind::GeoRestricted));
+
+            let err = details_error(FakeTransport::new(|_| {
+                respond(
+                    403,
+                    server_message("Your device is not compatible with this version."),
+                )
+            }))
+            .await;
+            assert!(matches!(err.kind(), GpapiErrorKind::IncompatibleDevice));
+        }
+
+        #[tokio::test]
+        async fn maps_auth_replies() {
+            let mut api = Gpapi::new("px_7a", "user@example.com");
+            api.set_transport(FakeTransport::new(|_| {
+                respond(
+                    403,
+                    b"Error=NeedsBrowser\nUrl=https://accounts.google.com/signin/continue?sarp=1\nErrorDetail=Sign in required\n".to_vec(),
+                )
+            }));
+            let err = GpapiError::from(api.request_aas_token("oauth2_4/xyz").await.unwrap_err());
+            match err.kind() {
+                GpapiErrorKind::NeedsBrowser(hints) => assert_eq!(
+                    hints.url.as_deref(),
+                    Some("https://accounts.google.com/signin/continue?sarp=1")
+                ),
+                kind => panic!("unexpected kind {:?}", kind),
+            }
+            assert!(api.get_aas_token().is_none());
+
+            // Replies that aren't text are rejected instead of panicking
+            api.set_transport(FakeTransport::new(|_| respond(200, vec![0xff, 0xfe])));
+            let err = GpapiError::from(api.request_aas_token("oauth2_4/xyz").await.unwrap_err());
+            assert!(matches!(err.kind(), GpapiErrorKind::InvalidResponse));
+        }
+    }
+
+    mod session {
+        use super::*;
+
+        fn logged_in_api() -> Gpapi {
+            let mut api = Gpapi::new("px_7a", "user@example.com");
+            api.set_auth_token(String::from("auth"), None);
+            api.gsf_id = Some(0x1234);
+            api.device_config_token = Some(String::from("config"));
+            api.device_checkin_consistency_token = Some(String::from("consistency"));
+            api.dfe_cookie = Some(String::from("cookie"));
+            api
+        }
+
+        #[test]
+        fn no_session_before_login() {
+            let api = Gpapi::new("px_7a", "user@example.com");
+            assert!(api.session().is_none());
+        }
+
+        #[test]
+        fn session_round_trip() {
+            let session = logged_in_api().session().unwrap();
+            let encoded = bincode::serialize(&session).unwrap();
+            let decoded: Session = bincode::deserialize(&encoded).unwrap();
+            assert_eq!(session, decoded);
+
+            let mut api = Gpapi::new("px_7a", "user@example.com");
+            api.restore_session(decoded).unwrap();
+            assert_eq!(api.session(), Some(session));
+            let headers = api.get_default_headers().unwrap();
+            assert_eq!(headers.get("Authorization").unwrap(), "Bearer auth");
+            assert_eq!(headers.get("X-DFE-Device-Id").unwrap(), "1234");
+            assert_eq!(headers.get("X-DFE-Cookie").unwrap(), "cookie");
+        }
+
+        #[test]
+        fn restore_rejects_mismatched_session() {
+            let session = logged_in_api().session().unwrap();
+
+            let mut other_account = Gpapi::new("px_7a", "other@example.com");
+            assert!(other_account.restore_session(session.clone()).is_err());
+
+            let mut api = Gpapi::new("px_7a", "user@example.com");
+            let outdated = Session {
+                version: Session::VERSION + 1,
+                ..session
+            };
+            assert!(api.restore_session(outdated).is_err());
+            assert!(api.session().is_none());
+        }
+    }
+
+    mod search {
+        use super::fake::{encode, respond, FakeTransport};
+        use super::*;
+        use googleplay_protobuf::{Item, Payload, SearchResponse};
+
+        fn search_api(transport: FakeTransport) -> Gpapi {
+            let mut api = Gpapi::new("px_7a", "user@example.com");
+            api.set_transport(transport);
+            api.set_auth_token(String::from("token"), None);
+            api
+        }
+
+        #[tokio::test]
+        async fn follows_page_cursors() {
+            let transport = FakeTransport::new(|_| {
+                let wrapper = ResponseWrapper {
+                    payload: Some(Payload {
+                        search_response: Some(SearchResponse {
+                            item: vec![Item {
+                                id: Some(String::from("com.example")),
+                                r#type: Some(1),
+                                ..Default::default()
+                            }],
+                            next_page_url: Some(String::from("search?c=3&q=example&o=1")),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                };
+                respond(200, encode(&wrapper))
+            });
+            let api = search_api(transport.clone());
+
+            let page = api
+                .search("example app", SearchOptions::default())
+                .await
+                .unwrap();
+            assert_eq!(page.results[0].package_name, "com.example");
+            let next_page = page.next_page.unwrap();
+
+            let options = SearchOptions {
+                page: Some(next_page),
+                ..Default::default()
+            };
+            api.search("ignored", options).await.unwrap();
+
+            let requests = transport.requests.lock().unwrap();
+            let url = Url::parse(&requests[0].url).unwrap();
+            assert_eq!(url.path(), "/fdfe/search");
+            let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+            assert_eq!(query.get("q").map(String::as_str), Some("example app"));
+            assert_eq!(query.get("c").map(String::as_str), Some("3"));
+            assert!(requests[1].url.ends_with("/fdfe/search?c=3&q=example&o=1"));
+        }
+
+        #[tokio::test]
+        async fn lists_top_charts() {
+            let transport =
+                FakeTransport::new(|_| respond(200, encode(&ResponseWrapper::default())));
+            let api = search_api(transport.clone());
+
+            let options = ChartOptions {
+                category: Some(String::from("GAME_PUZZLE")),
+                ..Default::default()
+            };
+            let page = api.top_chart(Chart::TopPaid, options).await.unwrap();
+            assert!(page.results.is_empty());
+
+            let requests = transport.requests.lock().unwrap();
+            let url = Url::parse(&requests[0].url).unwrap();
+            assert_eq!(url.path(), "/fdfe/list");
+            let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+            assert_eq!(query.get("cat").map(String::as_str), Some("GAME_PUZZLE"));
+            assert_eq!(
+                query.get("ctr").map(String::as_str),
+                Some("apps_topselling_paid")
+            );
+        }
+
+        #[tokio::test]
+        async fn rejects_foreign_cursors() {
+            let api = search_api(FakeTransport::new(|_| respond(200, Vec::new())));
+            let pages = [
+                "https://example.com/",
+                "../checkin",
+                "%2e%2e/checkin",
+                "search/%2E%2E/.%2e/checkin",
+                "..%2Fcheckin",
+                "search%5C..%5Ccheckin",
+                "search//details",
+                "",
+            ];
+            for page in pages {
+                let options = SearchOptions {
+                    page: Some(String::from(page)),
+                    ..Default::default()
+                };
+                assert!(api.search("example", options).await.is_err(), "{}", page);
+            }
+        }
+    }
+
+    mod token_refresh {
+        use super::fake::{encode, respond, FakeTransport};
+        use super::*;
+        use googleplay_protobuf::{Payload, ResponseWrapper};
+
+        /// Answers `/auth` with a fresh token and only accepts that token on `/fdfe/details`.
+        fn fake_backend(expiry: u64) -> FakeTransport {
+            FakeTransport::new(move |req| {
+                if req.url.contains("/auth?") {
+                    return respond(200, format!("Auth=fresh\nExpiry={}\n", expiry).into_bytes());
+                }
+                let authorized = req
+                    .headers
+                    .iter()
+                    .any(|(k, v)| k == "Authorization" && v == "Bearer fresh");
+                if !authorized {
+                    return respond(401, Vec::new());
+                }
+                let wrapper = ResponseWrapper {
+                    payload: Some(Payload {
+                        details_response: Some(DetailsResponse::default()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                };
+                respond(200, encode(&wrapper))
+            })
+        }
+
+        fn urls(transport: &FakeTransport) -> Vec<String> {
+            let requests = transport.requests.lock().unwrap();
+            requests.iter().map(|req| req.url.clone()).collect()
+        }
+
+        #[tokio::test]
+        async fn renews_token_and_retries_after_401() {
+            let expiry = now_secs() + 3600;
+            let transport = fake_backend(expiry);
+            let mut api = Gpapi::new("px_7a", "user@example.com");
+            api.set_transport(transport.clone());
+            api.set_aas_token("aas_et/abc");
+            api.set_auth_token(String::from("stale"), None);
+            api.gsf_id = Some(1);
+
+            assert!(api.details("com.example").await.unwrap().is_some());
+            let urls = urls(&transport);
+            assert_eq!(urls.len(), 3);
+            assert!(urls[0].ends_with("/fdfe/details?doc=com.example"));
+            assert!(urls[1].contains("/auth?"));
+            assert!(urls[2].ends_with("/fdfe/details?doc=com.example"));
+
+            let session = api.session().unwrap();
+            assert_eq!(session.auth_token, "fresh");
+            assert_eq!(session.auth_token_expiry, Some(expiry));
+        }
+
+        #[tokio::test]
+        async fn renews_token_before_expiry() {
+            let transport = fake_backend(now_secs() + 3600);
+            let mut api = Gpapi::new("px_7a", "user@example.com");
+            api.set_transport(transport.clone());
+            api.set_aas_token("aas_et/abc");
+            api.set_auth_token(String::from("stale"), Some(now_secs() + 10));
+
+            assert!(api.details("com.example").await.unwrap().is_some());
+            let urls = urls(&transport);
+            assert_eq!(urls.len(), 2);
+            assert!(urls[0].contains("/auth?"));
+        }
+
+        #[tokio::test]
+        async fn does_not_retry_without_aas_token() {
+            let transport = fake_backend(now_secs() + 3600);
+            let mut api = Gpapi::new("px_7a", "user@example.com");
+            api.set_transport(transport.clone());
+            api.set_auth_token(String::from("stale"), None);
+
+            let err = GpapiError::from(api.details("com.example").await.unwrap_err());
+            assert!(matches!(err.kind(), GpapiErrorKind::TokenExpired));
+            assert_eq!(urls(&transport).len(), 1);
+        }
+    }
+
     mod gpapi {
         use std::env;
 
@@ -1033,7 +1935,7 @@ mod tests {
                 assert!(api.device_checkin_consistency_token.is_some());
                 assert!(api.gsf_id.is_some());
                 assert!(api.device_config_token.is_some());
-                assert!(api.auth_token.is_some());
+                assert!(api.auth_token().is_some());
                 assert!(api.dfe_cookie.is_some() || api.tos_token.is_some());
             }
         }
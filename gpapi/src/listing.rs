@@ -0,0 +1,294 @@
+//! Typed, paginated app listings, as returned by `Gpapi::search` and `Gpapi::top_chart`, and
+//! the categories they can be narrowed to.
+
+use googleplay_protobuf::{BrowseLink, Item, Payload};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// `Item::type` of an Android app document.
+const DOC_TYPE_ANDROID_APP: i32 = 1;
+
+/// `Image::image_type` of an app icon.
+const IMAGE_TYPE_ICON: i32 = 4;
+
+/// Options for `Gpapi::search`.
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    /// Cursor from a previous page's `next_page`. The query is ignored when this is set, as the
+    /// cursor already carries it.
+    pub page: Option<String>,
+    /// Return at most this many results. Play picks the page size otherwise.
+    pub limit: Option<usize>,
+}
+
+/// The Play Store top charts.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Chart {
+    TopFree,
+    TopPaid,
+    TopGrossing,
+    Trending,
+}
+
+impl Chart {
+    pub const ALL: [Chart; 4] = [
+        Chart::TopFree,
+        Chart::TopPaid,
+        Chart::TopGrossing,
+        Chart::Trending,
+    ];
+
+    /// The chart id the fdfe `list` endpoint expects.
+    pub(crate) fn list_id(&self) -> &'static str {
+        match self {
+            Chart::TopFree => "apps_topselling_free",
+            Chart::TopPaid => "apps_topselling_paid",
+            Chart::TopGrossing => "apps_topgrossing",
+            Chart::Trending => "apps_movers_shakers",
+        }
+    }
+}
+
+impl fmt::Display for Chart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chart::TopFree => write!(f, "top_free"),
+            Chart::TopPaid => write!(f, "top_paid"),
+            Chart::TopGrossing => write!(f, "top_grossing"),
+            Chart::Trending => write!(f, "trending"),
+        }
+    }
+}
+
+impl FromStr for Chart {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Chart::ALL
+            .into_iter()
+            .find(|chart| chart.to_string() == s)
+            .ok_or_else(|| format!("Unknown chart '{}'", s))
+    }
+}
+
+/// Options for `Gpapi::top_chart`.
+#[derive(Debug, Clone, Default)]
+pub struct ChartOptions {
+    /// Category id as returned by `Gpapi::categories`, e.g. `GAME_PUZZLE`. All apps by default.
+    pub category: Option<String>,
+    /// Cursor from a previous page's `next_page`.
+    pub page: Option<String>,
+    /// Return at most this many results. Play picks the page size otherwise.
+    pub limit: Option<usize>,
+}
+
+/// A Play Store category apps can be browsed by.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Category {
+    /// Id to pass as `ChartOptions::category`, e.g. `GAME_PUZZLE`
+    pub id: String,
+    pub name: String,
+    pub icon_url: Option<String>,
+}
+
+impl Category {
+    /// Categories link to their browse page, e.g. `browse?c=3&cat=GAME_PUZZLE`, which is where
+    /// the id comes from.
+    pub(crate) fn from_link(link: BrowseLink) -> Option<Self> {
+        let data_url = link.data_url?;
+        let (_, query) = data_url.split_once('?')?;
+        let id = query
+            .split('&')
+            .find_map(|param| param.strip_prefix("cat="))
+            .filter(|id| !id.is_empty())?;
+        Some(Category {
+            id: id.to_string(),
+            name: link.name.unwrap_or_else(|| id.to_string()),
+            icon_url: link.icon.and_then(|icon| icon.image_url),
+        })
+    }
+}
+
+/// One page of an app listing.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Page {
+    pub results: Vec<AppSummary>,
+    /// Cursor for the next page, `None` on the last page.
+    pub next_page: Option<String>,
+}
+
+/// The listing view of an app: enough to show it in a list and look up its details.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct AppSummary {
+    pub package_name: String,
+    pub title: Option<String>,
+    pub creator: Option<String>,
+    pub icon_url: Option<String>,
+    pub star_rating: Option<f32>,
+    pub ratings_count: Option<u64>,
+    pub version_code: Option<i32>,
+    pub version_string: Option<String>,
+    /// Whether the app can be installed without paying for it.
+    pub free: bool,
+    /// Localized price, e.g. `$1.99`, if the app is paid.
+    pub formatted_price: Option<String>,
+}
+
+impl Page {
+    /// Collect the apps listed in a search or list payload. Results are usually grouped into
+    /// clusters, whose container metadata carries the next page URL.
+    pub(crate) fn from_payload(payload: Payload, limit: Option<usize>) -> Self {
+        let mut page = Page::default();
+        let items = if let Some(search_response) = payload.search_response {
+            page.next_page = search_response.next_page_url;
+            search_response.item
+        } else if let Some(list_response) = payload.list_response {
+            list_response.item
+        } else {
+            Vec::new()
+        };
+        for item in items {
+            page.collect(item);
+        }
+        if let Some(limit) = limit {
+            page.results.truncate(limit);
+        }
+        page.next_page = page.next_page.filter(|next_page| !next_page.is_empty());
+        page
+    }
+
+    fn collect(&mut self, item: Item) {
+        if item.r#type == Some(DOC_TYPE_ANDROID_APP) {
+            if let Some(summary) = AppSummary::from_item(item) {
+                self.results.push(summary);
+            }
+            return;
+        }
+        if self.next_page.is_none() {
+            self.next_page = item
+                .container_metadata
+                .as_ref()
+                .and_then(|metadata| metadata.next_page_url.clone());
+        }
+        for sub_item in item.sub_item {
+            self.collect(sub_item);
+        }
+    }
+}
+
+impl AppSummary {
+    fn from_item(item: Item) -> Option<Self> {
+        let app_details = item
+            .details
+            .as_ref()
+            .and_then(|details| details.app_details.as_ref());
+        let package_name = item
+            .id
+            .clone()
+            .or_else(|| app_details.and_then(|app| app.package_name.clone()))?;
+        let offer = item.offer.first();
+        let free = offer.and_then(|offer| offer.micros).unwrap_or(0) == 0;
+        Some(AppSummary {
+            package_name,
+            title: item.title.clone(),
+            creator: item.creator.clone(),
+            icon_url: item
+                .image
+                .iter()
+                .find(|image| image.image_type == Some(IMAGE_TYPE_ICON))
+                .and_then(|image| image.image_url.clone()),
+            star_rating: item
+                .aggregate_rating
+                .as_ref()
+                .and_then(|rating| rating.star_rating),
+            ratings_count: item
+                .aggregate_rating
+                .as_ref()
+                .and_then(|rating| rating.ratings_count),
+            version_code: app_details.and_then(|app| app.version_code),
+            version_string: app_details.and_then(|app| app.version_string.clone()),
+            free,
+            formatted_price: if free {
+                None
+            } else {
+                offer.and_then(|offer| offer.formatted_amount.clone())
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googleplay_protobuf::{ContainerMetadata, Offer, SearchResponse};
+
+    fn app(package_name: &str, price_micros: i64) -> Item {
+        Item {
+            id: Some(String::from(package_name)),
+            r#type: Some(DOC_TYPE_ANDROID_APP),
+            title: Some(format!("{} title", package_name)),
+            offer: vec![Offer {
+                micros: Some(price_micros),
+                formatted_amount: Some(String::from("$1.99")),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn collects_apps_from_clusters() {
+        let payload = Payload {
+            search_response: Some(SearchResponse {
+                item: vec![Item {
+                    container_metadata: Some(ContainerMetadata {
+                        next_page_url: Some(String::from("search?q=chat&o=2")),
+                        ..Default::default()
+                    }),
+                    sub_item: vec![
+                        app("com.example.free", 0),
+                        app("com.example.paid", 1_990_000),
+                    ],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let page = Page::from_payload(payload.clone(), None);
+        assert_eq!(page.next_page.as_deref(), Some("search?q=chat&o=2"));
+        assert_eq!(page.results.len(), 2);
+        assert_eq!(page.results[0].package_name, "com.example.free");
+        assert!(page.results[0].free);
+        assert_eq!(page.results[0].formatted_price, None);
+        assert!(!page.results[1].free);
+        assert_eq!(page.results[1].formatted_price.as_deref(), Some("$1.99"));
+
+        let page = Page::from_payload(payload, Some(1));
+        assert_eq!(page.results.len(), 1);
+    }
+
+    #[test]
+    fn parses_chart_names() {
+        for chart in Chart::ALL {
+            assert_eq!(chart.to_string().parse::<Chart>(), Ok(chart));
+        }
+        assert!("top_sold".parse::<Chart>().is_err());
+    }
+
+    #[test]
+    fn reads_category_ids_from_links() {
+        let link = |data_url: &str| BrowseLink {
+            name: Some(String::from("Puzzle")),
+            data_url: Some(String::from(data_url)),
+            ..Default::default()
+        };
+        let category = Category::from_link(link("browse?c=3&cat=GAME_PUZZLE")).unwrap();
+        assert_eq!(category.id, "GAME_PUZZLE");
+        assert_eq!(category.name, "Puzzle");
+        assert!(Category::from_link(link("browse?c=3")).is_none());
+    }
+}
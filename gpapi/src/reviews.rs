@@ -0,0 +1,187 @@
+//! Typed view of the `rev` response, as returned by `Gpapi::reviews`.
+
+use googleplay_protobuf::ReviewResponse;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Number of reviews requested per page.
+pub(crate) const REVIEWS_PAGE_SIZE: usize = 20;
+
+/// Order in which `Gpapi::reviews` returns reviews.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewSort {
+    #[default]
+    MostRelevant,
+    Newest,
+    Rating,
+}
+
+impl ReviewSort {
+    /// The `sort` value the fdfe `rev` endpoint expects.
+    pub(crate) fn param(&self) -> &'static str {
+        match self {
+            ReviewSort::MostRelevant => "1",
+            ReviewSort::Newest => "2",
+            ReviewSort::Rating => "3",
+        }
+    }
+}
+
+impl fmt::Display for ReviewSort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReviewSort::MostRelevant => write!(f, "most_relevant"),
+            ReviewSort::Newest => write!(f, "newest"),
+            ReviewSort::Rating => write!(f, "rating"),
+        }
+    }
+}
+
+impl FromStr for ReviewSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "most_relevant" => Ok(ReviewSort::MostRelevant),
+            "newest" => Ok(ReviewSort::Newest),
+            "rating" => Ok(ReviewSort::Rating),
+            _ => Err(format!("Unknown review sort '{}'", s)),
+        }
+    }
+}
+
+/// One page of reviews.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReviewPage {
+    pub reviews: Vec<Review>,
+    /// Total number of reviews matching the filters, if the server said so.
+    pub matching_count: Option<i64>,
+    /// Offset to pass to `Gpapi::reviews` for the next page, `None` on the last page.
+    pub next_offset: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Review {
+    pub id: Option<String>,
+    pub author: Option<String>,
+    /// Star rating, from 1 to 5
+    pub rating: Option<i32>,
+    pub title: Option<String>,
+    pub text: Option<String>,
+    /// Version name of the app the review was written for
+    pub version: Option<String>,
+    pub device_name: Option<String>,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: Option<i64>,
+    pub thumbs_up_count: Option<i64>,
+    pub developer_reply: Option<DeveloperReply>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeveloperReply {
+    pub text: String,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: Option<i64>,
+}
+
+impl ReviewPage {
+    pub(crate) fn from_response(response: ReviewResponse, offset: usize) -> Self {
+        let has_next_page = response.next_page_url.is_some();
+        let (reviews, matching_count) = match response.user_reviews_response {
+            Some(reviews) => (reviews.review, reviews.matching_count),
+            None => (Vec::new(), None),
+        };
+        let reviews: Vec<Review> = reviews.into_iter().map(Review::from).collect();
+        let next_offset = if has_next_page || reviews.len() == REVIEWS_PAGE_SIZE {
+            Some(offset + reviews.len())
+        } else {
+            None
+        };
+        ReviewPage {
+            reviews,
+            matching_count,
+            next_offset,
+        }
+    }
+}
+
+impl From<googleplay_protobuf::Review> for Review {
+    fn from(review: googleplay_protobuf::Review) -> Self {
+        Review {
+            id: review.comment_id,
+            author: review
+                .author_name
+                .or_else(|| review.user_profile.and_then(|profile| profile.name)),
+            rating: review.star_rating,
+            title: review.title.filter(|title| !title.is_empty()),
+            text: review.comment,
+            version: review.version,
+            device_name: review.device_name,
+            timestamp: review.timestamp,
+            thumbs_up_count: review.thumbs_up_count,
+            developer_reply: review.reply_text.map(|text| DeveloperReply {
+                text,
+                timestamp: review.reply_time_stamp,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googleplay_protobuf::{GetReviewsResponse, UserProfile};
+
+    #[test]
+    fn converts_review_response() {
+        let response = ReviewResponse {
+            user_reviews_response: Some(GetReviewsResponse {
+                review: vec![googleplay_protobuf::Review {
+                    comment_id: Some(String::from("gp:1")),
+                    user_profile: Some(UserProfile {
+                        name: Some(String::from("Jane")),
+                        ..Default::default()
+                    }),
+                    star_rating: Some(4),
+                    comment: Some(String::from("Works well")),
+                    version: Some(String::from("289.20")),
+                    timestamp: Some(1_700_000_000_000),
+                    reply_text: Some(String::from("Thanks!")),
+                    reply_time_stamp: Some(1_700_000_100_000),
+                    ..Default::default()
+                }],
+                matching_count: Some(1),
+            }),
+            ..Default::default()
+        };
+
+        let page = ReviewPage::from_response(response, 0);
+        assert_eq!(page.next_offset, None);
+        assert_eq!(page.matching_count, Some(1));
+        let review = &page.reviews[0];
+        assert_eq!(review.author.as_deref(), Some("Jane"));
+        assert_eq!(review.rating, Some(4));
+        assert_eq!(
+            review.developer_reply,
+            Some(DeveloperReply {
+                text: String::from("Thanks!"),
+                timestamp: Some(1_700_000_100_000),
+            })
+        );
+    }
+
+    #[test]
+    fn full_pages_have_a_next_offset() {
+        let response = ReviewResponse {
+            user_reviews_response: Some(GetReviewsResponse {
+                review: vec![Default::default(); REVIEWS_PAGE_SIZE],
+                matching_count: None,
+            }),
+            ..Default::default()
+        };
+        let page = ReviewPage::from_response(response, 40);
+        assert_eq!(page.next_offset, Some(40 + REVIEWS_PAGE_SIZE));
+    }
+}
@@ -0,0 +1,148 @@
+//! Enrollment in an app's testing program (its beta or alpha track), as managed by
+//! `Gpapi::join_testing_program`, `Gpapi::leave_testing_program` and
+//! `Gpapi::testing_program_status`.
+
+use googleplay_protobuf::{DetailsResponse, TestingProgramResponse};
+use serde::{Deserialize, Serialize};
+
+/// Whether an app runs a testing program, and whether the logged-in account is enrolled in it.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct TestingProgramStatus {
+    /// The app has a testing program open to the account.
+    pub available: bool,
+    /// The account is enrolled in the testing program.
+    pub subscribed: bool,
+    /// The account is enrolled and has the test build installed.
+    pub subscribed_and_installed: bool,
+    /// Feedback address of the testing program, if the developer set one.
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+}
+
+impl TestingProgramStatus {
+    pub(crate) fn from_details(details: &DetailsResponse) -> Self {
+        let info = details
+            .item
+            .as_ref()
+            .and_then(|item| item.details.as_ref())
+            .and_then(|details| details.app_details.as_ref())
+            .and_then(|app_details| app_details.testing_program_info.as_ref());
+        match info {
+            Some(info) => TestingProgramStatus {
+                available: true,
+                subscribed: info.subscribed.unwrap_or(false),
+                subscribed_and_installed: info.subscribed_and_installed.unwrap_or(false),
+                email: info.email.clone(),
+                display_name: info.display_name.clone(),
+            },
+            None => TestingProgramStatus::default(),
+        }
+    }
+}
+
+/// Whether the server confirmed a join (`subscribe`) or leave request.
+pub(crate) fn confirmed(response: Option<TestingProgramResponse>, subscribe: bool) -> bool {
+    let details = response
+        .and_then(|response| response.result)
+        .and_then(|result| result.details);
+    match details {
+        Some(details) if subscribe => details.subscribed.unwrap_or(false),
+        Some(details) => details.unsubscribed.unwrap_or(false),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fake::{encode, respond, FakeTransport};
+    use crate::Gpapi;
+    use googleplay_protobuf::{
+        AppDetails, DocumentDetails, Item, Payload, ResponseWrapper, TestingProgramDetails,
+        TestingProgramInfo, TestingProgramRequest, TestingProgramResult,
+    };
+    use prost::Message;
+
+    #[test]
+    fn reads_status_from_details() {
+        assert_eq!(
+            TestingProgramStatus::from_details(&DetailsResponse::default()),
+            TestingProgramStatus::default()
+        );
+
+        let details = DetailsResponse {
+            item: Some(Item {
+                details: Some(DocumentDetails {
+                    app_details: Some(AppDetails {
+                        testing_program_info: Some(TestingProgramInfo {
+                            subscribed: Some(true),
+                            email: Some(String::from("beta@example.com")),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let status = TestingProgramStatus::from_details(&details);
+        assert!(status.available);
+        assert!(status.subscribed);
+        assert!(!status.subscribed_and_installed);
+        assert_eq!(status.email.as_deref(), Some("beta@example.com"));
+    }
+
+    #[test]
+    fn confirms_requested_change() {
+        let response = |subscribed, unsubscribed| {
+            Some(TestingProgramResponse {
+                result: Some(TestingProgramResult {
+                    details: Some(TestingProgramDetails {
+                        subscribed: Some(subscribed),
+                        unsubscribed: Some(unsubscribed),
+                        ..Default::default()
+                    }),
+                }),
+            })
+        };
+        assert!(confirmed(response(true, false), true));
+        assert!(!confirmed(response(true, false), false));
+        assert!(confirmed(response(false, true), false));
+        assert!(!confirmed(None, true));
+    }
+
+    #[tokio::test]
+    async fn joins_testing_programs() {
+        let transport = FakeTransport::new(|_| {
+            let wrapper = ResponseWrapper {
+                payload: Some(Payload {
+                    testing_program_response: Some(TestingProgramResponse {
+                        result: Some(TestingProgramResult {
+                            details: Some(TestingProgramDetails {
+                                subscribed: Some(true),
+                                ..Default::default()
+                            }),
+                        }),
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            respond(200, encode(&wrapper))
+        });
+        let mut api = Gpapi::new("px_7a", "user@example.com");
+        api.set_transport(transport.clone());
+        api.set_auth_token(String::from("token"), None);
+
+        assert!(api.join_testing_program("com.example").await.unwrap());
+
+        let requests = transport.requests.lock().unwrap();
+        assert!(requests[0].url.ends_with("/fdfe/apps/testingProgram"));
+        let body = requests[0].body.as_deref().unwrap();
+        let req = TestingProgramRequest::decode(body).unwrap();
+        assert_eq!(req.package_name.as_deref(), Some("com.example"));
+        assert_eq!(req.subscribe, Some(true));
+    }
+}
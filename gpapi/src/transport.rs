@@ -0,0 +1,117 @@
+//! HTTP transport used by [`Gpapi`](crate::Gpapi) to talk to the Play backend.
+//!
+//! By default requests go through [`ReqwestTransport`]. Embedders that already have an HTTP
+//! stack (e.g. `worker::Fetch` on Cloudflare Workers) or tests that want to answer requests
+//! from a fake server can implement [`Transport`] and install it with
+//! [`Gpapi::set_transport`](crate::Gpapi::set_transport).
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Post,
+}
+
+/// A fully prepared request: the URL already carries its query string.
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    /// Response headers, keyed by lowercase header name.
+    pub headers: HashMap<String, String>,
+    pub body: Bytes,
+}
+
+impl HttpResponse {
+    /// Look up a response header, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait Transport: Debug + Send + Sync {
+    async fn send(&self, req: HttpRequest) -> Result<HttpResponse, Box<dyn Error + Send + Sync>>;
+}
+
+/// A transport shared between several `Gpapi` instances.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+    async fn send(&self, req: HttpRequest) -> Result<HttpResponse, Box<dyn Error + Send + Sync>> {
+        (**self).send(req).await
+    }
+}
+
+/// The default transport, backed by a `reqwest::Client`.
+#[derive(Debug, Default)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+impl ReqwestTransport {
+    pub fn new(client: reqwest::Client) -> Self {
+        ReqwestTransport { client }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Transport for ReqwestTransport {
+    async fn send(&self, req: HttpRequest) -> Result<HttpResponse, Box<dyn Error + Send + Sync>> {
+        let mut reqwest_headers = HeaderMap::new();
+        for (key, val) in req.headers {
+            reqwest_headers.insert(
+                HeaderName::from_bytes(key.as_bytes())?,
+                HeaderValue::from_str(&val)?,
+            );
+        }
+
+        let builder = match req.method {
+            Method::Get => self.client.get(&req.url),
+            Method::Post => self.client.post(&req.url),
+        };
+        let builder = builder.headers(reqwest_headers);
+        let builder = match req.body {
+            Some(body) => builder.body(body),
+            None => builder,
+        };
+        let res = builder.send().await?;
+
+        let status = res.status().as_u16();
+        let headers = res
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| {
+                value
+                    .to_str()
+                    .ok()
+                    .map(|value| (name.as_str().to_ascii_lowercase(), value.to_string()))
+            })
+            .collect();
+        let body = res.bytes().await?;
+
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
@@ -0,0 +1,14 @@
+-- Every version of an app seen per channel, see src/version_history.rs
+CREATE TABLE IF NOT EXISTS versions (
+    package_name TEXT NOT NULL,
+    channel TEXT NOT NULL,
+    version_code INTEGER NOT NULL,
+    version_string TEXT,
+    recent_changes_html TEXT,
+    info_updated_on TEXT,
+    download_size INTEGER,
+    first_seen INTEGER NOT NULL,
+    PRIMARY KEY (package_name, channel, version_code)
+);
+
+CREATE INDEX IF NOT EXISTS versions_timeline ON versions (package_name, first_seen DESC);
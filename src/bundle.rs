@@ -0,0 +1,496 @@
+//! Layout of the split APK archives served by `/v1/bundle`: the files, their paths and the
+//! manifest each format is read by.
+
+use gpapi::DownloadInfo;
+use prost::Message;
+use serde::Serialize;
+
+use crate::splits::split_module;
+use crate::zip::ZipEntry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BundleFormat {
+    /// bundletool's APK set, with a `toc.pb` describing the splits.
+    Apks,
+    /// APKPure's format, with a `manifest.json` and the OBBs installers copy into place.
+    Xapk,
+}
+
+impl BundleFormat {
+    /// Parse the `format` query parameter; `apks` by default.
+    pub fn from_query(format: Option<&str>) -> Result<Self, String> {
+        match format.map(|format| format.to_ascii_lowercase()).as_deref() {
+            None | Some("") | Some("apks") => Ok(BundleFormat::Apks),
+            Some("xapk") => Ok(BundleFormat::Xapk),
+            Some(format) => Err(format!(
+                "Invalid format '{}', expected 'apks' or 'xapk'",
+                format
+            )),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            BundleFormat::Apks => "apks",
+            BundleFormat::Xapk => "xapk",
+        }
+    }
+}
+
+/// Where the data of an archive entry comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BundleSource {
+    /// Generated by Sniff, like the manifest.
+    Inline(Vec<u8>),
+    /// Downloaded from Google Play, with the download's auth cookies.
+    Download(String),
+}
+
+/// What the manifests say about the app, from its details.
+#[derive(Debug, Clone, Default)]
+pub struct BundleApp {
+    pub name: Option<String>,
+    pub version_code: i32,
+    pub version_name: Option<String>,
+    pub target_sdk_version: Option<i32>,
+    pub permissions: Vec<String>,
+}
+
+/// The entries of an archive of `download`, manifest first, then the base APK, the splits and
+/// the expansion files. Fails when Google Play gave no URL for one of the files.
+pub fn layout(
+    format: BundleFormat,
+    package_name: &str,
+    app: &BundleApp,
+    download: &DownloadInfo,
+) -> Result<Vec<ZipEntry<BundleSource>>, String> {
+    let main_url = download
+        .main_apk
+        .download_url
+        .clone()
+        .ok_or("No download URL available")?;
+
+    let mut files = vec![ZipEntry {
+        name: match format {
+            BundleFormat::Apks => String::from("splits/base-master.apk"),
+            BundleFormat::Xapk => format!("{}.apk", package_name),
+        },
+        size: file_size(download.main_apk.size),
+        source: BundleSource::Download(main_url),
+    }];
+    for split in &download.splits {
+        let name = split.name.as_deref().ok_or("Split without a name")?;
+        let url = split
+            .file
+            .download_url
+            .clone()
+            .ok_or_else(|| format!("No download URL available for split '{}'", name))?;
+        files.push(ZipEntry {
+            name: match format {
+                BundleFormat::Apks => apks_split_path(name),
+                BundleFormat::Xapk => format!("{}.apk", name),
+            },
+            size: file_size(split.file.size),
+            source: BundleSource::Download(url),
+        });
+    }
+    for file in &download.additional_files {
+        let url = file
+            .download_url
+            .clone()
+            .ok_or_else(|| format!("No download URL available for '{}'", file.filename))?;
+        files.push(ZipEntry {
+            name: obb_path(package_name, &file.filename),
+            size: file_size(file.size),
+            source: BundleSource::Download(url),
+        });
+    }
+
+    let (name, manifest) = match format {
+        BundleFormat::Apks => (
+            String::from("toc.pb"),
+            apks_toc(package_name, download).encode_to_vec(),
+        ),
+        BundleFormat::Xapk => (
+            String::from("manifest.json"),
+            serde_json::to_vec_pretty(&xapk_manifest(package_name, app, download))
+                .map_err(|e| e.to_string())?,
+        ),
+    };
+    files.insert(
+        0,
+        ZipEntry {
+            name,
+            size: Some(manifest.len() as u64),
+            source: BundleSource::Inline(manifest),
+        },
+    );
+    Ok(files)
+}
+
+/// The size Google Play gave for a file, if any.
+fn file_size(size: Option<i64>) -> Option<u64> {
+    size.and_then(|size| u64::try_from(size).ok())
+}
+
+/// Expansion files go where Android looks for them, e.g. `Android/obb/com.example/main.1.com.example.obb`.
+fn obb_path(package_name: &str, filename: &str) -> String {
+    format!("Android/obb/{}/{}", package_name, filename)
+}
+
+fn apks_split_path(name: &str) -> String {
+    let (module, config) = split_module(name);
+    format!("splits/{}-{}.apk", module, config.unwrap_or("master"))
+}
+
+/// The subset of bundletool's `BuildApksResult` (`commands.proto`) that installers read from a
+/// `toc.pb`.
+mod toc {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct BuildApksResult {
+        #[prost(message, repeated, tag = "1")]
+        pub variant: Vec<Variant>,
+        #[prost(string, tag = "4")]
+        pub package_name: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Variant {
+        #[prost(message, repeated, tag = "2")]
+        pub apk_set: Vec<ApkSet>,
+        #[prost(uint32, tag = "3")]
+        pub variant_number: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ApkSet {
+        #[prost(message, optional, tag = "1")]
+        pub module_metadata: Option<ModuleMetadata>,
+        #[prost(message, repeated, tag = "2")]
+        pub apk_description: Vec<ApkDescription>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ModuleMetadata {
+        #[prost(string, tag = "1")]
+        pub name: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ApkDescription {
+        #[prost(string, tag = "2")]
+        pub path: String,
+        #[prost(message, optional, tag = "3")]
+        pub split_apk_metadata: Option<SplitApkMetadata>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SplitApkMetadata {
+        #[prost(string, tag = "1")]
+        pub split_id: String,
+        #[prost(bool, tag = "2")]
+        pub is_master_split: bool,
+    }
+}
+
+/// Add an APK to its module's set, creating the set for the module's first APK.
+fn add_apk(apk_sets: &mut Vec<toc::ApkSet>, module: &str, description: toc::ApkDescription) {
+    let existing = apk_sets.iter_mut().find(|set| {
+        set.module_metadata
+            .as_ref()
+            .is_some_and(|metadata| metadata.name == module)
+    });
+    match existing {
+        Some(set) => set.apk_description.push(description),
+        None => apk_sets.push(toc::ApkSet {
+            module_metadata: Some(toc::ModuleMetadata {
+                name: module.to_string(),
+            }),
+            apk_description: vec![description],
+        }),
+    }
+}
+
+/// A single variant with an APK set per module, listing the base APK and every split.
+fn apks_toc(package_name: &str, download: &DownloadInfo) -> toc::BuildApksResult {
+    let mut apk_sets = Vec::new();
+    add_apk(
+        &mut apk_sets,
+        "base",
+        toc::ApkDescription {
+            path: String::from("splits/base-master.apk"),
+            split_apk_metadata: Some(toc::SplitApkMetadata {
+                split_id: String::new(),
+                is_master_split: true,
+            }),
+        },
+    );
+    for name in download
+        .splits
+        .iter()
+        .filter_map(|split| split.name.as_deref())
+    {
+        let (module, config) = split_module(name);
+        add_apk(
+            &mut apk_sets,
+            module,
+            toc::ApkDescription {
+                path: apks_split_path(name),
+                split_apk_metadata: Some(toc::SplitApkMetadata {
+                    split_id: name.to_string(),
+                    is_master_split: config.is_none(),
+                }),
+            },
+        );
+    }
+
+    toc::BuildApksResult {
+        variant: vec![toc::Variant {
+            apk_set: apk_sets,
+            variant_number: 0,
+        }],
+        package_name: package_name.to_string(),
+    }
+}
+
+#[derive(Serialize)]
+struct XapkManifest {
+    xapk_version: u32,
+    package_name: String,
+    name: String,
+    version_code: String,
+    version_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_sdk_version: Option<String>,
+    permissions: Vec<String>,
+    total_size: i64,
+    split_configs: Vec<String>,
+    split_apks: Vec<XapkSplit>,
+    expansions: Vec<XapkExpansion>,
+}
+
+#[derive(Serialize)]
+struct XapkSplit {
+    file: String,
+    id: String,
+}
+
+#[derive(Serialize)]
+struct XapkExpansion {
+    file: String,
+    install_location: String,
+    install_path: String,
+}
+
+fn xapk_manifest(package_name: &str, app: &BundleApp, download: &DownloadInfo) -> XapkManifest {
+    let split_names: Vec<String> = download
+        .splits
+        .iter()
+        .filter_map(|split| split.name.clone())
+        .collect();
+    let mut split_apks = vec![XapkSplit {
+        file: format!("{}.apk", package_name),
+        id: String::from("base"),
+    }];
+    split_apks.extend(split_names.iter().map(|name| XapkSplit {
+        file: format!("{}.apk", name),
+        id: name.clone(),
+    }));
+    let total_size = download.main_apk.size.unwrap_or(0)
+        + download
+            .splits
+            .iter()
+            .filter_map(|split| split.file.size)
+            .sum::<i64>()
+        + download
+            .additional_files
+            .iter()
+            .filter_map(|file| file.size)
+            .sum::<i64>();
+
+    XapkManifest {
+        xapk_version: 2,
+        package_name: package_name.to_string(),
+        name: app.name.clone().unwrap_or_else(|| package_name.to_string()),
+        version_code: app.version_code.to_string(),
+        version_name: app
+            .version_name
+            .clone()
+            .unwrap_or_else(|| app.version_code.to_string()),
+        target_sdk_version: app.target_sdk_version.map(|sdk| sdk.to_string()),
+        permissions: app.permissions.clone(),
+        total_size,
+        split_configs: split_names,
+        split_apks,
+        expansions: download
+            .additional_files
+            .iter()
+            .map(|file| {
+                let path = obb_path(package_name, &file.filename);
+                XapkExpansion {
+                    file: path.clone(),
+                    install_location: String::from("EXTERNAL_STORAGE"),
+                    install_path: path,
+                }
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gpapi::{AdditionalFile, AdditionalFileType, ApkFile, SplitApk};
+
+    fn apk(url: &str, size: i64) -> ApkFile {
+        ApkFile {
+            download_url: Some(url.to_string()),
+            size: Some(size),
+            ..Default::default()
+        }
+    }
+
+    fn download() -> DownloadInfo {
+        DownloadInfo {
+            main_apk: apk("https://play.example/base", 100),
+            splits: vec![
+                SplitApk {
+                    name: Some(String::from("config.arm64_v8a")),
+                    file: apk("https://play.example/arm64", 20),
+                },
+                SplitApk {
+                    name: Some(String::from("camera")),
+                    file: apk("https://play.example/camera", 10),
+                },
+                SplitApk {
+                    name: Some(String::from("camera.config.en")),
+                    file: apk("https://play.example/camera-en", 1),
+                },
+            ],
+            additional_files: vec![AdditionalFile {
+                filename: String::from("main.42.com.example.obb"),
+                file_type: AdditionalFileType::Main,
+                version_code: 42,
+                download_url: Some(String::from("https://play.example/obb")),
+                size: Some(1000),
+                gzipped_download_url: None,
+                gzipped_size: None,
+                sha1: None,
+            }],
+            download_auth_cookies: Vec::new(),
+        }
+    }
+
+    fn names(entries: &[ZipEntry<BundleSource>]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.name.as_str()).collect()
+    }
+
+    #[test]
+    fn lays_out_apks() {
+        let entries = layout(
+            BundleFormat::Apks,
+            "com.example",
+            &BundleApp::default(),
+            &download(),
+        )
+        .unwrap();
+        assert_eq!(
+            names(&entries),
+            [
+                "toc.pb",
+                "splits/base-master.apk",
+                "splits/base-arm64_v8a.apk",
+                "splits/camera-master.apk",
+                "splits/camera-en.apk",
+                "Android/obb/com.example/main.42.com.example.obb",
+            ]
+        );
+        assert_eq!(
+            entries[2].source,
+            BundleSource::Download(String::from("https://play.example/arm64"))
+        );
+
+        let BundleSource::Inline(toc) = &entries[0].source else {
+            panic!("toc.pb is generated");
+        };
+        let toc = toc::BuildApksResult::decode(toc.as_slice()).unwrap();
+        assert_eq!(toc.package_name, "com.example");
+        let apk_sets = &toc.variant[0].apk_set;
+        let modules: Vec<&str> = apk_sets
+            .iter()
+            .map(|set| set.module_metadata.as_ref().unwrap().name.as_str())
+            .collect();
+        assert_eq!(modules, ["base", "camera"]);
+        assert_eq!(apk_sets[0].apk_description.len(), 2);
+        let camera = &apk_sets[1].apk_description;
+        assert_eq!(camera[0].path, "splits/camera-master.apk");
+        assert!(
+            camera[0]
+                .split_apk_metadata
+                .as_ref()
+                .unwrap()
+                .is_master_split
+        );
+        assert_eq!(
+            camera[1].split_apk_metadata.as_ref().unwrap().split_id,
+            "camera.config.en"
+        );
+    }
+
+    #[test]
+    fn lays_out_xapk() {
+        let app = BundleApp {
+            name: Some(String::from("Example")),
+            version_code: 42,
+            version_name: Some(String::from("4.2")),
+            target_sdk_version: Some(35),
+            permissions: vec![String::from("android.permission.INTERNET")],
+        };
+        let entries = layout(BundleFormat::Xapk, "com.example", &app, &download()).unwrap();
+        assert_eq!(
+            names(&entries),
+            [
+                "manifest.json",
+                "com.example.apk",
+                "config.arm64_v8a.apk",
+                "camera.apk",
+                "camera.config.en.apk",
+                "Android/obb/com.example/main.42.com.example.obb",
+            ]
+        );
+
+        let BundleSource::Inline(manifest) = &entries[0].source else {
+            panic!("manifest.json is generated");
+        };
+        let manifest: serde_json::Value = serde_json::from_slice(manifest).unwrap();
+        assert_eq!(manifest["package_name"], "com.example");
+        assert_eq!(manifest["version_code"], "42");
+        assert_eq!(manifest["target_sdk_version"], "35");
+        assert_eq!(manifest["total_size"], 1131);
+        assert_eq!(manifest["split_apks"][0]["id"], "base");
+        assert_eq!(manifest["split_apks"][1]["file"], "config.arm64_v8a.apk");
+        assert_eq!(
+            manifest["expansions"][0]["install_path"],
+            "Android/obb/com.example/main.42.com.example.obb"
+        );
+    }
+
+    #[test]
+    fn requires_download_urls() {
+        let mut download = download();
+        download.splits[0].file.download_url = None;
+        assert!(layout(
+            BundleFormat::Apks,
+            "com.example",
+            &BundleApp::default(),
+            &download
+        )
+        .is_err());
+        assert_eq!(BundleFormat::from_query(None), Ok(BundleFormat::Apks));
+        assert_eq!(
+            BundleFormat::from_query(Some("XAPK")),
+            Ok(BundleFormat::Xapk)
+        );
+        assert!(BundleFormat::from_query(Some("zip")).is_err());
+    }
+}
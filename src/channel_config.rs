@@ -0,0 +1,134 @@
+use serde::Deserialize;
+
+use crate::config::ConfigError;
+use crate::google_play_client::Channel;
+
+/// Variable holding the JSON list of channels, see `ChannelConfig`.
+pub const CHANNELS_VAR: &str = "CHANNELS";
+
+/// One release channel: a Google Play account, and the device it logs in as.
+///
+/// `email_secret` and `token_secret` name the secrets (or vars) holding the account's email and
+/// AAS token, so the credentials themselves stay out of `CHANNELS`.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChannelConfig {
+    pub name: String,
+    pub email_secret: String,
+    pub token_secret: String,
+    /// Device codename to log in as, `DEVICE_NAME` by default.
+    #[serde(default)]
+    pub device: Option<String>,
+    /// Locale to request listings in, e.g. `de_DE`. `en_US` by default.
+    #[serde(default)]
+    pub locale: Option<String>,
+}
+
+impl ChannelConfig {
+    fn legacy(name: &str, prefix: &str) -> Self {
+        ChannelConfig {
+            name: name.to_string(),
+            email_secret: format!("{}_EMAIL", prefix),
+            token_secret: format!("{}_AAS_TOKEN", prefix),
+            device: None,
+            locale: None,
+        }
+    }
+
+    /// The channel this config is for. `parse_channels` already rejects invalid names, but the
+    /// fields are public, so the name is checked again.
+    pub fn channel(&self) -> Result<Channel, ConfigError> {
+        Channel::from_str(&self.name).map_err(|e| ConfigError {
+            problems: vec![format!("Invalid {}: {}", CHANNELS_VAR, e)],
+        })
+    }
+}
+
+/// The channels used when `CHANNELS` is not set: stable, beta and alpha, with their accounts in
+/// `{STABLE,BETA,ALPHA}_EMAIL` and `{STABLE,BETA,ALPHA}_AAS_TOKEN`.
+pub fn default_channels() -> Vec<ChannelConfig> {
+    vec![
+        ChannelConfig::legacy("stable", "STABLE"),
+        ChannelConfig::legacy("beta", "BETA"),
+        ChannelConfig::legacy("alpha", "ALPHA"),
+    ]
+}
+
+/// Parse and validate the `CHANNELS` JSON. Channel names must be valid and unique. Every other
+/// channel is compared against `stable`, so without one the legacy `STABLE_EMAIL` and
+/// `STABLE_AAS_TOKEN` account is added as `stable`.
+pub fn parse_channels(json: &str) -> Result<Vec<ChannelConfig>, String> {
+    let mut channels: Vec<ChannelConfig> =
+        serde_json::from_str(json).map_err(|e| format!("Invalid {}: {}", CHANNELS_VAR, e))?;
+    let mut names = Vec::new();
+    for config in &channels {
+        let channel = Channel::from_str(&config.name)
+            .map_err(|e| format!("Invalid {}: {}", CHANNELS_VAR, e))?;
+        if names.contains(&channel) {
+            return Err(format!(
+                "Invalid {}: channel '{}' is defined twice",
+                CHANNELS_VAR, channel
+            ));
+        }
+        names.push(channel);
+    }
+    if !names.iter().any(Channel::is_stable) {
+        channels.insert(0, ChannelConfig::legacy("stable", "STABLE"));
+    }
+    Ok(channels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_channels() {
+        let channels = parse_channels(
+            r#"[
+                {"name": "stable", "email_secret": "STABLE_EMAIL", "token_secret": "STABLE_AAS_TOKEN"},
+                {"name": "Closed-EU", "email_secret": "EU_EMAIL", "token_secret": "EU_TOKEN", "device": "px_9a", "locale": "de_DE"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[1].channel().unwrap().to_string(), "closed-eu");
+        assert_eq!(channels[1].device.as_deref(), Some("px_9a"));
+        assert_eq!(channels[1].locale.as_deref(), Some("de_DE"));
+        assert_eq!(channels[0].device, None);
+    }
+
+    #[test]
+    fn falls_back_to_legacy_stable_channel() {
+        let channels = parse_channels(
+            r#"[{"name": "internal", "email_secret": "INTERNAL_EMAIL", "token_secret": "INTERNAL_AAS_TOKEN"}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            channels,
+            [
+                ChannelConfig::legacy("stable", "STABLE"),
+                ChannelConfig::legacy("internal", "INTERNAL")
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_channels() {
+        let channel = |name: &str| {
+            format!(
+                r#"{{"name": "{}", "email_secret": "E", "token_secret": "T"}}"#,
+                name
+            )
+        };
+        assert!(parse_channels("{}").is_err());
+        assert!(parse_channels(&format!("[{},{}]", channel("stable"), channel("STABLE"))).is_err());
+        assert!(parse_channels(&format!("[{},{}]", channel("stable"), channel("a b"))).is_err());
+        assert!(parse_channels(r#"[{"name": "stable"}]"#).is_err());
+
+        let config = ChannelConfig {
+            name: String::from("a b"),
+            ..ChannelConfig::legacy("stable", "STABLE")
+        };
+        assert!(config.channel().is_err());
+    }
+}
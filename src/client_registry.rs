@@ -1,161 +1,1120 @@
-use gpapi::DownloadInfo;
+use futures::future::{join, join_all};
+use futures::lock::Mutex as AsyncMutex;
+use googleplay_protobuf::DetailsResponse;
+use gpapi::transport::Transport;
+use gpapi::{
+    Category, Chart, ChartOptions, DownloadInfo, Page, ReviewPage, ReviewSort, SearchOptions,
+    TestingProgramStatus,
+};
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use worker::{console_log, Env};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use worker::Env;
 
-use crate::google_play_client::{Channel, GooglePlayClient};
+use crate::config::{ChannelAccount, Config};
+use crate::details_cache::{
+    details_key, CachedDetails, DetailsCache, FetchedDetails, KvDetailsCache, MemoryDetailsCache,
+    DETAILS_KV_BINDING, STALE_DETAILS_SECS,
+};
+use crate::fetch_transport::FetchTransport;
+use crate::google_play_client::{Channel, GooglePlayClient, PlayError};
+use crate::runtime::{log, now_secs};
+use crate::session_store::{KvSessionStore, MemorySessionStore, SessionStore, SESSIONS_KV_BINDING};
+use crate::track_cache::{
+    has_own_track, KvTrackCache, MemoryTrackCache, TrackCache, TRACKS_KV_BINDING,
+};
+use crate::version_history::{
+    D1HistoryStore, HistoryStore, MemoryHistoryStore, VersionRecord, HISTORY_D1_BINDING,
+};
+
+/// A configured channel and its client. The lock is only held while the channel logs in, so
+/// concurrent requests share one login and never wait on other channels.
+struct ChannelSlot {
+    account: ChannelAccount,
+    client: AsyncMutex<Option<Arc<GooglePlayClient>>>,
+    /// Whether `client` runs on a session restored from the `SessionStore`.
+    restored: AtomicBool,
+}
+
+/// How many packages go into one `bulkDetails` request.
+pub const BULK_DETAILS_BATCH: usize = 50;
+
+/// The most packages one bulk details request may ask for.
+pub const MAX_BULK_PACKAGES: usize = 200;
 
 pub struct ClientRegistry {
-    clients: HashMap<Channel, GooglePlayClient>,
-    initialized: HashMap<Channel, bool>,
-    env: Env,
+    channels: Vec<ChannelSlot>,
+    sessions: Box<dyn SessionStore>,
+    tracks: Box<dyn TrackCache>,
+    details: Arc<dyn DetailsCache>,
+    details_ttl: u64,
+    history: Arc<dyn HistoryStore>,
+    /// What the clients send their requests through, `FetchTransport` on Workers.
+    transport: Arc<dyn Transport>,
 }
 
 impl ClientRegistry {
-    pub fn new(env: Env) -> Self {
+    pub fn new(
+        channels: Vec<ChannelAccount>,
+        sessions: Box<dyn SessionStore>,
+        tracks: Box<dyn TrackCache>,
+        details: Arc<dyn DetailsCache>,
+        details_ttl: u64,
+        history: Arc<dyn HistoryStore>,
+        transport: Arc<dyn Transport>,
+    ) -> Self {
         Self {
-            clients: HashMap::new(),
-            initialized: HashMap::new(),
-            env,
-        }
-    }
-
-    pub async fn get_client(&mut self, channel: Channel) -> Result<&GooglePlayClient, String> {
-        if !self.clients.contains_key(&channel) {
-            let device_name = self.env.var("DEVICE_NAME").unwrap().to_string();
-
-            let (email, aas_token) = match channel {
-                Channel::Stable => (
-                    self.env.var("STABLE_EMAIL").unwrap().to_string(),
-                    self.env.var("STABLE_AAS_TOKEN").unwrap().to_string(),
-                ),
-                Channel::Beta => (
-                    self.env.var("BETA_EMAIL").unwrap().to_string(),
-                    self.env.var("BETA_AAS_TOKEN").unwrap().to_string(),
-                ),
-                Channel::Alpha => (
-                    self.env.var("ALPHA_EMAIL").unwrap().to_string(),
-                    self.env.var("ALPHA_AAS_TOKEN").unwrap().to_string(),
-                ),
-            };
+            channels: channels
+                .into_iter()
+                .map(|account| ChannelSlot {
+                    account,
+                    client: AsyncMutex::new(None),
+                    restored: AtomicBool::new(false),
+                })
+                .collect(),
+            sessions,
+            tracks,
+            details,
+            details_ttl,
+            history,
+            transport,
+        }
+    }
+
+    /// How long details are served from the cache, in seconds. `0` when every request goes to
+    /// Google Play.
+    pub fn details_ttl(&self) -> u64 {
+        self.details_ttl
+    }
+
+    /// The configured channels other than stable, in configuration order.
+    pub fn testing_channels(&self) -> Vec<Channel> {
+        self.channels
+            .iter()
+            .map(|slot| slot.account.channel.clone())
+            .filter(|channel| !channel.is_stable())
+            .collect()
+    }
+
+    fn slot(&self, channel: &Channel) -> Result<&ChannelSlot, PlayError> {
+        self.channels
+            .iter()
+            .find(|slot| slot.account.channel == *channel)
+            .ok_or_else(|| PlayError::UnknownChannel {
+                channel: channel.clone(),
+            })
+    }
 
-            let client = GooglePlayClient::new(&device_name, &email, &aas_token, channel);
-            self.clients.insert(channel, client);
-            self.initialized.insert(channel, false);
+    pub async fn get_client(&self, channel: &Channel) -> Result<Arc<GooglePlayClient>, PlayError> {
+        let slot = self.slot(channel)?;
+        let mut current = slot.client.lock().await;
+        if let Some(client) = current.as_ref() {
+            return Ok(client.clone());
         }
 
-        if !self.initialized.get(&channel).unwrap_or(&false) {
-            let client = self.clients.get_mut(&channel).unwrap();
+        let account = &slot.account;
+        let mut client = GooglePlayClient::new(
+            &account.device,
+            &account.email,
+            &account.aas_token,
+            account.locale.as_deref(),
+            channel.clone(),
+            self.transport.clone(),
+        );
+        let restored = match self.sessions.load(channel).await {
+            Some(session) => match client.restore_session(session) {
+                Ok(()) => true,
+                Err(e) => {
+                    log!("Discarding stored {} session: {}", channel, e);
+                    false
+                }
+            },
+            None => false,
+        };
+        if !restored {
             client.initialize().await?;
-            self.initialized.insert(channel, true);
+            self.save_session(channel, &client).await;
         }
+        slot.restored.store(restored, Ordering::Relaxed);
 
-        Ok(self.clients.get(&channel).unwrap())
+        let client = Arc::new(client);
+        *current = Some(client.clone());
+        Ok(client)
     }
 
-    pub async fn get_details_with_fallback(
-        &mut self,
-        package_name: &str,
-        channel: Channel,
-    ) -> Result<Option<(Channel, googleplay_protobuf::DetailsResponse)>, String> {
-        if !channel.is_available_for_package(package_name) {
-            return Err(format!(
-                "Channel '{}' is not available for package '{}'",
-                channel, package_name
-            ));
+    async fn save_session(&self, channel: &Channel, client: &GooglePlayClient) {
+        if let Some(session) = client.session() {
+            if let Err(e) = self.sessions.save(channel, &session).await {
+                log!("Failed to store {} session: {}", channel, e);
+            }
+        }
+    }
+
+    /// Drop a restored session that Google rejected, so the next `get_client` logs in again.
+    /// Returns false if the error is not an auth failure or the client was not running on a
+    /// restored session.
+    async fn discard_restored_session(&self, channel: &Channel, error: &PlayError) -> bool {
+        let Ok(slot) = self.slot(channel) else {
+            return false;
+        };
+        if !error.is_auth_failure() || !slot.restored.swap(false, Ordering::Relaxed) {
+            return false;
+        }
+        log!(
+            "Stored {} session was rejected, logging in again: {}",
+            channel,
+            error
+        );
+        *slot.client.lock().await = None;
+        if let Err(e) = self.sessions.remove(channel).await {
+            log!("Failed to remove {} session: {}", channel, e);
+        }
+        true
+    }
+
+    /// Run `call` with the channel's client, logging in again and retrying once if it ran on a
+    /// stored session that Google rejected.
+    async fn with_client<T, F, Fut>(&self, channel: &Channel, call: F) -> Result<T, PlayError>
+    where
+        F: Fn(Arc<GooglePlayClient>) -> Fut,
+        Fut: Future<Output = Result<T, PlayError>>,
+    {
+        let result = self.call_client(channel, &call).await;
+        match result {
+            Err(e) if self.discard_restored_session(channel, &e).await => {
+                self.call_client(channel, &call).await
+            }
+            result => result,
         }
+    }
 
+    /// Run `call` with the channel's client. gpapi renews an expiring or rejected auth token on
+    /// its own; the renewed token is stored, so later requests do not renew it again.
+    async fn call_client<T, F, Fut>(&self, channel: &Channel, call: &F) -> Result<T, PlayError>
+    where
+        F: Fn(Arc<GooglePlayClient>) -> Fut,
+        Fut: Future<Output = Result<T, PlayError>>,
+    {
         let client = self.get_client(channel).await?;
-        match client.get_details(package_name).await {
-            Ok(Some(response)) => return Ok(Some((channel, response))),
-            Ok(None) => return Ok(None),
-            Err(e) => return Err(e),
+        let auth_token = client.auth_token();
+        let result = call(client.clone()).await;
+        if client.auth_token() != auth_token {
+            self.save_session(channel, &client).await;
         }
+        result
     }
 
-    pub async fn get_details_multi(
-        &mut self,
+    fn details_key(&self, package_name: &str, channel: &Channel) -> Result<String, PlayError> {
+        let account = &self.slot(channel)?.account;
+        // Listings are requested in gpapi's default locale unless the channel sets one
+        let locale = account.locale.as_deref().unwrap_or("en_US");
+        Ok(details_key(package_name, channel, &account.device, locale))
+    }
+
+    /// Details of `package_name` as the channel account sees them, served from the details
+    /// cache while younger than `details_ttl`. When Google Play fails, the last good copy is
+    /// served instead, marked stale. Cached copies are not recorded again, since
+    /// `refresh_details` recorded their version when it cached them.
+    async fn fetch_details(
+        &self,
         package_name: &str,
-    ) -> Result<HashMap<Channel, googleplay_protobuf::DetailsResponse>, String> {
-        let mut results = HashMap::new();
+        channel: &Channel,
+    ) -> Result<Option<FetchedDetails>, PlayError> {
+        let key = self.details_key(package_name, channel)?;
+        let now = now_secs();
+        let cached = self.details.get(&key).await;
+        if let Some(cached) = &cached {
+            if now.saturating_sub(cached.fetched_at) < self.details_ttl {
+                return Ok(Some(FetchedDetails::fresh(cached.details.clone())));
+            }
+        }
 
-        match self
-            .get_details_with_fallback(package_name, Channel::Stable)
-            .await
-        {
-            Ok(Some((_, response))) => {
-                results.insert(Channel::Stable, response);
+        match self.refresh_details(package_name, channel).await {
+            Ok(details) => Ok(details.map(FetchedDetails::fresh)),
+            Err(e) if e.is_upstream_failure() => match cached {
+                Some(cached) => {
+                    log!(
+                        "Serving stale {} details of {}: {}",
+                        channel,
+                        package_name,
+                        e
+                    );
+                    Ok(Some(FetchedDetails {
+                        details: cached.details,
+                        stale_age: Some(now.saturating_sub(cached.fetched_at)),
+                    }))
+                }
+                None => Err(e),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Fetch details from Google Play, bypassing the cache, and keep them as the last good
+    /// copy. The version they describe is added to the history. Run in the background after a
+    /// stale copy was served.
+    pub async fn refresh_details(
+        &self,
+        package_name: &str,
+        channel: &Channel,
+    ) -> Result<Option<DetailsResponse>, PlayError> {
+        let key = self.details_key(package_name, channel)?;
+        let details = self
+            .with_client(channel, |client| async move {
+                client.get_details(package_name).await
+            })
+            .await?;
+        if let Some(details) = &details {
+            let entry = CachedDetails {
+                details: details.clone(),
+                fetched_at: now_secs(),
+            };
+            // Kept well past `details_ttl`, as the fallback while Google Play is failing
+            let (cached, ()) = join(
+                self.details.put(&key, &entry, STALE_DETAILS_SECS),
+                self.record_version(package_name, channel, &entry),
+            )
+            .await;
+            if let Err(e) = cached {
+                log!(
+                    "Failed to cache {} details of {}: {}",
+                    channel,
+                    package_name,
+                    e
+                );
             }
+        }
+        Ok(details)
+    }
+
+    /// Add the version of `entry` to the history, as seen when it was fetched. The history
+    /// keeps the first sighting, so recording a known version again changes nothing.
+    async fn record_version(&self, package_name: &str, channel: &Channel, entry: &CachedDetails) {
+        let Some(record) =
+            VersionRecord::from_details(package_name, channel, &entry.details, entry.fetched_at)
+        else {
+            return;
+        };
+        if let Err(e) = self.history.record(&record).await {
+            log!(
+                "Failed to record {} version of {}: {}",
+                channel,
+                package_name,
+                e
+            );
+        }
+    }
+
+    /// Every version of `package_name` seen so far, newest first, on one channel or on all.
+    pub async fn version_history(
+        &self,
+        package_name: &str,
+        channel: Option<&Channel>,
+    ) -> Result<Vec<VersionRecord>, String> {
+        self.history.history(package_name, channel).await
+    }
+
+    async fn remember_track(&self, package_name: &str, channel: &Channel, available: bool) {
+        if let Err(e) = self.tracks.put(package_name, channel, available).await {
+            log!(
+                "Failed to cache {} track of {}: {}",
+                channel,
+                package_name,
+                e
+            );
+        }
+    }
+
+    /// Whether `channel` carries its own track for `package_name`. Stable always does; for the
+    /// testing channels this is detected from what the channel account is served, and cached.
+    /// When the channel's details had to be fetched to find out, they are returned too.
+    pub async fn is_channel_available(
+        &self,
+        package_name: &str,
+        channel: &Channel,
+    ) -> Result<(bool, Option<FetchedDetails>), PlayError> {
+        if channel.is_stable() {
+            return Ok((true, None));
+        }
+        self.slot(channel)?;
+        if let Some(available) = self.tracks.get(package_name, channel).await {
+            return Ok((available, None));
+        }
+        let (stable, details) = join(
+            self.fetch_details(package_name, &Channel::stable()),
+            self.fetch_details(package_name, channel),
+        )
+        .await;
+        let (stable, details) = (stable?, details?);
+        let available = match (&stable, &details) {
+            (Some(stable), Some(details)) => has_own_track(&stable.details, &details.details),
+            _ => false,
+        };
+        // A verdict from stale copies is good for this request only
+        let stale = [&stable, &details]
+            .iter()
+            .any(|fetched| fetched.as_ref().is_some_and(FetchedDetails::is_stale));
+        if !stale {
+            self.remember_track(package_name, channel, available).await;
+        }
+        Ok((available, details))
+    }
+
+    /// Fail with `ChannelUnavailable` unless `channel` has its own track, passing on the
+    /// channel's details if they were fetched to check.
+    async fn ensure_channel_available(
+        &self,
+        package_name: &str,
+        channel: &Channel,
+    ) -> Result<Option<FetchedDetails>, PlayError> {
+        match self.is_channel_available(package_name, channel).await? {
+            (true, details) => Ok(details),
+            (false, _) => Err(PlayError::ChannelUnavailable {
+                channel: channel.clone(),
+                package_name: package_name.to_string(),
+            }),
+        }
+    }
+
+    pub async fn get_details_with_fallback(
+        &self,
+        package_name: &str,
+        channel: &Channel,
+    ) -> Result<Option<(Channel, FetchedDetails)>, PlayError> {
+        let details = match self.ensure_channel_available(package_name, channel).await? {
+            Some(details) => Some(details),
+            None => self.fetch_details(package_name, channel).await?,
+        };
+        Ok(details.map(|details| (channel.clone(), details)))
+    }
+
+    /// Details from every channel with its own track. All channels are queried at once, so
+    /// this costs a single round trip to Google Play once the clients are logged in.
+    pub async fn get_details_multi(
+        &self,
+        package_name: &str,
+    ) -> Result<HashMap<Channel, FetchedDetails>, PlayError> {
+        let stable_channel = Channel::stable();
+        let testing_channels = self.testing_channels();
+
+        // Channels known not to have a track are skipped, the others are detected against the
+        // stable details once everything arrived
+        let known = join_all(
+            testing_channels
+                .iter()
+                .map(|channel| self.tracks.get(package_name, channel)),
+        )
+        .await;
+        let candidates: Vec<(&Channel, Option<bool>)> = testing_channels
+            .iter()
+            .zip(known)
+            .filter(|(_, known)| *known != Some(false))
+            .collect();
+        let (stable, fetched) = join(
+            self.fetch_details(package_name, &stable_channel),
+            join_all(
+                candidates
+                    .iter()
+                    .map(|(channel, _)| self.fetch_details(package_name, channel)),
+            ),
+        )
+        .await;
+
+        let stable = match stable {
+            Ok(Some(response)) => response,
             Ok(None) => {
-                return Err(format!("App '{}' not found", package_name));
+                return Err(PlayError::AppNotFound {
+                    package_name: package_name.to_string(),
+                });
             }
             Err(e) => {
-                console_log!("Error fetching {} for stable channel: {}", package_name, e);
+                log!("Error fetching {} for stable channel: {}", package_name, e);
                 return Err(e);
             }
-        }
+        };
 
-        if Channel::Beta.is_available_for_package(package_name) {
-            match self
-                .get_client(Channel::Beta)
-                .await?
-                .get_details(package_name)
-                .await
-            {
-                Ok(Some(response)) => {
-                    results.insert(Channel::Beta, response);
+        let mut results = HashMap::new();
+        let mut detected = Vec::new();
+        for ((channel, known), result) in candidates.into_iter().zip(fetched) {
+            match result {
+                Ok(details) => {
+                    let available = match (known, &details) {
+                        (Some(available), _) => available,
+                        (None, Some(details)) => {
+                            let available = has_own_track(&stable.details, &details.details);
+                            if !stable.is_stale() && !details.is_stale() {
+                                detected.push((channel, available));
+                            }
+                            available
+                        }
+                        (None, None) => {
+                            detected.push((channel, false));
+                            false
+                        }
+                    };
+                    if let (true, Some(details)) = (available, details) {
+                        results.insert(channel.clone(), details);
+                    }
                 }
                 Err(e) => {
-                    console_log!("Error fetching {} for beta channel: {}", package_name, e);
+                    log!(
+                        "Error fetching {} for {} channel: {}",
+                        package_name,
+                        channel,
+                        e
+                    );
                 }
-                _ => {}
             }
         }
+        join_all(
+            detected
+                .into_iter()
+                .map(|(channel, available)| self.remember_track(package_name, channel, available)),
+        )
+        .await;
+        results.insert(stable_channel, stable);
 
-        if Channel::Alpha.is_available_for_package(package_name) {
-            match self
-                .get_client(Channel::Alpha)
-                .await?
-                .get_details(package_name)
-                .await
-            {
-                Ok(Some(response)) => {
-                    results.insert(Channel::Alpha, response);
-                }
+        Ok(results)
+    }
+
+    /// Details of many packages as the channel account sees them, fetched with concurrent
+    /// `bulkDetails` requests of `BULK_DETAILS_BATCH` packages each. Unlike
+    /// `get_details_with_fallback`, the channel's track is not checked and the details cache is
+    /// bypassed. A failed batch fails only its own packages.
+    pub async fn get_bulk_details(
+        &self,
+        package_names: &[String],
+        channel: &Channel,
+    ) -> Result<HashMap<String, Result<DetailsResponse, String>>, PlayError> {
+        self.slot(channel)?;
+        let batches = package_names
+            .chunks(BULK_DETAILS_BATCH)
+            .map(|batch| async move {
+                let ids: Vec<&str> = batch.iter().map(String::as_str).collect();
+                let items = self
+                    .with_client(channel, |client| {
+                        let ids = ids.clone();
+                        async move { client.get_bulk_details(&ids).await }
+                    })
+                    .await;
+                (batch, items)
+            });
+
+        let mut results = HashMap::new();
+        for (batch, items) in join_all(batches).await {
+            let mut items = match items {
+                Ok(items) => items,
                 Err(e) => {
-                    console_log!("Error fetching {} for alpha channel: {}", package_name, e);
+                    log!("Failed to fetch {} bulk details: {}", channel, e);
+                    for package_name in batch {
+                        results.insert(package_name.clone(), Err(e.to_string()));
+                    }
+                    continue;
                 }
-                _ => {}
+            };
+            for package_name in batch {
+                let position = items
+                    .iter()
+                    .position(|item| item.id.as_deref() == Some(package_name.as_str()));
+                let result = match position {
+                    Some(position) => Ok(DetailsResponse {
+                        item: Some(items.swap_remove(position)),
+                        ..Default::default()
+                    }),
+                    None => Err(format!("App '{}' not found", package_name)),
+                };
+                results.insert(package_name.clone(), result);
             }
         }
-
         Ok(results)
     }
 
     pub async fn get_download_info(
-        &mut self,
+        &self,
         package_name: &str,
-        channel: Channel,
+        channel: &Channel,
         version_code: Option<i32>,
-    ) -> Result<Option<(Channel, DownloadInfo)>, String> {
-        if !channel.is_available_for_package(package_name) {
-            return Err(format!(
-                "Channel '{}' is not available for package '{}'",
-                channel, package_name
-            ));
-        }
+    ) -> Result<Option<(Channel, DownloadInfo)>, PlayError> {
+        self.ensure_channel_available(package_name, channel).await?;
 
-        let client = self.get_client(channel).await?;
-        match client.get_download_info(package_name, version_code).await {
-            Ok(download_info) => Ok(Some((channel, download_info))),
-            Err(e) => Err(e),
+        let download_info = self
+            .with_client(channel, |client| async move {
+                client.get_download_info(package_name, version_code).await
+            })
+            .await?;
+        Ok(Some((channel.clone(), download_info)))
+    }
+
+    pub async fn get_reviews(
+        &self,
+        package_name: &str,
+        channel: &Channel,
+        sort: ReviewSort,
+        filter_by_device: bool,
+        rating: Option<u8>,
+        offset: usize,
+    ) -> Result<ReviewPage, PlayError> {
+        self.ensure_channel_available(package_name, channel).await?;
+
+        self.with_client(channel, |client| async move {
+            client
+                .get_reviews(package_name, sort, filter_by_device, rating, offset)
+                .await
+        })
+        .await
+    }
+
+    pub async fn testing_program_status(
+        &self,
+        package_name: &str,
+        channel: &Channel,
+    ) -> Result<TestingProgramStatus, PlayError> {
+        self.with_client(channel, |client| async move {
+            client.testing_program_status(package_name).await
+        })
+        .await
+    }
+
+    /// Enroll the channel's account in the package's testing program, or take it out. The
+    /// detected track and cached details are forgotten so the next request checks the channel
+    /// again.
+    pub async fn set_testing_program(
+        &self,
+        package_name: &str,
+        channel: &Channel,
+        subscribe: bool,
+    ) -> Result<bool, PlayError> {
+        let result = self
+            .with_client(channel, |client| async move {
+                client.set_testing_program(package_name, subscribe).await
+            })
+            .await;
+        if let Err(e) = self.tracks.remove(package_name, channel).await {
+            log!(
+                "Failed to forget {} track of {}: {}",
+                channel,
+                package_name,
+                e
+            );
+        }
+        // The details carry the enrollment too
+        if let Err(e) = self
+            .details
+            .remove(&self.details_key(package_name, channel)?)
+            .await
+        {
+            log!(
+                "Failed to forget {} details of {}: {}",
+                channel,
+                package_name,
+                e
+            );
         }
+        result
+    }
+
+    /// Search, categories and charts are not tied to a release channel, so they always go
+    /// through the stable client.
+    pub async fn search(&self, query: &str, options: SearchOptions) -> Result<Page, PlayError> {
+        self.with_client(&Channel::stable(), |client| {
+            let options = options.clone();
+            async move { client.search(query, options).await }
+        })
+        .await
+    }
+
+    pub async fn categories(&self) -> Result<Vec<Category>, PlayError> {
+        self.with_client(&Channel::stable(), |client| async move {
+            client.categories().await
+        })
+        .await
     }
+
+    pub async fn top_chart(&self, chart: Chart, options: ChartOptions) -> Result<Page, PlayError> {
+        self.with_client(&Channel::stable(), |client| {
+            let options = options.clone();
+            async move { client.top_chart(chart, options).await }
+        })
+        .await
+    }
+}
+
+pub type SharedClientRegistry = Arc<ClientRegistry>;
+
+pub async fn create_registry(env: &Env, config: &Config) -> SharedClientRegistry {
+    let sessions: Box<dyn SessionStore> = match env.kv(SESSIONS_KV_BINDING) {
+        Ok(kv) => Box::new(KvSessionStore::new(kv)),
+        Err(_) => {
+            log!(
+                "No '{}' KV namespace bound, sessions will not be persisted",
+                SESSIONS_KV_BINDING
+            );
+            Box::new(MemorySessionStore::default())
+        }
+    };
+    let tracks: Box<dyn TrackCache> = match env.kv(TRACKS_KV_BINDING) {
+        Ok(kv) => Box::new(KvTrackCache::new(kv)),
+        Err(_) => {
+            log!(
+                "No '{}' KV namespace bound, detected tracks will not be persisted",
+                TRACKS_KV_BINDING
+            );
+            Box::new(MemoryTrackCache::default())
+        }
+    };
+    let details: Arc<dyn DetailsCache> = match env.kv(DETAILS_KV_BINDING) {
+        Ok(kv) => Arc::new(KvDetailsCache::new(kv)),
+        Err(_) => MemoryDetailsCache::shared(),
+    };
+    let history: Arc<dyn HistoryStore> = match env.d1(HISTORY_D1_BINDING) {
+        Ok(db) => Arc::new(D1HistoryStore::new(db)),
+        Err(_) => {
+            log!(
+                "No '{}' D1 database bound, version history is only kept by this isolate",
+                HISTORY_D1_BINDING
+            );
+            MemoryHistoryStore::shared()
+        }
+    };
+    Arc::new(ClientRegistry::new(
+        config.channels.clone(),
+        sessions,
+        tracks,
+        details,
+        config.details_ttl,
+        history,
+        Arc::new(FetchTransport),
+    ))
 }
 
-pub type SharedClientRegistry = Arc<Mutex<ClientRegistry>>;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use googleplay_protobuf::{
+        AppDetails, BulkDetailsEntry, BulkDetailsRequest, BulkDetailsResponse, DocumentDetails,
+        Item, Payload, ResponseWrapper,
+    };
+    use gpapi::transport::{HttpRequest, HttpResponse};
+    use gpapi::Session;
+    use prost::Message;
+    use std::fmt;
+    use std::sync::Mutex;
+    use std::thread;
+    use worker::async_trait::async_trait;
+
+    type Handler = Box<dyn Fn(&HttpRequest) -> HttpResponse + Send + Sync>;
+
+    /// Run a test on a thread with a larger stack, since the registry's futures outgrow the
+    /// 2 MiB of a test thread in debug builds.
+    fn run(test: impl FnOnce() + Send + 'static) {
+        thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(test)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    /// A stand-in for Google Play that answers every request through `handler` and records the
+    /// URLs it was sent.
+    struct FakePlay {
+        handler: Handler,
+        urls: Mutex<Vec<String>>,
+    }
+
+    impl FakePlay {
+        fn new<F>(handler: F) -> Arc<Self>
+        where
+            F: Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+        {
+            Arc::new(FakePlay {
+                handler: Box::new(handler),
+                urls: Mutex::new(Vec::new()),
+            })
+        }
+
+        fn urls(&self) -> Vec<String> {
+            self.urls.lock().unwrap().clone()
+        }
+    }
+
+    impl fmt::Debug for FakePlay {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_struct("FakePlay").finish()
+        }
+    }
+
+    #[async_trait]
+    impl Transport for FakePlay {
+        async fn send(
+            &self,
+            req: HttpRequest,
+        ) -> Result<HttpResponse, Box<dyn std::error::Error + Send + Sync>> {
+            self.urls.lock().unwrap().push(req.url.clone());
+            Ok((self.handler)(&req))
+        }
+    }
+
+    fn respond(status: u16, body: Vec<u8>) -> HttpResponse {
+        HttpResponse {
+            status,
+            headers: HashMap::new(),
+            body: body.into(),
+        }
+    }
+
+    fn authorized(req: &HttpRequest, auth_token: &str) -> bool {
+        let expected = format!("Bearer {}", auth_token);
+        req.headers
+            .iter()
+            .any(|(name, value)| name == "Authorization" && *value == expected)
+    }
+
+    fn details(version_code: i32) -> DetailsResponse {
+        DetailsResponse {
+            item: Some(Item {
+                details: Some(DocumentDetails {
+                    app_details: Some(AppDetails {
+                        version_code: Some(version_code),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn details_reply(version_code: i32) -> HttpResponse {
+        let wrapper = ResponseWrapper {
+            payload: Some(Payload {
+                details_response: Some(details(version_code)),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        respond(200, wrapper.encode_to_vec())
+    }
+
+    /// An answer to a `bulkDetails` request with an item for every requested package but `missing`.
+    fn bulk_details_reply(req: &HttpRequest, missing: &str) -> HttpResponse {
+        let entry = requested_packages(req)
+            .into_iter()
+            .filter(|id| id != missing)
+            .map(|id| BulkDetailsEntry {
+                item: Some(Item {
+                    id: Some(id),
+                    ..Default::default()
+                }),
+            })
+            .collect();
+        let wrapper = ResponseWrapper {
+            payload: Some(Payload {
+                bulk_details_response: Some(BulkDetailsResponse { entry }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        respond(200, wrapper.encode_to_vec())
+    }
+
+    fn account(channel: &str) -> ChannelAccount {
+        ChannelAccount {
+            channel: Channel::from_str(channel).unwrap(),
+            email: String::from("user@example.com"),
+            aas_token: String::from("aas_et/token"),
+            device: String::from("px_7a"),
+            locale: None,
+            email_secret: String::from("EMAIL"),
+            token_secret: String::from("AAS_TOKEN"),
+        }
+    }
+
+    /// Sessions for the given channels and auth tokens, so the registry never logs in.
+    fn sessions(tokens: &[(&str, &str)]) -> MemorySessionStore {
+        let sessions = MemorySessionStore::default();
+        for &(channel, auth_token) in tokens {
+            let session = Session {
+                version: Session::VERSION,
+                email: String::from("user@example.com"),
+                auth_token: String::from(auth_token),
+                auth_token_expiry: None,
+                gsf_id: 1,
+                device_config_token: None,
+                device_checkin_consistency_token: None,
+                dfe_cookie: None,
+            };
+            block_on(sessions.save(&Channel::from_str(channel).unwrap(), &session)).unwrap();
+        }
+        sessions
+    }
+
+    /// What a registry keeps between requests. Like on Workers, it outlives the registry.
+    #[derive(Clone, Default)]
+    struct Stores {
+        details: Arc<MemoryDetailsCache>,
+        history: Arc<MemoryHistoryStore>,
+    }
+
+    /// A registry for stable and beta that always asks Google Play for details.
+    fn registry(
+        play: Arc<FakePlay>,
+        sessions: MemorySessionStore,
+        stores: &Stores,
+    ) -> ClientRegistry {
+        registry_with_ttl(play, sessions, stores, 0)
+    }
+
+    fn registry_with_ttl(
+        play: Arc<FakePlay>,
+        sessions: MemorySessionStore,
+        stores: &Stores,
+        details_ttl: u64,
+    ) -> ClientRegistry {
+        ClientRegistry::new(
+            vec![account("stable"), account("beta")],
+            Box::new(sessions),
+            Box::new(MemoryTrackCache::default()),
+            stores.details.clone(),
+            details_ttl,
+            stores.history.clone(),
+            play,
+        )
+    }
+
+    #[test]
+    fn stores_renewed_tokens() {
+        run(|| {
+            // Only a renewed token is accepted
+            let play = FakePlay::new(|req| {
+                if req.url.contains("/auth?") {
+                    return respond(200, b"Auth=fresh\nExpiry=4102444800\n".to_vec());
+                }
+                if !authorized(req, "fresh") {
+                    return respond(401, Vec::new());
+                }
+                details_reply(100)
+            });
+            let sessions = sessions(&[("stable", "stale"), ("beta", "stale")]);
+            let registry = registry(play.clone(), sessions, &Stores::default());
+            let stable = Channel::stable();
+
+            block_on(async {
+                let (_, fetched) = registry
+                    .get_details_with_fallback("com.example", &stable)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(fetched, FetchedDetails::fresh(details(100)));
+                let session = registry.sessions.load(&stable).await.unwrap();
+                assert_eq!(session.auth_token, "fresh");
+                assert_eq!(session.auth_token_expiry, Some(4102444800));
+            });
+            assert_eq!(play.urls().len(), 3);
+        });
+    }
+
+    #[test]
+    fn fetches_detected_details_once() {
+        run(|| {
+            // Beta is served a newer version, so it has its own track
+            let play = FakePlay::new(|req| match authorized(req, "beta") {
+                true => details_reply(101),
+                false => details_reply(100),
+            });
+            let sessions = sessions(&[("stable", "stable"), ("beta", "beta")]);
+            let registry = registry(play.clone(), sessions, &Stores::default());
+            let beta = Channel::from_str("beta").unwrap();
+
+            let (channel, fetched) =
+                block_on(registry.get_details_with_fallback("com.example", &beta))
+                    .unwrap()
+                    .unwrap();
+            assert_eq!(channel, beta);
+            assert_eq!(fetched, FetchedDetails::fresh(details(101)));
+            let details_requests = play
+                .urls()
+                .iter()
+                .filter(|url| url.contains("/details?"))
+                .count();
+            assert_eq!(details_requests, 2);
+        });
+    }
+
+    #[test]
+    fn serves_stale_details_from_earlier_requests() {
+        run(|| {
+            let stores = Stores::default();
+            let stable = Channel::stable();
+            let fetch = |play: Arc<FakePlay>| {
+                let sessions = sessions(&[("stable", "token")]);
+                let registry = registry(play, sessions, &stores);
+                block_on(registry.get_details_with_fallback("com.example", &stable))
+            };
+
+            let (_, fetched) = fetch(FakePlay::new(|_| details_reply(100)))
+                .unwrap()
+                .unwrap();
+            assert!(!fetched.is_stale());
+
+            // A later request, with a registry of its own, while Google Play is down
+            let (_, fetched) = fetch(FakePlay::new(|_| respond(503, Vec::new())))
+                .unwrap()
+                .unwrap();
+            assert_eq!(fetched.details, details(100));
+            assert!(fetched.is_stale());
+
+            // Nothing to fall back on in a cache that never saw the app
+            let registry = registry(
+                FakePlay::new(|_| respond(503, Vec::new())),
+                sessions(&[("stable", "token")]),
+                &Stores::default(),
+            );
+            let fetched = block_on(registry.get_details_with_fallback("com.example", &stable));
+            assert!(fetched.is_err());
+        });
+    }
+
+    #[test]
+    fn records_versions_when_caching_details() {
+        run(|| {
+            let stores = Stores::default();
+            let stable = Channel::stable();
+            let history = |registry: &ClientRegistry| {
+                let history = block_on(registry.version_history("com.example", None)).unwrap();
+                history
+                    .iter()
+                    .map(|record| record.version_code)
+                    .collect::<Vec<_>>()
+            };
+
+            // Recorded when fetched and cached
+            let caching = registry_with_ttl(
+                FakePlay::new(|_| details_reply(100)),
+                sessions(&[("stable", "token")]),
+                &stores,
+                u64::MAX,
+            );
+            block_on(caching.get_details_with_fallback("com.example", &stable)).unwrap();
+            assert_eq!(history(&caching), [100]);
+
+            // Not again when served from the cache, or stale while Google Play is down
+            let stores = Stores {
+                history: Default::default(),
+                ..stores
+            };
+            for details_ttl in [u64::MAX, 0] {
+                let registry = registry_with_ttl(
+                    FakePlay::new(|_| respond(503, Vec::new())),
+                    sessions(&[("stable", "token")]),
+                    &stores,
+                    details_ttl,
+                );
+                let (_, fetched) =
+                    block_on(registry.get_details_with_fallback("com.example", &stable))
+                        .unwrap()
+                        .unwrap();
+                assert_eq!(fetched.details, details(100));
+                assert!(history(&registry).is_empty());
+            }
+        });
+    }
+
+    /// Bulk details of `com.example.0` to `com.example.{count - 1}` on stable.
+    fn bulk_details(
+        play: Arc<FakePlay>,
+        count: usize,
+    ) -> (
+        Vec<String>,
+        HashMap<String, Result<DetailsResponse, String>>,
+    ) {
+        let sessions = sessions(&[("stable", "token")]);
+        let registry = registry(play, sessions, &Stores::default());
+        let package_names: Vec<String> = (0..count).map(|i| format!("com.example.{}", i)).collect();
+        let results =
+            block_on(registry.get_bulk_details(&package_names, &Channel::stable())).unwrap();
+        (package_names, results)
+    }
+
+    fn requested_packages(req: &HttpRequest) -> Vec<String> {
+        BulkDetailsRequest::decode(req.body.as_deref().unwrap())
+            .unwrap()
+            .doc_id
+    }
 
-pub async fn create_registry(env: Env) -> SharedClientRegistry {
-    let registry = ClientRegistry::new(env);
-    Arc::new(Mutex::new(registry))
+    #[test]
+    fn splits_bulk_details_into_batches() {
+        run(|| {
+            let batches = Arc::new(Mutex::new(Vec::new()));
+            let play = FakePlay::new({
+                let batches = batches.clone();
+                move |req| {
+                    batches.lock().unwrap().push(requested_packages(req));
+                    bulk_details_reply(req, "")
+                }
+            });
+
+            let (package_names, results) = bulk_details(play, 120);
+            let mut batches = batches.lock().unwrap().clone();
+            batches.sort_by_key(|batch| batch.len());
+            assert_eq!(
+                batches.iter().map(Vec::len).collect::<Vec<_>>(),
+                [20, BULK_DETAILS_BATCH, BULK_DETAILS_BATCH]
+            );
+            let mut requested = batches.concat();
+            requested.sort();
+            let mut expected = package_names.clone();
+            expected.sort();
+            assert_eq!(requested, expected);
+            for package_name in &package_names {
+                let item = results[package_name]
+                    .as_ref()
+                    .unwrap()
+                    .item
+                    .as_ref()
+                    .unwrap();
+                assert_eq!(item.id.as_deref(), Some(package_name.as_str()));
+            }
+        });
+    }
+
+    #[test]
+    fn fails_only_the_packages_of_a_failed_batch() {
+        run(|| {
+            let play = FakePlay::new(|req| {
+                match requested_packages(req)
+                    .iter()
+                    .any(|id| id == "com.example.50")
+                {
+                    true => respond(503, Vec::new()),
+                    false => bulk_details_reply(req, ""),
+                }
+            });
+
+            let (package_names, results) = bulk_details(play, 120);
+            assert_eq!(results.len(), package_names.len());
+            for (i, package_name) in package_names.iter().enumerate() {
+                let failed = (BULK_DETAILS_BATCH..2 * BULK_DETAILS_BATCH).contains(&i);
+                assert_eq!(results[package_name].is_err(), failed, "{}", package_name);
+            }
+        });
+    }
+
+    #[test]
+    fn reports_packages_missing_from_bulk_details() {
+        run(|| {
+            let play = FakePlay::new(|req| bulk_details_reply(req, "com.example.1"));
+
+            let (_, results) = bulk_details(play, 3);
+            assert!(results["com.example.0"].is_ok());
+            assert_eq!(
+                results["com.example.1"].as_ref().unwrap_err(),
+                "App 'com.example.1' not found"
+            );
+            assert!(results["com.example.2"].is_ok());
+        });
+    }
 }
@@ -0,0 +1,280 @@
+use std::fmt;
+use std::sync::OnceLock;
+use worker::Env;
+
+use crate::channel_config::{default_channels, parse_channels, CHANNELS_VAR};
+use crate::details_cache::{DEFAULT_DETAILS_TTL_SECS, DETAILS_TTL_VAR, MIN_DETAILS_TTL_SECS};
+use crate::google_play_client::Channel;
+
+/// Secret that enables the admin routes, see `handlers::check_admin`.
+pub const ADMIN_TOKEN_SECRET: &str = "ADMIN_TOKEN";
+
+const DEFAULT_BRAND_NAME: &str = "Sniff";
+
+/// Everything the Worker needs from its environment, read and validated up front so a missing
+/// variable is reported instead of panicking in the middle of a request.
+#[derive(Clone)]
+pub struct Config {
+    /// Prefix of downloaded APK filenames.
+    pub brand_name: String,
+    pub channels: Vec<ChannelAccount>,
+    /// How long details are served from the cache, in seconds. `0` only keeps the fallback.
+    pub details_ttl: u64,
+}
+
+/// A channel with its account resolved. Deliberately not `Debug`, so the token cannot end up
+/// in a log line.
+#[derive(Clone)]
+pub struct ChannelAccount {
+    pub channel: Channel,
+    pub email: String,
+    pub aas_token: String,
+    pub device: String,
+    pub locale: Option<String>,
+    /// Names of the secrets the email and token were read from, for diagnostics.
+    pub email_secret: String,
+    pub token_secret: String,
+}
+
+/// Every problem found while loading the `Config`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration: {}", self.problems.join("; "))
+    }
+}
+
+/// Where configuration values are read from. Implemented by `Env`, and by maps in tests.
+pub trait ConfigSource {
+    /// A `[vars]` entry or a secret.
+    fn var(&self, name: &str) -> Option<String>;
+    /// A secret set with `wrangler secret put`.
+    fn secret(&self, name: &str) -> Option<String>;
+}
+
+impl ConfigSource for Env {
+    fn var(&self, name: &str) -> Option<String> {
+        Env::var(self, name).ok().map(|value| value.to_string())
+    }
+
+    fn secret(&self, name: &str) -> Option<String> {
+        Env::secret(self, name).ok().map(|value| value.to_string())
+    }
+}
+
+/// Device codenames gpapi knows, decoded once per isolate.
+fn known_devices() -> &'static [String] {
+    static DEVICES: OnceLock<Vec<String>> = OnceLock::new();
+    DEVICES.get_or_init(gpapi::device_codenames)
+}
+
+/// The token guarding the admin routes, if set. Read apart from `Config` so the config check
+/// can be authenticated while the rest of the configuration is broken.
+pub fn admin_token(env: &Env) -> Option<String> {
+    ConfigSource::secret(env, ADMIN_TOKEN_SECRET).filter(|token| !token.is_empty())
+}
+
+impl Config {
+    pub fn from_env(env: &Env) -> Result<Config, ConfigError> {
+        Config::load(env, known_devices())
+    }
+
+    pub fn load(
+        source: &dyn ConfigSource,
+        known_devices: &[String],
+    ) -> Result<Config, ConfigError> {
+        let mut problems = Vec::new();
+        let check_device = |device: &str, origin: &str, problems: &mut Vec<String>| {
+            if known_devices
+                .binary_search_by(|known| known.as_str().cmp(device))
+                .is_err()
+            {
+                problems.push(format!(
+                    "{} '{}' is not a known device codename",
+                    origin, device
+                ));
+            }
+        };
+
+        let device_name = match source.var("DEVICE_NAME") {
+            Some(device_name) => {
+                check_device(&device_name, "DEVICE_NAME", &mut problems);
+                device_name
+            }
+            None => {
+                problems.push(String::from("DEVICE_NAME is not set"));
+                String::new()
+            }
+        };
+        let brand_name = source
+            .var("BRAND_NAME")
+            .unwrap_or_else(|| String::from(DEFAULT_BRAND_NAME));
+        let details_ttl = match source.var(DETAILS_TTL_VAR).map(|ttl| ttl.parse::<u64>()) {
+            None => DEFAULT_DETAILS_TTL_SECS,
+            Some(Ok(ttl)) if ttl == 0 || ttl >= MIN_DETAILS_TTL_SECS => ttl,
+            Some(_) => {
+                problems.push(format!(
+                    "{} must be 0 (disabled) or at least {} seconds",
+                    DETAILS_TTL_VAR, MIN_DETAILS_TTL_SECS
+                ));
+                0
+            }
+        };
+
+        let channel_configs = match source.var(CHANNELS_VAR) {
+            Some(json) => parse_channels(&json).unwrap_or_else(|e| {
+                problems.push(e);
+                Vec::new()
+            }),
+            None => default_channels(),
+        };
+
+        let mut channels = Vec::new();
+        for config in channel_configs {
+            let channel = match config.channel() {
+                Ok(channel) => channel,
+                Err(e) => {
+                    problems.extend(e.problems);
+                    continue;
+                }
+            };
+            if let Some(device) = &config.device {
+                check_device(
+                    device,
+                    &format!("Device of channel '{}'", channel),
+                    &mut problems,
+                );
+            }
+            if let Some(locale) = config.locale.as_deref().filter(|locale| !is_locale(locale)) {
+                problems.push(format!(
+                    "Locale '{}' of channel '{}' is not like 'en_US'",
+                    locale, channel
+                ));
+            }
+            let email = source.var(&config.email_secret).unwrap_or_else(|| {
+                problems.push(format!(
+                    "{} (email of channel '{}') is not set",
+                    config.email_secret, channel
+                ));
+                String::new()
+            });
+            let aas_token = source.secret(&config.token_secret).unwrap_or_else(|| {
+                problems.push(format!(
+                    "{} (AAS token of channel '{}') is not set, add it with `wrangler secret put {}`",
+                    config.token_secret, channel, config.token_secret
+                ));
+                String::new()
+            });
+            channels.push(ChannelAccount {
+                channel,
+                email,
+                aas_token,
+                device: config.device.unwrap_or_else(|| device_name.clone()),
+                locale: config.locale,
+                email_secret: config.email_secret,
+                token_secret: config.token_secret,
+            });
+        }
+
+        if !problems.is_empty() {
+            return Err(ConfigError { problems });
+        }
+        Ok(Config {
+            brand_name,
+            channels,
+            details_ttl,
+        })
+    }
+}
+
+fn is_locale(locale: &str) -> bool {
+    locale
+        .split('_')
+        .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Like on Workers, vars and secrets share one namespace.
+    #[derive(Default)]
+    struct MapSource(HashMap<String, String>);
+
+    impl MapSource {
+        fn set(&mut self, name: &str, value: &str) {
+            self.0.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    impl ConfigSource for MapSource {
+        fn var(&self, name: &str) -> Option<String> {
+            self.0.get(name).cloned()
+        }
+
+        fn secret(&self, name: &str) -> Option<String> {
+            self.0.get(name).cloned()
+        }
+    }
+
+    fn devices() -> Vec<String> {
+        vec![String::from("px_7a"), String::from("px_9a")]
+    }
+
+    #[test]
+    fn loads_legacy_channels() {
+        let mut source = MapSource::default();
+        source.set("DEVICE_NAME", "px_7a");
+        for prefix in ["STABLE", "BETA", "ALPHA"] {
+            source.set(&format!("{}_EMAIL", prefix), "user@example.com");
+            source.set(&format!("{}_AAS_TOKEN", prefix), "aas_et/token");
+        }
+
+        let config = Config::load(&source, &devices()).unwrap();
+        assert_eq!(config.brand_name, "Sniff");
+        assert_eq!(config.details_ttl, DEFAULT_DETAILS_TTL_SECS);
+        let names: Vec<String> = config
+            .channels
+            .iter()
+            .map(|account| account.channel.to_string())
+            .collect();
+        assert_eq!(names, ["stable", "beta", "alpha"]);
+        assert!(config
+            .channels
+            .iter()
+            .all(|account| account.device == "px_7a"));
+    }
+
+    #[test]
+    fn lists_every_problem() {
+        let mut source = MapSource::default();
+        source.set("DEVICE_NAME", "nokia_3310");
+        source.set("DETAILS_CACHE_TTL", "30");
+        source.set(
+            "CHANNELS",
+            r#"[
+                {"name": "stable", "email_secret": "STABLE_EMAIL", "token_secret": "STABLE_AAS_TOKEN", "device": "px_9a"},
+                {"name": "eu", "email_secret": "EU_EMAIL", "token_secret": "EU_AAS_TOKEN", "locale": "de-DE"}
+            ]"#,
+        );
+        source.set("STABLE_EMAIL", "user@example.com");
+        source.set("STABLE_AAS_TOKEN", "aas_et/token");
+        source.set("EU_EMAIL", "eu@example.com");
+
+        let problems = match Config::load(&source, &devices()) {
+            Err(e) => e.problems,
+            Ok(_) => panic!("configuration should be invalid"),
+        };
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+        assert!(problems[0].contains("nokia_3310"));
+        assert!(problems[1].starts_with("DETAILS_CACHE_TTL"));
+        assert!(problems[2].contains("de-DE"));
+        assert!(problems[3].starts_with("EU_AAS_TOKEN"));
+        assert!(problems.iter().all(|problem| !problem.contains("aas_et/")));
+    }
+}
@@ -0,0 +1,401 @@
+use googleplay_protobuf::{AppDetails, DetailsResponse};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use worker::async_trait::async_trait;
+use worker::KvStore;
+
+use crate::google_play_client::Channel;
+
+/// KV namespace binding used to cache details responses.
+pub const DETAILS_KV_BINDING: &str = "DETAILS";
+
+/// Variable holding how long details are served from the cache, in seconds. With `0` every
+/// request goes to Google Play, and the cache only serves as the fallback while it fails.
+pub const DETAILS_TTL_VAR: &str = "DETAILS_CACHE_TTL";
+
+pub const DEFAULT_DETAILS_TTL_SECS: u64 = 5 * 60;
+
+/// The shortest expiration Workers KV accepts.
+pub const MIN_DETAILS_TTL_SECS: u64 = 60;
+
+/// How long the last good copy of a details response is kept, to be served while Google Play
+/// is failing.
+pub const STALE_DETAILS_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// A details response and when it was fetched from Google Play, in seconds since the epoch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedDetails {
+    pub details: DetailsResponse,
+    pub fetched_at: u64,
+}
+
+/// Details as served to a request. `stale_age` is set when Google Play failed and the last good
+/// copy, fetched that many seconds earlier, was served instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FetchedDetails {
+    pub details: DetailsResponse,
+    pub stale_age: Option<u64>,
+}
+
+impl FetchedDetails {
+    pub fn fresh(details: DetailsResponse) -> Self {
+        FetchedDetails {
+            details,
+            stale_age: None,
+        }
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.stale_age.is_some()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Metadata {
+    fetched_at: u64,
+}
+
+/// Details responses, keyed by `details_key`. Freshness is judged by the caller from
+/// `CachedDetails::fetched_at`; `ttl` only bounds how long an entry is kept at all.
+#[async_trait(?Send)]
+pub trait DetailsCache: Send + Sync {
+    async fn get(&self, key: &str) -> Option<CachedDetails>;
+    async fn put(&self, key: &str, entry: &CachedDetails, ttl: u64) -> Result<(), String>;
+    async fn remove(&self, key: &str) -> Result<(), String>;
+}
+
+/// Everything a details response depends on: the account's channel, the device it logs in as
+/// and the locale listings are requested in.
+pub fn details_key(package_name: &str, channel: &Channel, device: &str, locale: &str) -> String {
+    format!("details:{}:{}:{}:{}", channel, device, locale, package_name)
+}
+
+/// Details stored in Workers KV as encoded protobuf, with the fetch time as metadata.
+pub struct KvDetailsCache {
+    kv: KvStore,
+}
+
+impl KvDetailsCache {
+    pub fn new(kv: KvStore) -> Self {
+        Self { kv }
+    }
+}
+
+#[async_trait(?Send)]
+impl DetailsCache for KvDetailsCache {
+    async fn get(&self, key: &str) -> Option<CachedDetails> {
+        let (bytes, metadata) = self
+            .kv
+            .get(key)
+            .bytes_with_metadata::<Metadata>()
+            .await
+            .ok()?;
+        Some(CachedDetails {
+            details: DetailsResponse::decode(bytes?.as_slice()).ok()?,
+            fetched_at: metadata?.fetched_at,
+        })
+    }
+
+    async fn put(&self, key: &str, entry: &CachedDetails, ttl: u64) -> Result<(), String> {
+        self.kv
+            .put_bytes(key, &entry.details.encode_to_vec())
+            .map_err(|e| e.to_string())?
+            .metadata(Metadata {
+                fetched_at: entry.fetched_at,
+            })
+            .map_err(|e| e.to_string())?
+            .expiration_ttl(ttl.max(MIN_DETAILS_TTL_SECS))
+            .execute()
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), String> {
+        self.kv.delete(key).await.map_err(|e| e.to_string())
+    }
+}
+
+/// Details kept in memory. Used when no KV namespace is bound.
+#[derive(Default)]
+pub struct MemoryDetailsCache {
+    entries: Mutex<HashMap<String, CachedDetails>>,
+}
+
+impl MemoryDetailsCache {
+    /// The cache of this isolate. Every request gets a new registry, so a cache of its own
+    /// would never be hit.
+    pub fn shared() -> Arc<MemoryDetailsCache> {
+        static CACHE: OnceLock<Arc<MemoryDetailsCache>> = OnceLock::new();
+        CACHE.get_or_init(Default::default).clone()
+    }
+}
+
+#[async_trait(?Send)]
+impl DetailsCache for MemoryDetailsCache {
+    async fn get(&self, key: &str) -> Option<CachedDetails> {
+        self.entries.lock().unwrap().get(key).cloned()
+    }
+
+    async fn put(&self, key: &str, entry: &CachedDetails, _ttl: u64) -> Result<(), String> {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), entry.clone());
+        Ok(())
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), String> {
+        self.entries.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+fn app_details(details: &DetailsResponse) -> Option<&AppDetails> {
+    details
+        .item
+        .as_ref()
+        .and_then(|item| item.details.as_ref())
+        .and_then(|details| details.app_details.as_ref())
+}
+
+/// `ETag` and `Last-Modified` of a details response, derived from the version code and update
+/// date of every channel in it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: Option<String>,
+}
+
+/// 64-bit FNV-1a. Unlike `DefaultHasher`, its output is the same across Rust versions and
+/// platforms, so an `ETag` stays valid over deploys.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+impl Validators {
+    pub fn new(entries: &[(&Channel, &DetailsResponse)]) -> Self {
+        let mut entries = entries.to_vec();
+        entries.sort_by_key(|(channel, _)| channel.to_string());
+
+        let mut hasher = Fnv1a::new();
+        let mut last_modified = None;
+        for (channel, details) in entries {
+            let app = app_details(details);
+            let version_code = app.and_then(|app| app.version_code);
+            let updated_on = app.and_then(|app| app.info_updated_on.as_deref());
+            hasher.write(channel.to_string().as_bytes());
+            hasher.write(&[0xff]);
+            match version_code {
+                Some(version_code) => {
+                    hasher.write(&[1]);
+                    hasher.write(&version_code.to_le_bytes());
+                }
+                None => hasher.write(&[0]),
+            }
+            match updated_on {
+                Some(updated_on) => {
+                    hasher.write(&[1]);
+                    hasher.write(updated_on.as_bytes());
+                    hasher.write(&[0xff]);
+                }
+                None => hasher.write(&[0]),
+            }
+            if let Some(date) = updated_on.and_then(parse_update_date) {
+                last_modified = last_modified.max(Some(date));
+            }
+        }
+        // Weak, since the JSON is not guaranteed to be byte for byte the same
+        Validators {
+            etag: format!("W/\"{:016x}\"", hasher.0),
+            last_modified: last_modified
+                .map(|(year, month, day)| http_date(days_from_civil(year, month, day) * DAY_SECS)),
+        }
+    }
+
+    /// Whether an `If-None-Match` header matches our `ETag`, so the client's copy is current.
+    pub fn matches(&self, if_none_match: Option<&str>) -> bool {
+        let Some(if_none_match) = if_none_match else {
+            return false;
+        };
+        let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+        if_none_match.trim() == "*"
+            || if_none_match
+                .split(',')
+                .any(|tag| opaque(tag) == opaque(&self.etag))
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+/// Parse an update date like `Jul 21, 2025`, as Google Play formats it in English locales.
+pub(crate) fn parse_update_date(date: &str) -> Option<(i64, u32, u32)> {
+    let (month, rest) = date.trim().split_once(' ')?;
+    let (day, year) = rest.split_once(',')?;
+    let month = MONTHS.iter().position(|name| *name == month)? as u32 + 1;
+    let day: u32 = day.trim().parse().ok()?;
+    let year: i64 = year.trim().parse().ok()?;
+    (1..=31).contains(&day).then_some((year, month, day))
+}
+
+/// Format seconds since the epoch as an HTTP date, e.g. `Mon, 21 Jul 2025 00:00:00 GMT`. This
+/// is also the RFC 822 date RSS uses.
+pub(crate) fn http_date(secs: i64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    let days = secs.div_euclid(DAY_SECS);
+    let time = secs.rem_euclid(DAY_SECS);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// Format seconds since the epoch as an RFC 3339 date in UTC, e.g. `2025-07-21T00:00:00Z`.
+pub(crate) fn rfc3339_date(secs: i64) -> String {
+    let time = secs.rem_euclid(DAY_SECS);
+    let (year, month, day) = civil_from_days(secs.div_euclid(DAY_SECS));
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The proleptic Gregorian date of a number of days since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googleplay_protobuf::{DocumentDetails, Item};
+
+    fn details(version_code: i32, updated_on: &str) -> DetailsResponse {
+        DetailsResponse {
+            item: Some(Item {
+                details: Some(DocumentDetails {
+                    app_details: Some(AppDetails {
+                        version_code: Some(version_code),
+                        info_updated_on: Some(updated_on.to_string()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn derives_validators() {
+        let stable = Channel::stable();
+        let beta = Channel::from_str("beta").unwrap();
+        let old = details(100, "Jul 21, 2025");
+        let new = details(101, "Aug 2, 2025");
+
+        let validators = Validators::new(&[(&stable, &old), (&beta, &new)]);
+        assert_eq!(
+            validators.last_modified.as_deref(),
+            Some("Sat, 02 Aug 2025 00:00:00 GMT")
+        );
+        // Channel order does not matter, versions do
+        assert_eq!(
+            validators,
+            Validators::new(&[(&beta, &new), (&stable, &old)])
+        );
+        assert_ne!(
+            validators,
+            Validators::new(&[(&stable, &old), (&beta, &old)])
+        );
+        assert_ne!(
+            Validators::new(&[(&stable, &old)]).etag,
+            Validators::new(&[(&beta, &old)]).etag
+        );
+        // Clients keep ETags across deploys, so they must not depend on the build
+        assert_eq!(
+            Validators::new(&[(&stable, &old)]).etag,
+            "W/\"ca05e0281dec5ccf\""
+        );
+
+        assert_eq!(
+            Validators::new(&[(&stable, &details(100, "21. Juli 2025"))]).last_modified,
+            None
+        );
+    }
+
+    #[test]
+    fn matches_if_none_match() {
+        let validators = Validators::new(&[(&Channel::stable(), &details(100, "Jul 21, 2025"))]);
+        let strong = validators.etag.trim_start_matches("W/").to_string();
+        assert!(validators.matches(Some(&validators.etag)));
+        assert!(validators.matches(Some(&format!("\"other\", {}", strong))));
+        assert!(validators.matches(Some("*")));
+        assert!(!validators.matches(Some("\"other\"")));
+        assert!(!validators.matches(None));
+    }
+
+    #[test]
+    fn formats_http_dates() {
+        assert_eq!(http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        let leap_day = days_from_civil(2000, 2, 29) * DAY_SECS;
+        assert_eq!(http_date(leap_day), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(civil_from_days(leap_day / DAY_SECS), (2000, 2, 29));
+        assert_eq!(rfc3339_date(1753056000 + 3723), "2025-07-21T01:02:03Z");
+        assert_eq!(parse_update_date("Dec 31, 1999"), Some((1999, 12, 31)));
+        assert_eq!(parse_update_date("Dec 32, 1999"), None);
+    }
+}
@@ -0,0 +1,269 @@
+use crate::details_cache::{days_from_civil, http_date, parse_update_date, rfc3339_date};
+use crate::google_play_client::Channel;
+use crate::version_history::VersionRecord;
+
+/// How many versions a feed lists, newest first.
+pub const FEED_ENTRIES: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedFormat {
+    Atom,
+    Rss,
+}
+
+impl FeedFormat {
+    /// Split the last segment of a feed route, like `beta.atom`, into the channel and format.
+    pub fn split_extension(segment: &str) -> Option<(&str, FeedFormat)> {
+        let (channel, extension) = segment.rsplit_once('.')?;
+        let format = match extension {
+            "atom" => FeedFormat::Atom,
+            "rss" => FeedFormat::Rss,
+            _ => return None,
+        };
+        Some((channel, format))
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            FeedFormat::Atom => "application/atom+xml; charset=utf-8",
+            FeedFormat::Rss => "application/rss+xml; charset=utf-8",
+        }
+    }
+}
+
+/// The changelog of a package on a channel. Links are absolute, built from `base_url`.
+pub struct Feed<'a> {
+    pub package_name: &'a str,
+    pub channel: &'a Channel,
+    pub app_name: Option<&'a str>,
+    pub base_url: &'a str,
+    /// Newest first
+    pub versions: &'a [VersionRecord],
+}
+
+impl Feed<'_> {
+    pub fn render(&self, format: FeedFormat) -> String {
+        match format {
+            FeedFormat::Atom => self.atom(),
+            FeedFormat::Rss => self.rss(),
+        }
+    }
+
+    fn title(&self) -> String {
+        format!(
+            "{} ({})",
+            self.app_name.unwrap_or(self.package_name),
+            self.channel.display_name()
+        )
+    }
+
+    fn self_url(&self, format: FeedFormat) -> String {
+        let extension = match format {
+            FeedFormat::Atom => "atom",
+            FeedFormat::Rss => "rss",
+        };
+        format!(
+            "{}/v1/feed/{}/{}.{}",
+            self.base_url, self.package_name, self.channel, extension
+        )
+    }
+
+    fn apk_url(&self, version: &VersionRecord) -> String {
+        format!(
+            "{}/v1/apk/{}/{}/{}",
+            self.base_url, self.package_name, self.channel, version.version_code
+        )
+    }
+
+    fn entry_title(&self, version: &VersionRecord) -> String {
+        let version_name = match &version.version_string {
+            Some(version_string) => version_string.clone(),
+            None => version.version_code.to_string(),
+        };
+        format!(
+            "{} {}",
+            self.app_name.unwrap_or(self.package_name),
+            version_name
+        )
+    }
+
+    fn atom(&self) -> String {
+        let self_url = self.self_url(FeedFormat::Atom);
+        let updated = self.versions.first().map(published).unwrap_or(0);
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+        xml.push_str(&format!("  <id>{}</id>\n", escape(&self_url)));
+        xml.push_str(&format!("  <title>{}</title>\n", escape(&self.title())));
+        xml.push_str(&format!("  <updated>{}</updated>\n", rfc3339_date(updated)));
+        xml.push_str(&format!(
+            "  <link rel=\"self\" href=\"{}\"/>\n",
+            escape(&self_url)
+        ));
+        xml.push_str("  <author><name>Sniff</name></author>\n");
+        for version in self.versions {
+            let apk_url = self.apk_url(version);
+            let date = rfc3339_date(published(version));
+            xml.push_str("  <entry>\n");
+            xml.push_str(&format!("    <id>{}</id>\n", escape(&apk_url)));
+            xml.push_str(&format!(
+                "    <title>{}</title>\n",
+                escape(&self.entry_title(version))
+            ));
+            xml.push_str(&format!("    <updated>{}</updated>\n", date));
+            xml.push_str(&format!("    <published>{}</published>\n", date));
+            xml.push_str(&format!("    <link href=\"{}\"/>\n", escape(&apk_url)));
+            if let Some(changes) = &version.recent_changes_html {
+                xml.push_str(&format!(
+                    "    <content type=\"html\">{}</content>\n",
+                    escape(changes)
+                ));
+            }
+            xml.push_str("  </entry>\n");
+        }
+        xml.push_str("</feed>\n");
+        xml
+    }
+
+    fn rss(&self) -> String {
+        let self_url = self.self_url(FeedFormat::Rss);
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str(
+            "<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n<channel>\n",
+        );
+        xml.push_str(&format!("  <title>{}</title>\n", escape(&self.title())));
+        xml.push_str(&format!(
+            "  <link>https://play.google.com/store/apps/details?id={}</link>\n",
+            escape(self.package_name)
+        ));
+        xml.push_str(&format!(
+            "  <description>{} versions on the {} channel</description>\n",
+            escape(self.app_name.unwrap_or(self.package_name)),
+            self.channel
+        ));
+        xml.push_str(&format!(
+            "  <atom:link rel=\"self\" type=\"application/rss+xml\" href=\"{}\"/>\n",
+            escape(&self_url)
+        ));
+        if let Some(latest) = self.versions.first() {
+            xml.push_str(&format!(
+                "  <lastBuildDate>{}</lastBuildDate>\n",
+                http_date(published(latest))
+            ));
+        }
+        for version in self.versions {
+            let apk_url = self.apk_url(version);
+            xml.push_str("  <item>\n");
+            xml.push_str(&format!(
+                "    <title>{}</title>\n",
+                escape(&self.entry_title(version))
+            ));
+            xml.push_str(&format!("    <link>{}</link>\n", escape(&apk_url)));
+            xml.push_str(&format!(
+                "    <guid isPermaLink=\"true\">{}</guid>\n",
+                escape(&apk_url)
+            ));
+            xml.push_str(&format!(
+                "    <pubDate>{}</pubDate>\n",
+                http_date(published(version))
+            ));
+            if let Some(changes) = &version.recent_changes_html {
+                xml.push_str(&format!(
+                    "    <description>{}</description>\n",
+                    escape(changes)
+                ));
+            }
+            xml.push_str("  </item>\n");
+        }
+        xml.push_str("</channel>\n</rss>\n");
+        xml
+    }
+}
+
+/// When a version was published: the listing's update date, or when Sniff first saw it if the
+/// date is missing or not in English.
+fn published(version: &VersionRecord) -> i64 {
+    version
+        .info_updated_on
+        .as_deref()
+        .and_then(parse_update_date)
+        .map(|(year, month, day)| days_from_civil(year, month, day) * 24 * 60 * 60)
+        .unwrap_or(version.first_seen as i64)
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(version_code: i32, updated_on: Option<&str>) -> VersionRecord {
+        VersionRecord {
+            package_name: String::from("com.discord"),
+            channel: String::from("beta"),
+            version_code,
+            version_string: Some(format!("{}.0 - Beta", version_code)),
+            recent_changes_html: Some(String::from("Fixed <b>crashes</b> & lag")),
+            info_updated_on: updated_on.map(str::to_string),
+            download_size: None,
+            first_seen: 1753056000,
+        }
+    }
+
+    #[test]
+    fn renders_feeds() {
+        let channel = Channel::from_str("beta").unwrap();
+        let versions = [version(101, Some("Aug 2, 2025")), version(100, None)];
+        let feed = Feed {
+            package_name: "com.discord",
+            channel: &channel,
+            app_name: Some("Discord"),
+            base_url: "https://sniff.example",
+            versions: &versions,
+        };
+
+        let atom = feed.render(FeedFormat::Atom);
+        assert!(atom.contains("<title>Discord (Beta)</title>"));
+        assert!(atom.contains("<id>https://sniff.example/v1/feed/com.discord/beta.atom</id>"));
+        assert!(atom.contains("<updated>2025-08-02T00:00:00Z</updated>"));
+        assert!(atom.contains("<link href=\"https://sniff.example/v1/apk/com.discord/beta/101\"/>"));
+        assert!(atom.contains("<title>Discord 101.0 - Beta</title>"));
+        assert!(atom.contains("Fixed &lt;b&gt;crashes&lt;/b&gt; &amp; lag"));
+        // Without an update date, entries fall back to when the version was first seen
+        assert!(atom.contains("<published>2025-07-21T00:00:00Z</published>"));
+        assert_eq!(atom.matches("<entry>").count(), 2);
+
+        let rss = feed.render(FeedFormat::Rss);
+        assert!(rss.contains("<pubDate>Sat, 02 Aug 2025 00:00:00 GMT</pubDate>"));
+        assert!(rss.contains(
+            "<guid isPermaLink=\"true\">https://sniff.example/v1/apk/com.discord/beta/100</guid>"
+        ));
+        assert_eq!(rss.matches("<item>").count(), 2);
+    }
+
+    #[test]
+    fn splits_extension() {
+        assert_eq!(
+            FeedFormat::split_extension("beta.atom"),
+            Some(("beta", FeedFormat::Atom))
+        );
+        assert_eq!(
+            FeedFormat::split_extension("stable.rss"),
+            Some(("stable", FeedFormat::Rss))
+        );
+        assert_eq!(FeedFormat::split_extension("beta.json"), None);
+        assert_eq!(FeedFormat::split_extension("beta"), None);
+    }
+}
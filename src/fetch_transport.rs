@@ -0,0 +1,53 @@
+use gpapi::transport::{HttpRequest, HttpResponse, Method as GpapiMethod, Transport};
+use std::collections::HashMap;
+use std::error::Error;
+use worker::async_trait::async_trait;
+use worker::send::SendFuture;
+use worker::{js_sys, Fetch, Headers, Method, Request, RequestInit};
+
+/// Sends gpapi's requests through the Workers runtime `fetch`.
+#[derive(Debug, Default)]
+pub struct FetchTransport;
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Transport for FetchTransport {
+    async fn send(&self, req: HttpRequest) -> Result<HttpResponse, Box<dyn Error + Send + Sync>> {
+        SendFuture::new(fetch(req))
+            .await
+            .map_err(|e| e.to_string().into())
+    }
+}
+
+async fn fetch(req: HttpRequest) -> worker::Result<HttpResponse> {
+    let headers = Headers::new();
+    for (key, val) in &req.headers {
+        headers.set(key, val)?;
+    }
+
+    let mut init = RequestInit::new();
+    init.with_headers(headers).with_method(match req.method {
+        GpapiMethod::Get => Method::Get,
+        GpapiMethod::Post => Method::Post,
+    });
+    if let Some(body) = &req.body {
+        init.with_body(Some(js_sys::Uint8Array::from(body.as_slice()).into()));
+    }
+
+    let request = Request::new_with_init(&req.url, &init)?;
+    let mut response = Fetch::Request(request).send().await?;
+
+    let status = response.status_code();
+    let headers: HashMap<String, String> = response
+        .headers()
+        .entries()
+        .map(|(key, val)| (key.to_ascii_lowercase(), val))
+        .collect();
+    let body = response.bytes().await?;
+
+    Ok(HttpResponse {
+        status,
+        headers,
+        body: body.into(),
+    })
+}
@@ -0,0 +1,48 @@
+//! Names of the files Sniff serves, shared by the download endpoints and webhook payloads.
+
+/// The app's name in a listing title, without its subtitle: `Discord` of
+/// `Discord - Talk, Play, Hang Out`.
+pub fn app_name(title: &str) -> &str {
+    title.split(" - ").next().unwrap_or(title).trim()
+}
+
+/// Build a suggested filename for the APK download
+/// Format: {brand}_{appname}_{channel}_{version}.apk
+pub fn build_suggested_filename(
+    brand: &str,
+    app_name: Option<&str>,
+    channel: &str,
+    version: Option<&str>,
+) -> String {
+    let sanitize = |s: &str| -> String {
+        s.chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '.' || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>()
+            .replace("__", "_")
+            .trim_matches('_')
+            .to_string()
+    };
+
+    let brand_part = sanitize(brand);
+    let app_part = app_name.map(sanitize).unwrap_or_else(|| "App".to_string());
+    let channel_part = sanitize(channel);
+
+    match version {
+        Some(v) => {
+            // Clean version string (remove channel suffix if present, e.g., "289.20 - Stable" -> "289.20")
+            let clean_version = v.split(" - ").next().unwrap_or(v).trim();
+            let version_part = sanitize(clean_version);
+            format!(
+                "{}_{}_{}_{}.apk",
+                brand_part, app_part, channel_part, version_part
+            )
+        }
+        None => format!("{}_{}_{}.apk", brand_part, app_part, channel_part),
+    }
+}
@@ -1,54 +1,155 @@
-use googleplay_protobuf::DetailsResponse;
-use gpapi::{DownloadInfo, Gpapi};
-use std::collections::HashSet;
-
-use lazy_static::lazy_static;
-
-lazy_static! {
-    pub static ref BETA_PACKAGES: HashSet<&'static str> = {
-        let mut set = HashSet::new();
-        set.insert("com.discord");
-        set
-    };
-    pub static ref ALPHA_PACKAGES: HashSet<&'static str> = {
-        let mut set = HashSet::new();
-        set.insert("com.discord");
-        set
-    };
-}
+use googleplay_protobuf::{DetailsResponse, Item};
+use gpapi::error::{Error as GpapiError, ErrorKind};
+use gpapi::transport::Transport;
+use gpapi::{
+    Category, Chart, ChartOptions, DownloadInfo, Gpapi, Page, ReviewPage, ReviewSort,
+    SearchOptions, Session, TestingProgramStatus,
+};
+use std::fmt;
+use std::sync::Arc;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum Channel {
-    Stable,
-    Beta,
-    Alpha,
-}
+/// Name of the channel every other channel is compared against.
+pub const STABLE_CHANNEL: &str = "stable";
+
+/// A release channel, named after one of the accounts configured in `CHANNELS`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Channel(String);
 
 impl std::fmt::Display for Channel {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Channel::Stable => write!(f, "stable"),
-            Channel::Beta => write!(f, "beta"),
-            Channel::Alpha => write!(f, "alpha"),
-        }
+        write!(f, "{}", self.0)
     }
 }
 
 impl Channel {
+    /// Channel names are case insensitive and made of ASCII letters, digits, `-` and `_`.
     pub fn from_str(s: &str) -> Result<Self, String> {
-        match s.to_lowercase().as_str() {
-            "stable" => Ok(Channel::Stable),
-            "beta" => Ok(Channel::Beta),
-            "alpha" => Ok(Channel::Alpha),
-            _ => Err(format!("Invalid Channel: {}", s)),
+        let valid = !s.is_empty()
+            && s.len() <= 32
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if valid {
+            Ok(Channel(s.to_ascii_lowercase()))
+        } else {
+            Err(format!("Invalid Channel: {}", s))
+        }
+    }
+
+    pub fn stable() -> Self {
+        Channel(String::from(STABLE_CHANNEL))
+    }
+
+    pub fn is_stable(&self) -> bool {
+        self.0 == STABLE_CHANNEL
+    }
+
+    /// The name with its first letter capitalized, e.g. `Beta`, for filenames.
+    pub fn display_name(&self) -> String {
+        let mut chars = self.0.chars();
+        match chars.next() {
+            Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+            None => String::new(),
+        }
+    }
+}
+
+/// Why a request to Google Play through the registry failed.
+#[derive(Debug)]
+pub enum PlayError {
+    ChannelUnavailable {
+        channel: Channel,
+        package_name: String,
+    },
+    AppNotFound {
+        package_name: String,
+    },
+    UnknownChannel {
+        channel: Channel,
+    },
+    Login {
+        channel: Channel,
+        error: GpapiError,
+    },
+    Api {
+        channel: Channel,
+        error: GpapiError,
+    },
+}
+
+impl PlayError {
+    /// The gpapi error behind this failure, if it came from Google Play.
+    pub fn kind(&self) -> Option<&ErrorKind> {
+        match self {
+            PlayError::Login { error, .. } | PlayError::Api { error, .. } => Some(error.kind()),
+            _ => None,
         }
     }
 
-    pub fn is_available_for_package(self, package_name: &str) -> bool {
+    /// Whether Google rejected our credentials, so logging in again may help.
+    pub fn is_auth_failure(&self) -> bool {
+        matches!(
+            self.kind(),
+            Some(ErrorKind::TokenExpired | ErrorKind::Unauthorized | ErrorKind::LoginRequired)
+        )
+    }
+
+    /// Whether Google Play failed rather than the request itself, so an older copy of the
+    /// response is better than none.
+    pub fn is_upstream_failure(&self) -> bool {
+        let status = self.status_code();
+        status == 429 || status >= 500
+    }
+
+    /// Seconds Google asked us to wait before retrying, if rate limited.
+    pub fn retry_after(&self) -> Option<u64> {
+        match self.kind() {
+            Some(ErrorKind::RateLimited(secs)) => *secs,
+            _ => None,
+        }
+    }
+
+    /// HTTP status to report this failure with.
+    pub fn status_code(&self) -> u16 {
         match self {
-            Channel::Stable => true,
-            Channel::Beta => BETA_PACKAGES.contains(package_name),
-            Channel::Alpha => ALPHA_PACKAGES.contains(package_name),
+            PlayError::ChannelUnavailable { .. } | PlayError::AppNotFound { .. } => 404,
+            PlayError::UnknownChannel { .. } => 400,
+            PlayError::Login { .. } => 502,
+            PlayError::Api { error, .. } => match error.kind() {
+                ErrorKind::NotFound | ErrorKind::InvalidApp => 404,
+                ErrorKind::PaidApp => 402,
+                ErrorKind::IncompatibleDevice => 422,
+                ErrorKind::RateLimited(_) => 429,
+                ErrorKind::GeoRestricted => 451,
+                ErrorKind::TokenExpired | ErrorKind::Unauthorized => 502,
+                _ => 500,
+            },
+        }
+    }
+}
+
+impl fmt::Display for PlayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlayError::ChannelUnavailable {
+                channel,
+                package_name,
+            } => write!(
+                f,
+                "Channel '{}' is not available for package '{}'",
+                channel, package_name
+            ),
+            PlayError::AppNotFound { package_name } => {
+                write!(f, "App '{}' not found", package_name)
+            }
+            PlayError::UnknownChannel { channel } => {
+                write!(f, "Channel '{}' is not configured", channel)
+            }
+            PlayError::Login { channel, error } => {
+                write!(f, "Login error for {} channel: {}", channel, error)
+            }
+            PlayError::Api { channel, error } => {
+                write!(f, "API error for {} channel: {}", channel, error)
+            }
         }
     }
 }
@@ -59,35 +160,147 @@ pub struct GooglePlayClient {
 }
 
 impl GooglePlayClient {
-    pub fn new(device_name: &str, email: &str, aas_token: &str, channel: Channel) -> Self {
+    pub fn new(
+        device_name: &str,
+        email: &str,
+        aas_token: &str,
+        locale: Option<&str>,
+        channel: Channel,
+        transport: Arc<dyn Transport>,
+    ) -> Self {
         let mut client = Gpapi::new(device_name, email);
         client.set_aas_token(aas_token);
+        if let Some(locale) = locale {
+            client.set_locale(locale);
+        }
+        client.set_transport(transport);
 
         Self { client, channel }
     }
 
-    pub async fn initialize(&mut self) -> Result<(), String> {
-        self.client
-            .login()
-            .await
-            .map_err(|e| format!("Login error for {} channel: {:?}", self.channel, e))
+    pub async fn initialize(&mut self) -> Result<(), PlayError> {
+        self.client.login().await.map_err(|e| PlayError::Login {
+            channel: self.channel.clone(),
+            error: e.into(),
+        })
+    }
+
+    pub fn session(&self) -> Option<Session> {
+        self.client.session()
+    }
+
+    /// The auth token requests are currently sent with.
+    pub fn auth_token(&self) -> Option<String> {
+        self.client.session().map(|session| session.auth_token)
     }
 
-    pub async fn get_details(&self, package_name: &str) -> Result<Option<DetailsResponse>, String> {
+    pub fn restore_session(&mut self, session: Session) -> Result<(), GpapiError> {
+        self.client.restore_session(session)
+    }
+
+    pub async fn get_details(
+        &self,
+        package_name: &str,
+    ) -> Result<Option<DetailsResponse>, PlayError> {
         self.client
             .details(package_name)
             .await
-            .map_err(|e| format!("API error for {} channel: {:?}", self.channel, e))
+            .map_err(|e| self.api_error(e))
+    }
+
+    /// Items of every package Google Play found, in one `bulkDetails` request.
+    pub async fn get_bulk_details(&self, package_names: &[&str]) -> Result<Vec<Item>, PlayError> {
+        let response = self
+            .client
+            .bulk_details(package_names)
+            .await
+            .map_err(|e| self.api_error(e))?;
+        Ok(response
+            .map(|response| {
+                response
+                    .entry
+                    .into_iter()
+                    .filter_map(|entry| entry.item)
+                    .collect()
+            })
+            .unwrap_or_default())
     }
 
     pub async fn get_download_info(
         &self,
         package_name: &str,
         version_code: Option<i32>,
-    ) -> Result<DownloadInfo, String> {
+    ) -> Result<DownloadInfo, PlayError> {
         self.client
             .get_download_info(package_name, version_code)
             .await
-            .map_err(|e| format!("API error for {} channel: {:?}", self.channel, e))
+            .map_err(|e| self.api_error(e))
+    }
+
+    pub async fn search(&self, query: &str, options: SearchOptions) -> Result<Page, PlayError> {
+        self.client
+            .search(query, options)
+            .await
+            .map_err(|e| self.api_error(e))
+    }
+
+    pub async fn categories(&self) -> Result<Vec<Category>, PlayError> {
+        self.client
+            .categories()
+            .await
+            .map_err(|e| self.api_error(e))
+    }
+
+    pub async fn top_chart(&self, chart: Chart, options: ChartOptions) -> Result<Page, PlayError> {
+        self.client
+            .top_chart(chart, options)
+            .await
+            .map_err(|e| self.api_error(e))
+    }
+
+    pub async fn get_reviews(
+        &self,
+        package_name: &str,
+        sort: ReviewSort,
+        filter_by_device: bool,
+        rating: Option<u8>,
+        offset: usize,
+    ) -> Result<ReviewPage, PlayError> {
+        self.client
+            .reviews(package_name, sort, filter_by_device, rating, offset)
+            .await
+            .map_err(|e| self.api_error(e))
+    }
+
+    pub async fn testing_program_status(
+        &self,
+        package_name: &str,
+    ) -> Result<TestingProgramStatus, PlayError> {
+        self.client
+            .testing_program_status(package_name)
+            .await
+            .map_err(|e| self.api_error(e))
+    }
+
+    /// Join (`subscribe`) or leave the package's testing program. Returns whether Google
+    /// confirmed the change.
+    pub async fn set_testing_program(
+        &self,
+        package_name: &str,
+        subscribe: bool,
+    ) -> Result<bool, PlayError> {
+        let result = if subscribe {
+            self.client.join_testing_program(package_name).await
+        } else {
+            self.client.leave_testing_program(package_name).await
+        };
+        result.map_err(|e| self.api_error(e))
+    }
+
+    fn api_error(&self, error: Box<dyn std::error::Error + Send + Sync>) -> PlayError {
+        PlayError::Api {
+            channel: self.channel.clone(),
+            error: error.into(),
+        }
     }
 }
@@ -1,13 +1,187 @@
-use crate::client_registry::SharedClientRegistry;
-use crate::google_play_client::Channel;
+use crate::bundle::{self, BundleApp, BundleFormat, BundleSource};
+use crate::client_registry::{SharedClientRegistry, MAX_BULK_PACKAGES};
+use crate::config::{Config, ConfigError};
+use crate::details_cache::{FetchedDetails, Validators};
+use crate::feed::{Feed, FeedFormat, FEED_ENTRIES};
+use crate::filename::{app_name, build_suggested_filename};
+use crate::google_play_client::{Channel, PlayError};
 use crate::openapi_schema::{
-    ApiResponse, DownloadInfo, MultiChannelApiResponse, SerializableDetailsResponse,
+    ApiResponse, AppPage, BulkDetailsRequest, BulkDetailsResponse, Category, ChannelCheck,
+    ConfigCheck, DeviceSpecRequest, DownloadInfo, HistoryEntry, MultiChannelApiResponse,
+    ReviewPage, SerializableDetailsResponse, TestingProgram, TestingProgramEnrollment,
+    VersionHistory, WebhookInfo, WebhookRequest,
 };
+use crate::runtime::now_secs;
 use crate::serializable_types::SerializableDetailsResponse as ActualSerializableDetailsResponse;
-use std::collections::HashMap;
+use crate::splits::DeviceSpec;
+use crate::version_history::VersionRecord;
+use crate::watcher::{WatchStore, WATCHLIST_KV_BINDING};
+use crate::webhooks::{Webhook, WebhookStore, WEBHOOKS_KV_BINDING};
+use crate::zip::{zip_stream, ByteChunks};
+use futures::stream;
+use gpapi::{Chart, ChartOptions, ReviewSort, SearchOptions};
+use std::collections::{HashMap, HashSet};
 use utoipa;
 use worker::*;
 
+/// Set the HTTP status (and `Retry-After`, when rate limited) matching a Google Play failure.
+fn with_error_status(response: Response, error: &PlayError) -> Result<Response> {
+    let mut response = response.with_status(error.status_code());
+    if let Some(secs) = error.retry_after() {
+        response.headers_mut().set("Retry-After", &secs.to_string())?;
+    }
+    Ok(response)
+}
+
+fn bad_request(error: String) -> Result<Response> {
+    let response = ApiResponse::<()> {
+        success: false,
+        data: None,
+        error: Some(error),
+    };
+    Ok(Response::from_json(&response)?.with_status(400))
+}
+
+fn error_response(status: u16, error: String) -> Result<Response> {
+    let response = ApiResponse::<()> {
+        success: false,
+        data: None,
+        error: Some(error),
+    };
+    Ok(Response::from_json(&response)?.with_status(status))
+}
+
+/// The device to select splits for: a bundletool `device-spec.json` body, else the `abi`, `dpi`
+/// and `lang` query parameters. `None` keeps every split.
+fn requested_device(
+    params: &HashMap<String, String>,
+    device_spec: Option<&str>,
+) -> std::result::Result<Option<DeviceSpec>, String> {
+    match device_spec.filter(|body| !body.trim().is_empty()) {
+        Some(json) => DeviceSpec::from_json(json).map(Some),
+        None => DeviceSpec::from_query(params),
+    }
+}
+
+/// Response for every route while the configuration is invalid. The problems are logged and
+/// listed by `/v1/config/check`, not shown to anonymous callers.
+pub fn misconfigured() -> Result<Response> {
+    error_response(
+        503,
+        "Service is misconfigured, see /v1/config/check".to_string(),
+    )
+}
+
+/// Check an `Authorization` header against the `ADMIN_TOKEN` secret. Admin routes are disabled
+/// when the secret is not set.
+fn check_admin(
+    authorization: Option<&str>,
+    admin_token: Option<&str>,
+) -> std::result::Result<(), (u16, String)> {
+    let Some(admin_token) = admin_token.filter(|token| !token.is_empty()) else {
+        return Err((403, "Admin routes are disabled, set ADMIN_TOKEN to enable them".to_string()));
+    };
+    let given = authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    // Compare every byte so the time taken does not leak how much of the token matched
+    let matches = given.len() == admin_token.len()
+        && given
+            .bytes()
+            .zip(admin_token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0;
+    if matches {
+        Ok(())
+    } else {
+        Err((401, "Missing or invalid admin token".to_string()))
+    }
+}
+
+/// Parse the channel of an admin testing program route. Only the testing channels' accounts
+/// are meant to be enrolled in testing programs.
+fn testing_channel(channel: &str) -> std::result::Result<Channel, String> {
+    let channel = Channel::from_str(channel)?;
+    if channel.is_stable() {
+        return Err("The stable account does not join testing programs".to_string());
+    }
+    Ok(channel)
+}
+
+/// `ETag`, `Last-Modified` and `Cache-Control` of a details response. Sent with 304s as well.
+fn validator_headers(validators: &Validators, max_age: u64) -> Result<Headers> {
+    let headers = Headers::new();
+    headers.set("ETag", &validators.etag)?;
+    if let Some(last_modified) = &validators.last_modified {
+        headers.set("Last-Modified", last_modified)?;
+    }
+    if max_age > 0 {
+        headers.set("Cache-Control", &format!("public, max-age={}", max_age))?;
+    }
+    Ok(headers)
+}
+
+/// Validator headers for details served to a request. Stale copies are marked with
+/// `X-Sniff-Stale` and `Age`, and refreshed in the background once the response is sent.
+fn details_headers(
+    details: &[(&Channel, &FetchedDetails)],
+    package_name: &str,
+    client_registry: &SharedClientRegistry,
+    context: &Context,
+) -> Result<(Validators, Headers)> {
+    let validators = Validators::new(
+        &details
+            .iter()
+            .map(|(channel, fetched)| (*channel, &fetched.details))
+            .collect::<Vec<_>>(),
+    );
+    let stale: Vec<(Channel, u64)> = details
+        .iter()
+        .filter_map(|(channel, fetched)| fetched.stale_age.map(|age| ((*channel).clone(), age)))
+        .collect();
+    let Some(age) = stale.iter().map(|(_, age)| *age).max() else {
+        let headers = validator_headers(&validators, client_registry.details_ttl())?;
+        return Ok((validators, headers));
+    };
+
+    // No Cache-Control, the copy is out of date already
+    let headers = validator_headers(&validators, 0)?;
+    headers.set("X-Sniff-Stale", "true")?;
+    headers.set("Age", &age.to_string())?;
+    let client_registry = client_registry.clone();
+    let package_name = package_name.to_string();
+    context.wait_until(async move {
+        for (channel, _) in stale {
+            if let Err(e) = client_registry.refresh_details(&package_name, &channel).await {
+                console_log!(
+                    "Background refresh of {} details of {} failed: {}",
+                    channel,
+                    package_name,
+                    e
+                );
+            }
+        }
+    });
+    Ok((validators, headers))
+}
+
+fn not_modified(headers: Headers) -> Result<Response> {
+    Ok(Response::empty()?.with_status(304).with_headers(headers))
+}
+
+/// Read the `page` cursor and `limit` query parameters shared by the listing endpoints.
+fn page_params(
+    params: &HashMap<String, String>,
+) -> std::result::Result<(Option<String>, Option<usize>), String> {
+    let page = params.get("page").filter(|page| !page.is_empty()).cloned();
+    let limit = match params.get("limit").map(|limit| limit.parse::<usize>()) {
+        Some(Ok(0)) | Some(Err(_)) => return Err("'limit' must be a positive integer".to_string()),
+        Some(Ok(limit)) => Some(limit),
+        None => None,
+    };
+    Ok((page, limit))
+}
+
 #[utoipa::path(
     get,
     path = "/v1/details/{package_name}",
@@ -18,31 +192,44 @@ use worker::*;
         (status = 200, description = "App details retrieved successfully",
          body = MultiChannelApiResponse<SerializableDetailsResponse>,
          headers(
-             ("X-Available-Channels" = String, description = "Comma-separated list of available channels")
+             ("X-Available-Channels" = String, description = "Comma-separated list of available channels"),
+             ("ETag" = String, description = "Changes when the version of any channel changes"),
+             ("Last-Modified" = String, description = "Latest update date of the channels"),
+             ("X-Sniff-Stale" = String, description = "`true` when Google Play failed and a cached copy of some channel was served"),
+             ("Age" = u64, description = "Seconds since the oldest stale copy was fetched, with X-Sniff-Stale")
          )
         ),
+        (status = 304, description = "Not modified since the ETag given in If-None-Match"),
         (status = 404, description = "App not found", body = MultiChannelApiResponse<SerializableDetailsResponse>),
+        (status = 429, description = "Rate limited by Google Play (see Retry-After)", body = MultiChannelApiResponse<SerializableDetailsResponse>),
         (status = 500, description = "Internal server error", body = MultiChannelApiResponse<SerializableDetailsResponse>)
     ),
     tag = "App Details"
 )]
 pub async fn get_details_multi(
     package_name: String,
+    if_none_match: Option<String>,
     client_registry: SharedClientRegistry,
+    context: &Context,
 ) -> Result<Response> {
-    match client_registry
-        .lock()
-        .expect("Failed to lock client registry")
-        .get_details_multi(&package_name)
-        .await
-    {
+    match client_registry.get_details_multi(&package_name).await {
         Ok(details_map) => {
+            let (validators, headers) = details_headers(
+                &details_map.iter().collect::<Vec<_>>(),
+                &package_name,
+                &client_registry,
+                context,
+            )?;
+            if validators.matches(if_none_match.as_deref()) {
+                return not_modified(headers);
+            }
+
             let serialized_map: HashMap<String, ActualSerializableDetailsResponse> = details_map
                 .into_iter()
-                .map(|(channel, details)| {
+                .map(|(channel, fetched)| {
                     (
                         channel.to_string(),
-                        ActualSerializableDetailsResponse(details),
+                        ActualSerializableDetailsResponse(fetched.details),
                     )
                 })
                 .collect();
@@ -55,7 +242,6 @@ pub async fn get_details_multi(
                 error: None,
             };
 
-            let headers = Headers::new();
             headers.set("Content-Type", "application/json")?;
             headers.set("X-Available-Channels", &available_channels)?;
 
@@ -65,10 +251,10 @@ pub async fn get_details_multi(
             let response = MultiChannelApiResponse::<ActualSerializableDetailsResponse> {
                 success: false,
                 data: None,
-                error: Some(e),
+                error: Some(e.to_string()),
             };
 
-            Ok(Response::from_json(&response)?.with_status(500))
+            with_error_status(Response::from_json(&response)?, &e)
         }
     }
 }
@@ -81,9 +267,19 @@ pub async fn get_details_multi(
         ("channel" = String, Path, description = "Release channel", example = "stable")
     ),
     responses(
-        (status = 200, description = "App details retrieved successfully", body = ApiResponse<SerializableDetailsResponse>),
+        (status = 200, description = "App details retrieved successfully",
+         body = ApiResponse<SerializableDetailsResponse>,
+         headers(
+             ("ETag" = String, description = "Changes when the version of the app changes"),
+             ("Last-Modified" = String, description = "Update date of the app"),
+             ("X-Sniff-Stale" = String, description = "`true` when Google Play failed and a cached copy was served"),
+             ("Age" = u64, description = "Seconds since the stale copy was fetched, with X-Sniff-Stale")
+         )
+        ),
+        (status = 304, description = "Not modified since the ETag given in If-None-Match"),
         (status = 400, description = "Invalid channel", body = ApiResponse<String>),
         (status = 404, description = "App not found", body = ApiResponse<SerializableDetailsResponse>),
+        (status = 429, description = "Rate limited by Google Play (see Retry-After)", body = ApiResponse<SerializableDetailsResponse>),
         (status = 500, description = "Internal server error", body = ApiResponse<SerializableDetailsResponse>)
     ),
     tag = "App Details"
@@ -91,7 +287,9 @@ pub async fn get_details_multi(
 pub async fn get_details_single(
     package_name: String,
     channel: String,
+    if_none_match: Option<String>,
     client_registry: SharedClientRegistry,
+    context: &Context,
 ) -> Result<Response> {
     let channel = match Channel::from_str(&channel) {
         Ok(ch) => ch,
@@ -105,20 +303,23 @@ pub async fn get_details_single(
         }
     };
 
-    let result = client_registry
-        .lock()
-        .expect("Failed to lock client registry")
-        .get_details_with_fallback(&package_name, channel)
-        .await;
+    let result = client_registry.get_details_with_fallback(&package_name, &channel).await;
 
     match result {
-        Ok(Some((_, details))) => {
+        Ok(Some((channel, fetched))) => {
+            let (validators, headers) =
+                details_headers(&[(&channel, &fetched)], &package_name, &client_registry, context)?;
+            if validators.matches(if_none_match.as_deref()) {
+                return not_modified(headers);
+            }
+
             let response = ApiResponse {
                 success: true,
-                data: Some(ActualSerializableDetailsResponse(details)),
+                data: Some(ActualSerializableDetailsResponse(fetched.details)),
                 error: None,
             };
-            Ok(Response::from_json(&response)?)
+            headers.set("Content-Type", "application/json")?;
+            Ok(Response::from_json(&response)?.with_headers(headers))
         }
         Ok(None) => {
             let response = ApiResponse::<ActualSerializableDetailsResponse> {
@@ -132,25 +333,110 @@ pub async fn get_details_single(
             let response = ApiResponse::<ActualSerializableDetailsResponse> {
                 success: false,
                 data: None,
-                error: Some(e),
+                error: Some(e.to_string()),
             };
-            Ok(Response::from_json(&response)?.with_status(500))
+            with_error_status(Response::from_json(&response)?, &e)
         }
     }
 }
 
 #[utoipa::path(
-    get,
+    post,
+    path = "/v1/details/bulk",
+    request_body = BulkDetailsRequest,
+    responses(
+        (status = 200, description = "Details of every package, keyed by package name, each with its own success and error",
+         body = BulkDetailsResponse),
+        (status = 400, description = "Invalid request, channel, or too many packages", body = ApiResponse<String>)
+    ),
+    tag = "App Details"
+)]
+pub async fn get_details_bulk(body: String, client_registry: SharedClientRegistry) -> Result<Response> {
+    let request: BulkDetailsRequest = match serde_json::from_str(&body) {
+        Ok(request) => request,
+        Err(e) => return bad_request(format!("Invalid request: {}", e)),
+    };
+    let channel = match request.channel.as_deref() {
+        Some(channel) => match Channel::from_str(channel) {
+            Ok(ch) => ch,
+            Err(e) => return bad_request(e),
+        },
+        None => Channel::stable(),
+    };
+    if request.package_names.len() > MAX_BULK_PACKAGES {
+        return bad_request(format!(
+            "'package_names' lists {} packages, at most {} are allowed",
+            request.package_names.len(),
+            MAX_BULK_PACKAGES
+        ));
+    }
+    let mut seen = HashSet::new();
+    let package_names: Vec<String> = request
+        .package_names
+        .into_iter()
+        .map(|package_name| package_name.trim().to_string())
+        .filter(|package_name| !package_name.is_empty() && seen.insert(package_name.clone()))
+        .collect();
+    if package_names.is_empty() {
+        return bad_request("'package_names' must list at least one package".to_string());
+    }
+
+    match client_registry.get_bulk_details(&package_names, &channel).await {
+        Ok(results) => {
+            let data = results
+                .into_iter()
+                .map(|(package_name, result)| {
+                    let entry = match result {
+                        Ok(details) => ApiResponse {
+                            success: true,
+                            data: Some(ActualSerializableDetailsResponse(details)),
+                            error: None,
+                        },
+                        Err(e) => ApiResponse {
+                            success: false,
+                            data: None,
+                            error: Some(e),
+                        },
+                    };
+                    (package_name, entry)
+                })
+                .collect();
+            let response = MultiChannelApiResponse {
+                success: true,
+                data: Some(data),
+                error: None,
+            };
+            Ok(Response::from_json(&response)?)
+        }
+        Err(e) => {
+            let response = ApiResponse::<()> {
+                success: false,
+                data: None,
+                error: Some(e.to_string()),
+            };
+            with_error_status(Response::from_json(&response)?, &e)
+        }
+    }
+}
+
+#[utoipa::path(
+    method(get, post),
     path = "/v1/download/{package_name}/{channel}/{version_code}",
     params(
         ("package_name" = String, Path, description = "Android package name"),
         ("channel" = String, Path, description = "Release channel"),
-        ("version_code" = i32, Path, description = "Android version code")
+        ("version_code" = i32, Path, description = "Android version code"),
+        ("abi" = Option<String>, Query, description = "Only the splits for these ABIs, most preferred first, e.g. `arm64_v8a,armeabi_v7a`"),
+        ("dpi" = Option<String>, Query, description = "Only the density splits closest to this density, e.g. `xxhdpi` or `480`"),
+        ("lang" = Option<String>, Query, description = "Only the language splits for these languages, e.g. `en,de`")
     ),
+    request_body(content = Option<DeviceSpecRequest>, description = "POST only: a bundletool `device-spec.json` to select splits for, instead of the query parameters"),
     responses(
         (status = 200, description = "Download info retrieved successfully", body = ApiResponse<DownloadInfo>),
-        (status = 400, description = "Invalid parameters", body = ApiResponse<String>),
+        (status = 400, description = "Invalid parameters or device spec", body = ApiResponse<String>),
         (status = 404, description = "App or version not found", body = ApiResponse<DownloadInfo>),
+        (status = 422, description = "No native code for the device's ABIs", body = ApiResponse<String>),
+        (status = 429, description = "Rate limited by Google Play (see Retry-After)", body = ApiResponse<DownloadInfo>),
         (status = 500, description = "Internal server error", body = ApiResponse<DownloadInfo>)
     ),
     tag = "Downloads"
@@ -159,6 +445,8 @@ pub async fn get_download_info(
     package_name: String,
     channel: String,
     version_code: Option<i32>,
+    params: HashMap<String, String>,
+    device_spec: Option<String>,
     client_registry: SharedClientRegistry,
     brand_name: String,
 ) -> Result<Response> {
@@ -173,17 +461,19 @@ pub async fn get_download_info(
             return Ok(Response::from_json(&response)?.with_status(400));
         }
     };
+    let device = match requested_device(&params, device_spec.as_deref()) {
+        Ok(device) => device,
+        Err(e) => return bad_request(e),
+    };
 
     // First, get app details to extract app name and version
     let details_result = client_registry
-        .lock()
-        .expect("Failed to lock client registry")
-        .get_details_with_fallback(&package_name, parsed_channel)
+        .get_details_with_fallback(&package_name, &parsed_channel)
         .await;
 
     let (app_name, version_string, actual_version_code) = match &details_result {
-        Ok(Some((_, details))) => {
-            let item = details.item.as_ref();
+        Ok(Some((_, fetched))) => {
+            let item = fetched.details.item.as_ref();
             let title = item.and_then(|i| i.title.clone());
             let app_details = item
                 .and_then(|i| i.details.as_ref())
@@ -192,9 +482,7 @@ pub async fn get_download_info(
             let ver_code = app_details.and_then(|a| a.version_code);
             
             // Extract just the app name (before " - " subtitle if present)
-            let clean_name = title.map(|t| {
-                t.split(" - ").next().unwrap_or(&t).trim().to_string()
-            });
+            let clean_name = title.map(|t| app_name(&t).to_string());
             
             (clean_name, ver_string, ver_code)
         }
@@ -203,42 +491,65 @@ pub async fn get_download_info(
 
     // Now get download info
     let result = client_registry
-        .lock()
-        .expect("Failed to lock client registry")
-        .get_download_info(&package_name, parsed_channel, version_code)
+        .get_download_info(&package_name, &parsed_channel, version_code)
         .await;
 
     match result {
-        Ok(Some((_, download_info))) => {
-            let (main_apk_url, splits_data, additional_files_data) = download_info;
+        Ok(Some((_, mut download_info))) => {
+            if let Some(device) = &device {
+                match device.select(std::mem::take(&mut download_info.splits)) {
+                    Ok(splits) => download_info.splits = splits,
+                    Err(e) => return error_response(422, e),
+                }
+            }
+            let splits: Vec<_> = download_info
+                .splits
+                .into_iter()
+                .map(|split| crate::openapi_schema::SplitFile {
+                    name: split.name,
+                    download_url: split.file.download_url,
+                    size: split.file.size,
+                    gzipped_download_url: split.file.gzipped_download_url,
+                    gzipped_size: split.file.gzipped_size,
+                    sha1: split.file.sha1,
+                    sha256: split.file.sha256,
+                })
+                .collect();
 
-            let splits: Vec<_> = splits_data
+            let additional_files: Vec<_> = download_info
+                .additional_files
                 .into_iter()
-                .map(|(name, url)| crate::openapi_schema::SplitFile {
-                    name,
-                    download_url: url,
+                .map(|file| crate::openapi_schema::AdditionalFile {
+                    filename: Some(file.filename),
+                    download_url: file.download_url,
+                    file_type: match file.file_type {
+                        gpapi::AdditionalFileType::Main => String::from("main"),
+                        gpapi::AdditionalFileType::Patch => String::from("patch"),
+                    },
+                    version_code: file.version_code,
+                    size: file.size,
+                    gzipped_download_url: file.gzipped_download_url,
+                    gzipped_size: file.gzipped_size,
+                    sha1: file.sha1,
                 })
                 .collect();
 
-            let additional_files: Vec<_> = additional_files_data
+            let download_auth_cookies: Vec<_> = download_info
+                .download_auth_cookies
                 .into_iter()
-                .map(|(filename, url)| crate::openapi_schema::AdditionalFile {
-                    filename,
-                    download_url: url,
+                .map(|cookie| crate::openapi_schema::DownloadAuthCookie {
+                    name: cookie.name,
+                    value: cookie.value,
                 })
                 .collect();
 
             // Build suggested filename: {brand}_{appname}_{channel}_{version}.apk
-            let channel_display = match parsed_channel {
-                Channel::Stable => "Stable",
-                Channel::Beta => "Beta",
-                Channel::Alpha => "Alpha",
-            };
+            let channel_display = parsed_channel.display_name();
             
             let suggested_filename = build_suggested_filename(
                 &brand_name,
                 app_name.as_deref(),
-                channel_display,
+                &channel_display,
                 version_string.as_deref(),
             );
 
@@ -248,9 +559,15 @@ pub async fn get_download_info(
                 version_string,
                 version_code: actual_version_code,
                 channel: Some(channel_display.to_lowercase()),
-                main_apk_url,
+                main_apk_url: download_info.main_apk.download_url,
+                main_apk_size: download_info.main_apk.size,
+                main_apk_gzipped_url: download_info.main_apk.gzipped_download_url,
+                main_apk_gzipped_size: download_info.main_apk.gzipped_size,
+                main_apk_sha1: download_info.main_apk.sha1,
+                main_apk_sha256: download_info.main_apk.sha256,
                 splits,
                 additional_files,
+                download_auth_cookies,
             };
 
             let response = ApiResponse {
@@ -272,56 +589,29 @@ pub async fn get_download_info(
             let response = ApiResponse::<DownloadInfo> {
                 success: false,
                 data: None,
-                error: Some(e),
+                error: Some(e.to_string()),
             };
-            Ok(Response::from_json(&response)?.with_status(500))
-        }
-    }
-}
-
-/// Build a suggested filename for the APK download
-/// Format: {brand}_{appname}_{channel}_{version}.apk
-fn build_suggested_filename(
-    brand: &str,
-    app_name: Option<&str>,
-    channel: &str,
-    version: Option<&str>,
-) -> String {
-    let sanitize = |s: &str| -> String {
-        s.chars()
-            .map(|c| if c.is_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
-            .collect::<String>()
-            .replace("__", "_")
-            .trim_matches('_')
-            .to_string()
-    };
-
-    let brand_part = sanitize(brand);
-    let app_part = app_name.map(|n| sanitize(n)).unwrap_or_else(|| "App".to_string());
-    let channel_part = sanitize(channel);
-    
-    match version {
-        Some(v) => {
-            // Clean version string (remove channel suffix if present, e.g., "289.20 - Stable" -> "289.20")
-            let clean_version = v.split(" - ").next().unwrap_or(v).trim();
-            let version_part = sanitize(clean_version);
-            format!("{}_{}_{}_{}.apk", brand_part, app_part, channel_part, version_part)
+            with_error_status(Response::from_json(&response)?, &e)
         }
-        None => format!("{}_{}_{}.apk", brand_part, app_part, channel_part),
     }
 }
 
 #[utoipa::path(
-    get,
+    method(get, head),
     path = "/v1/apk/{package_name}/{channel}",
     params(
         ("package_name" = String, Path, description = "Android package name (e.g., com.discord)"),
-        ("channel" = String, Path, description = "Release channel: stable, beta, or alpha")
+        ("channel" = String, Path, description = "Release channel: stable, beta, or alpha"),
+        ("Range" = Option<String>, Header, description = "Bytes to download, e.g. `bytes=1048576-` to resume a download"),
+        ("If-Range" = Option<String>, Header, description = "`ETag` or `Last-Modified` of the partial download; the whole APK is sent if it changed since")
     ),
     responses(
         (status = 200, description = "APK file streamed successfully", content_type = "application/vnd.android.package-archive"),
+        (status = 206, description = "The requested range of the APK, see Content-Range", content_type = "application/vnd.android.package-archive"),
         (status = 400, description = "Invalid channel"),
         (status = 404, description = "App not found or no download URL available"),
+        (status = 416, description = "Range not satisfiable"),
+        (status = 429, description = "Rate limited by Google Play (see Retry-After)"),
         (status = 502, description = "Failed to fetch APK from upstream")
     ),
     tag = "Direct APK Download"
@@ -334,10 +624,15 @@ fn build_suggested_filename(
 /// The download starts immediately without buffering the entire file server-side.
 /// 
 /// Also supports version-specific downloads: `/v1/apk/{package_name}/{channel}/{version_code}`
+///
+/// `Range` and `If-Range` are passed on to Google Play, so interrupted downloads can resume.
+/// `HEAD` answers with the size and filename only.
 pub async fn proxy_download(
     package_name: String,
     channel: String,
     version_code: Option<i32>,
+    method: Method,
+    request_headers: &Headers,
     client_registry: SharedClientRegistry,
     brand_name: String,
 ) -> Result<Response> {
@@ -350,23 +645,19 @@ pub async fn proxy_download(
 
     // Get app details for filename
     let details_result = client_registry
-        .lock()
-        .expect("Failed to lock client registry")
-        .get_details_with_fallback(&package_name, parsed_channel)
+        .get_details_with_fallback(&package_name, &parsed_channel)
         .await;
 
     let (app_name, version_string) = match &details_result {
-        Ok(Some((_, details))) => {
-            let item = details.item.as_ref();
+        Ok(Some((_, fetched))) => {
+            let item = fetched.details.item.as_ref();
             let title = item.and_then(|i| i.title.clone());
             let app_details = item
                 .and_then(|i| i.details.as_ref())
                 .and_then(|d| d.app_details.as_ref());
             let ver_string = app_details.and_then(|a| a.version_string.clone());
             
-            let clean_name = title.map(|t| {
-                t.split(" - ").next().unwrap_or(&t).trim().to_string()
-            });
+            let clean_name = title.map(|t| app_name(&t).to_string());
             
             (clean_name, ver_string)
         }
@@ -375,16 +666,12 @@ pub async fn proxy_download(
 
     // Get download info
     let result = client_registry
-        .lock()
-        .expect("Failed to lock client registry")
-        .get_download_info(&package_name, parsed_channel, version_code)
+        .get_download_info(&package_name, &parsed_channel, version_code)
         .await;
 
     match result {
         Ok(Some((_, download_info))) => {
-            let (main_apk_url, _, _) = download_info;
-            
-            let download_url = match main_apk_url {
+            let download_url = match download_info.main_apk.download_url.as_deref() {
                 Some(url) => url,
                 None => {
                     return Ok(Response::error("No download URL available", 404)?);
@@ -392,53 +679,1268 @@ pub async fn proxy_download(
             };
 
             // Build the filename
-            let channel_display = match parsed_channel {
-                Channel::Stable => "Stable",
-                Channel::Beta => "Beta",
-                Channel::Alpha => "Alpha",
-            };
+            let channel_display = parsed_channel.display_name();
             
             let filename = build_suggested_filename(
                 &brand_name,
                 app_name.as_deref(),
-                channel_display,
+                &channel_display,
                 version_string.as_deref(),
             );
 
             // Fetch the APK from Google - streaming response
-            let fetch_request = Request::new(&download_url, Method::Get)?;
-            let apk_response = Fetch::Request(fetch_request).send().await?;
-            
-            if apk_response.status_code() != 200 {
+            let forwarded = forwarded_headers(|name| request_headers.get(name).ok().flatten());
+            let cookies = download_cookies(&download_info);
+            let apk_response =
+                fetch_download(download_url, &cookies, method.clone(), &forwarded).await?;
+
+            let upstream = apk_response.headers();
+            let Some(proxied) = proxy_response(
+                &method,
+                apk_response.status_code(),
+                |name| upstream.get(name).ok().flatten(),
+                &filename,
+                download_info.main_apk.size,
+            ) else {
                 return Ok(Response::error(
                     format!("Failed to fetch APK: HTTP {}", apk_response.status_code()),
                     502,
                 )?);
-            }
+            };
 
-            // Get the response body as a stream and pass through headers
             let headers = Headers::new();
-            headers.set("Content-Type", "application/vnd.android.package-archive")?;
-            headers.set(
-                "Content-Disposition",
-                &format!("attachment; filename=\"{}\"", filename),
-            )?;
-            
-            // Copy Content-Length from upstream if available
-            if let Ok(Some(content_length)) = apk_response.headers().get("Content-Length") {
-                headers.set("Content-Length", &content_length)?;
+            for (name, value) in &proxied.headers {
+                headers.set(name, value)?;
             }
-            headers.set("Cache-Control", "no-cache")?;
-
             // Stream the response body directly without buffering
-            Ok(apk_response.with_headers(headers))
+            let response = match proxied.body {
+                true => apk_response,
+                false => Response::empty()?,
+            };
+            Ok(response.with_status(proxied.status).with_headers(headers))
         }
         Ok(None) => {
             Ok(Response::error(format!("App '{}' not found", package_name), 404)?)
         }
+        Err(e) => with_error_status(Response::error(e.to_string(), 500)?, &e),
+    }
+}
+
+/// Upstream statuses passed on to the client: the whole APK, the requested range of it, or a
+/// range outside of it.
+fn is_proxied_status(status: u16) -> bool {
+    matches!(status, 200 | 206 | 416)
+}
+
+/// The request headers passed on to Google Play, so interrupted downloads can resume.
+fn forwarded_headers(request: impl Fn(&str) -> Option<String>) -> Vec<(&'static str, String)> {
+    ["Range", "If-Range"]
+        .into_iter()
+        .filter_map(|name| request(name).map(|value| (name, value)))
+        .collect()
+}
+
+/// How `proxy_download` answers.
+#[derive(Debug, PartialEq)]
+struct ProxiedResponse {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    /// Whether the upstream body is streamed to the client.
+    body: bool,
+}
+
+/// The answer to `method` for an APK that Google Play answered with `status` and `upstream`
+/// headers, or `None` for a status that is not passed on. Only the whole APK or a range of it
+/// is sent as the APK; a 416 keeps just its `Content-Range`, which says how large the APK is.
+/// `size` is what Google Play said the APK is, in case the download server does not.
+fn proxy_response(
+    method: &Method,
+    status: u16,
+    upstream: impl Fn(&str) -> Option<String>,
+    filename: &str,
+    size: Option<i64>,
+) -> Option<ProxiedResponse> {
+    if !is_proxied_status(status) {
+        return None;
+    }
+    let mut headers = Vec::new();
+    if status != 416 {
+        headers.push((
+            "Content-Type",
+            String::from("application/vnd.android.package-archive"),
+        ));
+        headers.push((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", filename),
+        ));
+    }
+    match (upstream("Content-Length"), status, size) {
+        (Some(content_length), _, _) => headers.push(("Content-Length", content_length)),
+        (None, 200, Some(size)) => headers.push(("Content-Length", size.to_string())),
+        _ => {}
+    }
+    // Ranges, and the validators clients send back as If-Range when resuming
+    for name in ["Content-Range", "ETag", "Last-Modified", "Accept-Ranges"] {
+        if let Some(value) = upstream(name) {
+            headers.push((name, value));
+        }
+    }
+    headers.push(("Cache-Control", String::from("no-cache")));
+    Some(ProxiedResponse {
+        status,
+        headers,
+        body: *method != Method::Head,
+    })
+}
+
+/// The `Cookie` header Google Play's download servers expect for `download_info`'s files.
+fn download_cookies(download_info: &gpapi::DownloadInfo) -> String {
+    download_info
+        .download_auth_cookies
+        .iter()
+        .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Request a file from Google Play's download servers, with the cookies the download needs and
+/// `extra_headers`.
+async fn fetch_download(
+    url: &str,
+    cookies: &str,
+    method: Method,
+    extra_headers: &[(&str, String)],
+) -> Result<Response> {
+    let headers = Headers::new();
+    if !cookies.is_empty() {
+        headers.set("Cookie", cookies)?;
+    }
+    for (name, value) in extra_headers {
+        headers.set(name, value)?;
+    }
+    let mut init = RequestInit::new();
+    init.with_method(method).with_headers(headers);
+    let request = Request::new_with_init(url, &init)?;
+    Fetch::Request(request).send().await
+}
+
+#[utoipa::path(
+    method(get, post),
+    path = "/v1/bundle/{package_name}/{channel}",
+    params(
+        ("package_name" = String, Path, description = "Android package name (e.g., com.discord)"),
+        ("channel" = String, Path, description = "Release channel: stable, beta, or alpha"),
+        ("format" = Option<String>, Query, description = "`apks` (bundletool, with toc.pb) or `xapk` (with manifest.json); apks by default"),
+        ("abi" = Option<String>, Query, description = "Only the splits for these ABIs, most preferred first, e.g. `arm64_v8a,armeabi_v7a`"),
+        ("dpi" = Option<String>, Query, description = "Only the density splits closest to this density, e.g. `xxhdpi` or `480`"),
+        ("lang" = Option<String>, Query, description = "Only the language splits for these languages, e.g. `en,de`")
+    ),
+    request_body(content = Option<DeviceSpecRequest>, description = "POST only: a bundletool `device-spec.json` to select splits for, instead of the query parameters"),
+    responses(
+        (status = 200, description = "Zip of the base APK, its splits and OBBs, streamed as it is assembled", content_type = "application/zip"),
+        (status = 400, description = "Invalid channel, format or device spec"),
+        (status = 404, description = "App not found or no download URL available"),
+        (status = 422, description = "No native code for the device's ABIs"),
+        (status = 429, description = "Rate limited by Google Play (see Retry-After)"),
+        (status = 502, description = "Failed to fetch the base APK from upstream")
+    ),
+    tag = "Direct APK Download"
+)]
+/// Stream the base APK, splits and expansion files as one archive
+///
+/// The archive is zipped on the fly, one file after the other, without buffering any of them.
+/// Also supports version-specific downloads: `/v1/bundle/{package_name}/{channel}/{version_code}`
+///
+/// `abi`, `dpi` and `lang`, or a POSTed bundletool `device-spec.json`, keep only the splits
+/// that device installs.
+pub async fn get_bundle(
+    package_name: String,
+    channel: String,
+    version_code: Option<i32>,
+    params: HashMap<String, String>,
+    device_spec: Option<String>,
+    client_registry: SharedClientRegistry,
+    brand_name: String,
+) -> Result<Response> {
+    let parsed_channel = match Channel::from_str(&channel) {
+        Ok(ch) => ch,
+        Err(e) => return bad_request(e),
+    };
+    let format = match BundleFormat::from_query(params.get("format").map(String::as_str)) {
+        Ok(format) => format,
+        Err(e) => return bad_request(e),
+    };
+    let device = match requested_device(&params, device_spec.as_deref()) {
+        Ok(device) => device,
+        Err(e) => return bad_request(e),
+    };
+
+    // The manifests describe the version the details list, unless another one was asked for
+    let details_result = client_registry
+        .get_details_with_fallback(&package_name, &parsed_channel)
+        .await;
+    let mut app = BundleApp::default();
+    if let Ok(Some((_, fetched))) = &details_result {
+        let item = fetched.details.item.as_ref();
+        let app_details = item
+            .and_then(|i| i.details.as_ref())
+            .and_then(|d| d.app_details.as_ref());
+        app.name = item
+            .and_then(|i| i.title.as_deref())
+            .map(|t| app_name(t).to_string());
+        if let Some(details) = app_details {
+            app.version_code = details.version_code.unwrap_or(0);
+            app.version_name = details.version_string.clone();
+            app.target_sdk_version = details.target_sdk_version;
+            app.permissions = details.permission.clone();
+        }
+    }
+    if let Some(version_code) = version_code.filter(|code| *code != app.version_code) {
+        app.version_code = version_code;
+        app.version_name = None;
+    }
+
+    let mut download_info = match client_registry
+        .get_download_info(&package_name, &parsed_channel, version_code)
+        .await
+    {
+        Ok(Some((_, download_info))) => download_info,
+        Ok(None) => return error_response(404, format!("App '{}' not found", package_name)),
+        Err(e) => {
+            let response = ApiResponse::<()> {
+                success: false,
+                data: None,
+                error: Some(e.to_string()),
+            };
+            return with_error_status(Response::from_json(&response)?, &e);
+        }
+    };
+    if let Some(device) = &device {
+        match device.select(std::mem::take(&mut download_info.splits)) {
+            Ok(splits) => download_info.splits = splits,
+            Err(e) => return error_response(422, e),
+        }
+    }
+    let entries = match bundle::layout(format, &package_name, &app, &download_info) {
+        Ok(entries) => entries,
+        Err(e) => return error_response(404, e),
+    };
+    let cookies = download_cookies(&download_info);
+
+    // Fetched up front, so an expired or refused download fails before the archive starts
+    let main_url = download_info.main_apk.download_url.clone().unwrap_or_default();
+    let main_response = fetch_download(&main_url, &cookies, Method::Get, &[]).await?;
+    if main_response.status_code() != 200 {
+        return error_response(
+            502,
+            format!("Failed to fetch APK: HTTP {}", main_response.status_code()),
+        );
+    }
+    let mut prefetched = Some((main_url, main_response));
+
+    let archive = zip_stream(entries, move |source| {
+        let is_main = matches!(&source, BundleSource::Download(url)
+            if prefetched.as_ref().is_some_and(|(main, _)| main == url));
+        let prefetched = if is_main {
+            prefetched.take().map(|(_, response)| response)
+        } else {
+            None
+        };
+        let cookies = cookies.clone();
+        async move {
+            let chunks: ByteChunks<Error> = match source {
+                BundleSource::Inline(bytes) => Box::pin(stream::once(async { Ok(bytes) })),
+                BundleSource::Download(url) => {
+                    let mut response = match prefetched {
+                        Some(response) => response,
+                        None => fetch_download(&url, &cookies, Method::Get, &[]).await?,
+                    };
+                    if response.status_code() != 200 {
+                        return Err(Error::RustError(format!(
+                            "Failed to fetch bundle file: HTTP {}",
+                            response.status_code()
+                        )));
+                    }
+                    Box::pin(response.stream()?)
+                }
+            };
+            Ok(chunks)
+        }
+    });
+
+    let filename = build_suggested_filename(
+        &brand_name,
+        app.name.as_deref(),
+        &parsed_channel.display_name(),
+        app.version_name.as_deref(),
+    );
+    let filename = format!(
+        "{}.{}",
+        filename.strip_suffix(".apk").unwrap_or(&filename),
+        format.extension()
+    );
+    let headers = Headers::new();
+    headers.set("Content-Type", "application/zip")?;
+    headers.set(
+        "Content-Disposition",
+        &format!("attachment; filename=\"{}\"", filename),
+    )?;
+    headers.set("Cache-Control", "no-cache")?;
+    Ok(Response::from_stream(archive)?.with_headers(headers))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/search",
+    params(
+        ("q" = String, Query, description = "Search terms, e.g. an app name", example = "discord"),
+        ("page" = Option<String>, Query, description = "Cursor from a previous response's `next_page`; `q` is ignored when set"),
+        ("limit" = Option<usize>, Query, description = "Maximum number of results to return")
+    ),
+    responses(
+        (status = 200, description = "Search results retrieved successfully", body = ApiResponse<AppPage>),
+        (status = 400, description = "Missing query or invalid limit", body = ApiResponse<String>),
+        (status = 429, description = "Rate limited by Google Play (see Retry-After)", body = ApiResponse<AppPage>),
+        (status = 500, description = "Internal server error", body = ApiResponse<AppPage>)
+    ),
+    tag = "Search"
+)]
+pub async fn search(
+    params: HashMap<String, String>,
+    client_registry: SharedClientRegistry,
+) -> Result<Response> {
+    let (page, limit) = match page_params(&params) {
+        Ok(page_params) => page_params,
+        Err(e) => return bad_request(e),
+    };
+    let query = params.get("q").map(|q| q.trim()).unwrap_or_default();
+    if query.is_empty() && page.is_none() {
+        return bad_request("Missing search query 'q'".to_string());
+    }
+
+    let result = client_registry.search(query, SearchOptions { page, limit }).await;
+
+    match result {
+        Ok(page) => {
+            let response = ApiResponse {
+                success: true,
+                data: Some(AppPage::from(page)),
+                error: None,
+            };
+            Ok(Response::from_json(&response)?)
+        }
+        Err(e) => {
+            let response = ApiResponse::<AppPage> {
+                success: false,
+                data: None,
+                error: Some(e.to_string()),
+            };
+            with_error_status(Response::from_json(&response)?, &e)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/charts/{chart}",
+    params(
+        ("chart" = String, Path, description = "Chart: top_free, top_paid, top_grossing or trending", example = "top_free"),
+        ("category" = Option<String>, Query, description = "Category id from /v1/categories; all apps by default", example = "GAME_PUZZLE"),
+        ("page" = Option<String>, Query, description = "Cursor from a previous response's `next_page`"),
+        ("limit" = Option<usize>, Query, description = "Maximum number of results to return")
+    ),
+    responses(
+        (status = 200, description = "Chart retrieved successfully", body = ApiResponse<AppPage>),
+        (status = 400, description = "Unknown chart or invalid limit", body = ApiResponse<String>),
+        (status = 429, description = "Rate limited by Google Play (see Retry-After)", body = ApiResponse<AppPage>),
+        (status = 500, description = "Internal server error", body = ApiResponse<AppPage>)
+    ),
+    tag = "Browse"
+)]
+pub async fn get_chart(
+    chart: String,
+    params: HashMap<String, String>,
+    client_registry: SharedClientRegistry,
+) -> Result<Response> {
+    let chart = match chart.parse::<Chart>() {
+        Ok(chart) => chart,
+        Err(e) => return bad_request(e),
+    };
+    let (page, limit) = match page_params(&params) {
+        Ok(page_params) => page_params,
+        Err(e) => return bad_request(e),
+    };
+    let options = ChartOptions {
+        category: params.get("category").filter(|c| !c.is_empty()).cloned(),
+        page,
+        limit,
+    };
+
+    let result = client_registry.top_chart(chart, options).await;
+
+    match result {
+        Ok(page) => {
+            let response = ApiResponse {
+                success: true,
+                data: Some(AppPage::from(page)),
+                error: None,
+            };
+            Ok(Response::from_json(&response)?)
+        }
+        Err(e) => {
+            let response = ApiResponse::<AppPage> {
+                success: false,
+                data: None,
+                error: Some(e.to_string()),
+            };
+            with_error_status(Response::from_json(&response)?, &e)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/categories",
+    responses(
+        (status = 200, description = "Categories retrieved successfully", body = ApiResponse<Vec<Category>>),
+        (status = 429, description = "Rate limited by Google Play (see Retry-After)", body = ApiResponse<Vec<Category>>),
+        (status = 500, description = "Internal server error", body = ApiResponse<Vec<Category>>)
+    ),
+    tag = "Browse"
+)]
+pub async fn get_categories(client_registry: SharedClientRegistry) -> Result<Response> {
+    let result = client_registry.categories().await;
+
+    match result {
+        Ok(categories) => {
+            let response = ApiResponse {
+                success: true,
+                data: Some(
+                    categories
+                        .into_iter()
+                        .map(Category::from)
+                        .collect::<Vec<_>>(),
+                ),
+                error: None,
+            };
+            Ok(Response::from_json(&response)?)
+        }
         Err(e) => {
-            Ok(Response::error(format!("Error: {}", e), 500)?)
+            let response = ApiResponse::<Vec<Category>> {
+                success: false,
+                data: None,
+                error: Some(e.to_string()),
+            };
+            with_error_status(Response::from_json(&response)?, &e)
         }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/v1/reviews/{package_name}",
+    params(
+        ("package_name" = String, Path, description = "Android package name (e.g., com.discord)"),
+        ("sort" = Option<String>, Query, description = "most_relevant (default), newest or rating"),
+        ("device" = Option<bool>, Query, description = "Only reviews from devices like the configured one"),
+        ("rating" = Option<u8>, Query, description = "Only reviews with this many stars (1-5)"),
+        ("offset" = Option<usize>, Query, description = "Reviews to skip, e.g. a previous response's `next_offset`")
+    ),
+    responses(
+        (status = 200, description = "Stable channel reviews retrieved successfully", body = ApiResponse<ReviewPage>),
+        (status = 400, description = "Invalid parameters", body = ApiResponse<String>),
+        (status = 429, description = "Rate limited by Google Play (see Retry-After)", body = ApiResponse<ReviewPage>),
+        (status = 500, description = "Internal server error", body = ApiResponse<ReviewPage>)
+    ),
+    tag = "Reviews"
+)]
+pub async fn get_reviews(
+    package_name: String,
+    params: HashMap<String, String>,
+    client_registry: SharedClientRegistry,
+) -> Result<Response> {
+    reviews_response(package_name, Channel::stable(), params, client_registry).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/reviews/{package_name}/{channel}",
+    params(
+        ("package_name" = String, Path, description = "Android package name (e.g., com.discord)"),
+        ("channel" = String, Path, description = "Release channel", example = "beta"),
+        ("sort" = Option<String>, Query, description = "most_relevant (default), newest or rating"),
+        ("device" = Option<bool>, Query, description = "Only reviews from devices like the configured one"),
+        ("rating" = Option<u8>, Query, description = "Only reviews with this many stars (1-5)"),
+        ("offset" = Option<usize>, Query, description = "Reviews to skip, e.g. a previous response's `next_offset`")
+    ),
+    responses(
+        (status = 200, description = "Reviews retrieved successfully", body = ApiResponse<ReviewPage>),
+        (status = 400, description = "Invalid channel or parameters", body = ApiResponse<String>),
+        (status = 404, description = "Channel not available for this app", body = ApiResponse<ReviewPage>),
+        (status = 429, description = "Rate limited by Google Play (see Retry-After)", body = ApiResponse<ReviewPage>),
+        (status = 500, description = "Internal server error", body = ApiResponse<ReviewPage>)
+    ),
+    tag = "Reviews"
+)]
+pub async fn get_reviews_single(
+    package_name: String,
+    channel: String,
+    params: HashMap<String, String>,
+    client_registry: SharedClientRegistry,
+) -> Result<Response> {
+    let channel = match Channel::from_str(&channel) {
+        Ok(ch) => ch,
+        Err(e) => return bad_request(e),
+    };
+    reviews_response(package_name, channel, params, client_registry).await
+}
+
+async fn reviews_response(
+    package_name: String,
+    channel: Channel,
+    params: HashMap<String, String>,
+    client_registry: SharedClientRegistry,
+) -> Result<Response> {
+    let sort = match params.get("sort").map(|sort| sort.parse::<ReviewSort>()) {
+        Some(Ok(sort)) => sort,
+        Some(Err(e)) => return bad_request(e),
+        None => ReviewSort::default(),
+    };
+    let filter_by_device = matches!(params.get("device").map(String::as_str), Some("true" | "1"));
+    let rating = match params.get("rating").map(|rating| rating.parse::<u8>()) {
+        Some(Ok(rating @ 1..=5)) => Some(rating),
+        Some(_) => return bad_request("'rating' must be between 1 and 5".to_string()),
+        None => None,
+    };
+    let offset = match params.get("offset").map(|offset| offset.parse::<usize>()) {
+        Some(Ok(offset)) => offset,
+        Some(Err(_)) => return bad_request("'offset' must be a non-negative integer".to_string()),
+        None => 0,
+    };
+
+    let result = client_registry
+        .get_reviews(&package_name, &channel, sort, filter_by_device, rating, offset)
+        .await;
+
+    match result {
+        Ok(page) => {
+            let response = ApiResponse {
+                success: true,
+                data: Some(ReviewPage::from(page)),
+                error: None,
+            };
+            Ok(Response::from_json(&response)?)
+        }
+        Err(e) => {
+            let response = ApiResponse::<ReviewPage> {
+                success: false,
+                data: None,
+                error: Some(e.to_string()),
+            };
+            with_error_status(Response::from_json(&response)?, &e)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/history/{package_name}",
+    params(
+        ("package_name" = String, Path, description = "Android package name (e.g., com.discord)"),
+        ("channel" = Option<String>, Query, description = "Only versions seen on this channel, all channels by default")
+    ),
+    responses(
+        (status = 200, description = "Versions seen so far, newest first", body = ApiResponse<VersionHistory>),
+        (status = 400, description = "Invalid channel", body = ApiResponse<String>),
+        (status = 500, description = "Internal server error", body = ApiResponse<VersionHistory>)
+    ),
+    tag = "History"
+)]
+pub async fn get_history(
+    package_name: String,
+    params: HashMap<String, String>,
+    client_registry: SharedClientRegistry,
+) -> Result<Response> {
+    let channel = match params.get("channel").filter(|channel| !channel.is_empty()) {
+        Some(channel) => match Channel::from_str(channel) {
+            Ok(channel) => Some(channel),
+            Err(e) => return bad_request(e),
+        },
+        None => None,
+    };
+
+    match client_registry
+        .version_history(&package_name, channel.as_ref())
+        .await
+    {
+        Ok(records) => {
+            let response = ApiResponse {
+                success: true,
+                data: Some(VersionHistory {
+                    package_name,
+                    versions: records.into_iter().map(HistoryEntry::from).collect(),
+                }),
+                error: None,
+            };
+            Ok(Response::from_json(&response)?)
+        }
+        Err(e) => {
+            console_log!("Failed to read version history of {}: {}", package_name, e);
+            error_response(500, "Failed to read version history".to_string())
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/feed/{package_name}/{feed}",
+    params(
+        ("package_name" = String, Path, description = "Android package name (e.g., com.discord)"),
+        ("feed" = String, Path, description = "Release channel followed by `.atom` or `.rss`", example = "beta.atom")
+    ),
+    responses(
+        (status = 200, description = "Atom or RSS feed of the versions seen so far, newest first, linking to their APKs",
+         content_type = "application/atom+xml", body = String),
+        (status = 400, description = "Invalid channel", body = ApiResponse<String>),
+        (status = 404, description = "App not found, or neither .atom nor .rss", body = ApiResponse<String>),
+        (status = 429, description = "Rate limited by Google Play (see Retry-After)", body = ApiResponse<String>),
+        (status = 500, description = "Internal server error", body = ApiResponse<String>)
+    ),
+    tag = "History"
+)]
+pub async fn get_feed(
+    package_name: String,
+    feed: String,
+    base_url: String,
+    client_registry: SharedClientRegistry,
+) -> Result<Response> {
+    let Some((channel, format)) = FeedFormat::split_extension(&feed) else {
+        return error_response(404, "Feeds end in .atom or .rss".to_string());
+    };
+    let channel = match Channel::from_str(channel) {
+        Ok(ch) => ch,
+        Err(e) => return bad_request(e),
+    };
+
+    // Fetching the details records the current version, so the feed is never behind the listing
+    let details = match client_registry.get_details_with_fallback(&package_name, &channel).await {
+        Ok(Some((_, fetched))) => fetched.details,
+        Ok(None) => return error_response(404, format!("App '{}' not found", package_name)),
+        Err(e) => {
+            let response = ApiResponse::<()> {
+                success: false,
+                data: None,
+                error: Some(e.to_string()),
+            };
+            return with_error_status(Response::from_json(&response)?, &e);
+        }
+    };
+    let mut versions = match client_registry
+        .version_history(&package_name, Some(&channel))
+        .await
+    {
+        Ok(versions) => versions,
+        Err(e) => {
+            console_log!("Failed to read version history of {}: {}", package_name, e);
+            return error_response(500, "Failed to read version history".to_string());
+        }
+    };
+    let now = now_secs();
+    if let Some(current) = VersionRecord::from_details(&package_name, &channel, &details, now) {
+        if !versions.iter().any(|version| version.version_code == current.version_code) {
+            versions.insert(0, current);
+        }
+    }
+    versions.truncate(FEED_ENTRIES);
+
+    let title = details.item.as_ref().and_then(|item| item.title.as_deref());
+    let xml = Feed {
+        package_name: &package_name,
+        channel: &channel,
+        app_name: title.map(app_name),
+        base_url: &base_url,
+        versions: &versions,
+    }
+    .render(format);
+
+    let headers = Headers::new();
+    headers.set("Content-Type", format.content_type())?;
+    Ok(Response::ok(xml)?.with_headers(headers))
+}
+
+/// Every webhook route checks the admin token first, then that the `WEBHOOKS` namespace is bound.
+fn check_webhooks<'a>(
+    authorization: Option<&str>,
+    admin_token: Option<&str>,
+    store: Option<&'a dyn WebhookStore>,
+) -> std::result::Result<&'a dyn WebhookStore, (u16, String)> {
+    check_admin(authorization, admin_token)?;
+    store.ok_or_else(|| {
+        (
+            503,
+            format!(
+                "Webhooks are disabled, bind the '{}' KV namespace to enable them",
+                WEBHOOKS_KV_BINDING
+            ),
+        )
+    })
+}
+
+/// The channel named `name`, if it is one of the `configured` channels.
+fn configured_channel(name: &str, configured: &[Channel]) -> std::result::Result<Channel, String> {
+    let channel = Channel::from_str(name)?;
+    if !configured.contains(&channel) {
+        let names: Vec<String> = configured.iter().map(Channel::to_string).collect();
+        return Err(format!(
+            "Channel '{}' is not configured, expected one of {}",
+            channel,
+            names.join(", ")
+        ));
+    }
+    Ok(channel)
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/webhooks",
+    request_body = WebhookRequest,
+    responses(
+        (status = 201, description = "Subscribed, or replaced the secret of an existing subscription. The package and channel are added to the watchlist", body = ApiResponse<WebhookInfo>),
+        (status = 400, description = "Invalid URL, package name or secret, or a channel that is not configured", body = ApiResponse<String>),
+        (status = 401, description = "Missing or invalid admin token", body = ApiResponse<String>),
+        (status = 403, description = "Admin routes are disabled", body = ApiResponse<String>),
+        (status = 503, description = "No WEBHOOKS or WATCHLIST KV namespace is bound", body = ApiResponse<String>)
+    ),
+    security(("admin_token" = [])),
+    tag = "Webhooks"
+)]
+pub async fn create_webhook(
+    body: String,
+    authorization: Option<String>,
+    admin_token: Option<String>,
+    store: Option<&dyn WebhookStore>,
+    watch_store: Option<&dyn WatchStore>,
+    config: &Config,
+) -> Result<Response> {
+    let store = match check_webhooks(authorization.as_deref(), admin_token.as_deref(), store) {
+        Ok(store) => store,
+        Err((status, error)) => return error_response(status, error),
+    };
+    // Only watched packages are polled, so without a watchlist the webhook would never fire
+    let Some(watch_store) = watch_store else {
+        return error_response(
+            503,
+            format!(
+                "Webhooks only fire for watched packages, bind the '{}' KV namespace to enable them",
+                WATCHLIST_KV_BINDING
+            ),
+        );
+    };
+    let request: WebhookRequest = match serde_json::from_str(&body) {
+        Ok(request) => request,
+        Err(e) => return bad_request(format!("Invalid webhook: {}", e)),
+    };
+    let webhook = match Webhook::new(
+        &request.url,
+        &request.package_name,
+        &request.channel,
+        &request.secret,
+    ) {
+        Ok(webhook) => webhook,
+        Err(e) => return bad_request(e),
+    };
+
+    let configured: Vec<Channel> = config
+        .channels
+        .iter()
+        .map(|account| account.channel.clone())
+        .collect();
+    // The watcher only polls configured channels, so any other would never fire
+    let channel = match configured_channel(&webhook.channel, &configured) {
+        Ok(channel) => channel,
+        Err(e) => return bad_request(e),
+    };
+    if let Err(e) = watch_store.watch(&webhook.package_name, &channel).await {
+        console_log!("Failed to watch {}: {}", webhook.package_name, e);
+        return error_response(
+            500,
+            "Failed to add the package to the watchlist".to_string(),
+        );
+    }
+    if let Err(e) = store.save(&webhook).await {
+        console_log!("Failed to save webhook {}: {}", webhook.id, e);
+        return error_response(500, "Failed to save webhook".to_string());
+    }
+    let response = ApiResponse {
+        success: true,
+        data: Some(WebhookInfo::from(&webhook)),
+        error: None,
+    };
+    Ok(Response::from_json(&response)?.with_status(201))
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/webhooks",
+    params(
+        ("package_name" = Option<String>, Query, description = "Only list the webhooks of this package"),
+        ("channel" = Option<String>, Query, description = "Only list the webhooks of this channel")
+    ),
+    responses(
+        (status = 200, description = "Webhook subscriptions, without their secrets", body = ApiResponse<Vec<WebhookInfo>>),
+        (status = 400, description = "Invalid channel", body = ApiResponse<String>),
+        (status = 401, description = "Missing or invalid admin token", body = ApiResponse<String>),
+        (status = 403, description = "Admin routes are disabled", body = ApiResponse<String>),
+        (status = 503, description = "No WEBHOOKS KV namespace is bound", body = ApiResponse<String>)
+    ),
+    security(("admin_token" = [])),
+    tag = "Webhooks"
+)]
+pub async fn list_webhooks(
+    params: HashMap<String, String>,
+    authorization: Option<String>,
+    admin_token: Option<String>,
+    store: Option<&dyn WebhookStore>,
+) -> Result<Response> {
+    let store = match check_webhooks(authorization.as_deref(), admin_token.as_deref(), store) {
+        Ok(store) => store,
+        Err((status, error)) => return error_response(status, error),
+    };
+    let package_name = params.get("package_name").filter(|name| !name.is_empty());
+    let channel = match params.get("channel").filter(|channel| !channel.is_empty()) {
+        Some(channel) => match Channel::from_str(channel) {
+            Ok(channel) => Some(channel),
+            Err(e) => return bad_request(e),
+        },
+        None => None,
+    };
+
+    let target = package_name.zip(channel.as_ref());
+    let webhooks = match store
+        .list(target.map(|(package_name, channel)| (package_name.as_str(), channel)))
+        .await
+    {
+        Ok(webhooks) => webhooks,
+        Err(e) => {
+            console_log!("Failed to list webhooks: {}", e);
+            return error_response(500, "Failed to list webhooks".to_string());
+        }
+    };
+    let data: Vec<WebhookInfo> = webhooks
+        .iter()
+        .filter(|webhook| package_name.is_none_or(|name| webhook.package_name == *name))
+        .filter(|webhook| {
+            channel
+                .as_ref()
+                .is_none_or(|channel| webhook.channel == channel.to_string())
+        })
+        .map(WebhookInfo::from)
+        .collect();
+    let response = ApiResponse {
+        success: true,
+        data: Some(data),
+        error: None,
+    };
+    Response::from_json(&response)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/webhooks/{id}",
+    params(
+        ("id" = String, Path, description = "Webhook id, as returned when subscribing")
+    ),
+    responses(
+        (status = 200, description = "Unsubscribed", body = ApiResponse<WebhookInfo>),
+        (status = 401, description = "Missing or invalid admin token", body = ApiResponse<String>),
+        (status = 403, description = "Admin routes are disabled", body = ApiResponse<String>),
+        (status = 404, description = "No webhook with this id", body = ApiResponse<String>),
+        (status = 503, description = "No WEBHOOKS KV namespace is bound", body = ApiResponse<String>)
+    ),
+    security(("admin_token" = [])),
+    tag = "Webhooks"
+)]
+pub async fn delete_webhook(
+    id: String,
+    authorization: Option<String>,
+    admin_token: Option<String>,
+    store: Option<&dyn WebhookStore>,
+) -> Result<Response> {
+    let store = match check_webhooks(authorization.as_deref(), admin_token.as_deref(), store) {
+        Ok(store) => store,
+        Err((status, error)) => return error_response(status, error),
+    };
+
+    match store.remove(&id).await {
+        Ok(Some(webhook)) => {
+            let response = ApiResponse {
+                success: true,
+                data: Some(WebhookInfo::from(&webhook)),
+                error: None,
+            };
+            Ok(Response::from_json(&response)?)
+        }
+        Ok(None) => error_response(404, format!("No webhook with id '{}'", id)),
+        Err(e) => {
+            console_log!("Failed to delete webhook {}: {}", id, e);
+            error_response(500, "Failed to delete webhook".to_string())
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/admin/testing/{package_name}/{channel}",
+    params(
+        ("package_name" = String, Path, description = "Android package name (e.g., com.discord)"),
+        ("channel" = String, Path, description = "beta or alpha", example = "beta")
+    ),
+    responses(
+        (status = 200, description = "Testing program status of the channel's account", body = ApiResponse<TestingProgram>),
+        (status = 400, description = "Invalid channel", body = ApiResponse<String>),
+        (status = 401, description = "Missing or invalid admin token", body = ApiResponse<String>),
+        (status = 403, description = "Admin routes are disabled", body = ApiResponse<String>),
+        (status = 404, description = "App not found", body = ApiResponse<TestingProgram>),
+        (status = 500, description = "Internal server error", body = ApiResponse<TestingProgram>)
+    ),
+    security(("admin_token" = [])),
+    tag = "Admin"
+)]
+pub async fn get_testing_program(
+    package_name: String,
+    channel: String,
+    authorization: Option<String>,
+    admin_token: Option<String>,
+    client_registry: SharedClientRegistry,
+) -> Result<Response> {
+    if let Err((status, error)) = check_admin(authorization.as_deref(), admin_token.as_deref()) {
+        return error_response(status, error);
+    }
+    let channel = match testing_channel(&channel) {
+        Ok(ch) => ch,
+        Err(e) => return bad_request(e),
+    };
+
+    let result = client_registry.testing_program_status(&package_name, &channel).await;
+
+    match result {
+        Ok(status) => {
+            let response = ApiResponse {
+                success: true,
+                data: Some(TestingProgram::new(channel.to_string(), status)),
+                error: None,
+            };
+            Ok(Response::from_json(&response)?)
+        }
+        Err(e) => {
+            let response = ApiResponse::<TestingProgram> {
+                success: false,
+                data: None,
+                error: Some(e.to_string()),
+            };
+            with_error_status(Response::from_json(&response)?, &e)
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/admin/testing/{package_name}/{channel}",
+    params(
+        ("package_name" = String, Path, description = "Android package name (e.g., com.discord)"),
+        ("channel" = String, Path, description = "beta or alpha", example = "beta")
+    ),
+    responses(
+        (status = 200, description = "The channel's account joined the testing program", body = ApiResponse<TestingProgramEnrollment>),
+        (status = 400, description = "Invalid channel", body = ApiResponse<String>),
+        (status = 401, description = "Missing or invalid admin token", body = ApiResponse<String>),
+        (status = 403, description = "Admin routes are disabled", body = ApiResponse<String>),
+        (status = 502, description = "Google Play did not confirm the enrollment", body = ApiResponse<TestingProgramEnrollment>)
+    ),
+    security(("admin_token" = [])),
+    tag = "Admin"
+)]
+pub async fn join_testing_program(
+    package_name: String,
+    channel: String,
+    authorization: Option<String>,
+    admin_token: Option<String>,
+    client_registry: SharedClientRegistry,
+) -> Result<Response> {
+    enrollment_response(
+        package_name,
+        channel,
+        true,
+        authorization,
+        admin_token,
+        client_registry,
+    )
+    .await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v1/admin/testing/{package_name}/{channel}",
+    params(
+        ("package_name" = String, Path, description = "Android package name (e.g., com.discord)"),
+        ("channel" = String, Path, description = "beta or alpha", example = "beta")
+    ),
+    responses(
+        (status = 200, description = "The channel's account left the testing program", body = ApiResponse<TestingProgramEnrollment>),
+        (status = 400, description = "Invalid channel", body = ApiResponse<String>),
+        (status = 401, description = "Missing or invalid admin token", body = ApiResponse<String>),
+        (status = 403, description = "Admin routes are disabled", body = ApiResponse<String>),
+        (status = 502, description = "Google Play did not confirm leaving the program", body = ApiResponse<TestingProgramEnrollment>)
+    ),
+    security(("admin_token" = [])),
+    tag = "Admin"
+)]
+pub async fn leave_testing_program(
+    package_name: String,
+    channel: String,
+    authorization: Option<String>,
+    admin_token: Option<String>,
+    client_registry: SharedClientRegistry,
+) -> Result<Response> {
+    enrollment_response(
+        package_name,
+        channel,
+        false,
+        authorization,
+        admin_token,
+        client_registry,
+    )
+    .await
+}
+
+async fn enrollment_response(
+    package_name: String,
+    channel: String,
+    subscribe: bool,
+    authorization: Option<String>,
+    admin_token: Option<String>,
+    client_registry: SharedClientRegistry,
+) -> Result<Response> {
+    if let Err((status, error)) = check_admin(authorization.as_deref(), admin_token.as_deref()) {
+        return error_response(status, error);
+    }
+    let channel = match testing_channel(&channel) {
+        Ok(ch) => ch,
+        Err(e) => return bad_request(e),
+    };
+
+    let result = client_registry.set_testing_program(&package_name, &channel, subscribe).await;
+
+    match result {
+        Ok(true) => {
+            let response = ApiResponse {
+                success: true,
+                data: Some(TestingProgramEnrollment {
+                    channel: channel.to_string(),
+                    subscribed: subscribe,
+                }),
+                error: None,
+            };
+            Ok(Response::from_json(&response)?)
+        }
+        Ok(false) => error_response(
+            502,
+            format!(
+                "Google Play did not confirm the {} account {} the testing program of '{}'",
+                channel,
+                if subscribe { "joining" } else { "leaving" },
+                package_name
+            ),
+        ),
+        Err(e) => {
+            let response = ApiResponse::<TestingProgramEnrollment> {
+                success: false,
+                data: None,
+                error: Some(e.to_string()),
+            };
+            with_error_status(Response::from_json(&response)?, &e)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/config/check",
+    responses(
+        (status = 200, description = "What is misconfigured, if anything. Secrets are never included", body = ApiResponse<ConfigCheck>),
+        (status = 401, description = "Missing or invalid admin token", body = ApiResponse<String>),
+        (status = 403, description = "Admin routes are disabled", body = ApiResponse<String>)
+    ),
+    security(("admin_token" = [])),
+    tag = "Admin"
+)]
+pub fn check_config(
+    authorization: Option<String>,
+    admin_token: Option<String>,
+    config: &std::result::Result<Config, ConfigError>,
+) -> Result<Response> {
+    if let Err((status, error)) = check_admin(authorization.as_deref(), admin_token.as_deref()) {
+        return error_response(status, error);
+    }
+
+    let check = match config {
+        Ok(config) => ConfigCheck {
+            valid: true,
+            problems: Vec::new(),
+            channels: config
+                .channels
+                .iter()
+                .map(|account| ChannelCheck {
+                    name: account.channel.to_string(),
+                    device: account.device.clone(),
+                    locale: account.locale.clone(),
+                    email_secret: account.email_secret.clone(),
+                    token_secret: account.token_secret.clone(),
+                })
+                .collect(),
+        },
+        Err(e) => ConfigCheck {
+            valid: false,
+            problems: e.problems.clone(),
+            channels: Vec::new(),
+        },
+    };
+    let response = ApiResponse {
+        success: true,
+        data: Some(check),
+        error: None,
+    };
+    Response::from_json(&response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_admin_token() {
+        assert_eq!(check_admin(Some("Bearer secret"), None).unwrap_err().0, 403);
+        assert_eq!(check_admin(Some("Bearer "), Some("")).unwrap_err().0, 403);
+        assert!(check_admin(Some("Bearer secret"), Some("secret")).is_ok());
+        assert_eq!(check_admin(None, Some("secret")).unwrap_err().0, 401);
+        assert_eq!(check_admin(Some("secret"), Some("secret")).unwrap_err().0, 401);
+        assert_eq!(check_admin(Some("Bearer secreT"), Some("secret")).unwrap_err().0, 401);
+        assert_eq!(check_admin(Some("Bearer secret2"), Some("secret")).unwrap_err().0, 401);
+    }
+
+    #[test]
+    fn accepts_only_configured_channels() {
+        let configured = [Channel::stable(), Channel::from_str("internal").unwrap()];
+        assert_eq!(
+            configured_channel("Internal", &configured).unwrap(),
+            configured[1]
+        );
+        assert_eq!(
+            configured_channel("beta", &configured).unwrap_err(),
+            "Channel 'beta' is not configured, expected one of stable, internal"
+        );
+        assert!(configured_channel("a b", &configured).is_err());
+    }
+
+    #[test]
+    fn proxies_ranges() {
+        assert!(is_proxied_status(200));
+        assert!(is_proxied_status(206));
+        assert!(is_proxied_status(416));
+        assert!(!is_proxied_status(403));
+        assert!(!is_proxied_status(500));
+    }
+
+    fn lookup<'a>(headers: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| {
+            headers
+                .iter()
+                .find(|(header, _)| header.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    fn header<'a>(proxied: &'a ProxiedResponse, name: &str) -> Option<&'a str> {
+        proxied
+            .headers
+            .iter()
+            .find(|(header, _)| *header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn forwards_range_headers() {
+        let request = [
+            ("Range", "bytes=1048576-"),
+            ("If-Range", "\"abc\""),
+            ("Authorization", "Bearer secret"),
+        ];
+        assert_eq!(
+            forwarded_headers(lookup(&request)),
+            [
+                ("Range", String::from("bytes=1048576-")),
+                ("If-Range", String::from("\"abc\""))
+            ]
+        );
+        assert!(forwarded_headers(lookup(&[])).is_empty());
+    }
+
+    #[test]
+    fn passes_ranges_through() {
+        let upstream = [
+            ("Content-Length", "1000"),
+            ("Content-Range", "bytes 1000-1999/2000"),
+            ("ETag", "\"abc\""),
+            ("Last-Modified", "Mon, 21 Jul 2025 00:00:00 GMT"),
+            ("Accept-Ranges", "bytes"),
+        ];
+        let proxied =
+            proxy_response(&Method::Get, 206, lookup(&upstream), "App.apk", Some(2000)).unwrap();
+        assert_eq!(proxied.status, 206);
+        assert!(proxied.body);
+        for (name, value) in upstream {
+            assert_eq!(header(&proxied, name), Some(value));
+        }
+        assert_eq!(
+            header(&proxied, "Content-Type"),
+            Some("application/vnd.android.package-archive")
+        );
+        assert_eq!(
+            header(&proxied, "Content-Disposition"),
+            Some("attachment; filename=\"App.apk\"")
+        );
+
+        assert!(proxy_response(&Method::Get, 403, lookup(&upstream), "App.apk", None).is_none());
+    }
+
+    #[test]
+    fn passes_unsatisfiable_ranges_through() {
+        let upstream = [("Content-Range", "bytes */2000")];
+        let proxied =
+            proxy_response(&Method::Get, 416, lookup(&upstream), "App.apk", Some(2000)).unwrap();
+        assert_eq!(proxied.status, 416);
+        assert_eq!(header(&proxied, "Content-Range"), Some("bytes */2000"));
+        assert_eq!(header(&proxied, "Content-Type"), None);
+        assert_eq!(header(&proxied, "Content-Disposition"), None);
+        assert_eq!(header(&proxied, "Content-Length"), None);
+        // Only claimed when the download server does
+        assert_eq!(header(&proxied, "Accept-Ranges"), None);
+    }
+
+    #[test]
+    fn answers_head_without_body() {
+        let proxied = proxy_response(&Method::Head, 200, lookup(&[]), "App.apk", None).unwrap();
+        assert!(!proxied.body);
+        assert_eq!(header(&proxied, "Content-Length"), None);
+    }
+
+    #[test]
+    fn falls_back_to_the_apk_size_only_for_the_whole_apk() {
+        let whole = proxy_response(&Method::Get, 200, lookup(&[]), "App.apk", Some(2000)).unwrap();
+        assert_eq!(header(&whole, "Content-Length"), Some("2000"));
+        let range = proxy_response(&Method::Get, 206, lookup(&[]), "App.apk", Some(2000)).unwrap();
+        assert_eq!(header(&range, "Content-Length"), None);
+        let head = proxy_response(&Method::Head, 200, lookup(&[]), "App.apk", Some(2000)).unwrap();
+        assert_eq!(header(&head, "Content-Length"), Some("2000"));
+    }
+}
@@ -1,25 +1,173 @@
+mod bundle;
+mod channel_config;
 mod client_registry;
+mod config;
+mod details_cache;
+mod feed;
+mod fetch_transport;
+mod filename;
 mod google_play_client;
 mod handlers;
 mod openapi_schema;
+mod runtime;
 mod serializable_types;
+mod session_store;
+mod signature;
+mod splits;
+mod track_cache;
+mod version_history;
+mod watcher;
+mod webhooks;
+mod zip;
 
 use client_registry::create_registry;
+use config::Config;
+use fetch_transport::FetchTransport;
+use google_play_client::Channel;
 use openapi_schema::ApiDoc;
+use std::collections::HashMap;
 use utoipa::OpenApi;
+use watcher::{KvWatchStore, WatchStore, WATCHLIST_KV_BINDING};
+use webhooks::{KvWebhookStore, WebhookStore, WEBHOOKS_KV_BINDING};
 use worker::*;
 
 struct AppState {
     client_registry: client_registry::SharedClientRegistry,
-    env: Env,
+    config: Config,
+    admin_token: Option<String>,
+    /// Runs work like background refreshes after the response is sent.
+    context: Context,
+}
+
+/// Poll the watchlist for new versions, see `watcher::poll`.
+#[event(scheduled)]
+async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    console_error_panic_hook::set_once();
+
+    let config = match Config::from_env(&env) {
+        Ok(config) => config,
+        Err(e) => {
+            console_error!("{}", e);
+            return;
+        }
+    };
+    let store = match env.kv(WATCHLIST_KV_BINDING) {
+        Ok(kv) => KvWatchStore::new(kv),
+        Err(_) => {
+            console_log!(
+                "No '{}' KV namespace bound, nothing to watch",
+                WATCHLIST_KV_BINDING
+            );
+            return;
+        }
+    };
+
+    let client_registry = create_registry(&env, &config).await;
+    let channels: Vec<Channel> = config
+        .channels
+        .iter()
+        .map(|account| account.channel.clone())
+        .collect();
+    let now = runtime::now_secs();
+    let changes = watcher::poll(&store, &channels, now, |package_name, channel| {
+        let client_registry = client_registry.clone();
+        // Same track detection and fallback as the details endpoints
+        async move {
+            let details = client_registry
+                .get_details_with_fallback(&package_name, &channel)
+                .await?;
+            Ok(details.map(|(_, details)| details))
+        }
+    })
+    .await;
+    let changes = match changes {
+        Ok(changes) => changes,
+        Err(e) => {
+            console_error!("Failed to poll the watchlist: {}", e);
+            return;
+        }
+    };
+    for change in &changes {
+        console_log!(
+            "{} {} moved from {} to {} ({})",
+            change.version.package_name,
+            change.version.channel,
+            change.previous_version_code,
+            change.version.version_code,
+            change
+                .version
+                .version_string
+                .as_deref()
+                .unwrap_or("unknown")
+        );
+    }
+
+    let done = match env.kv(WEBHOOKS_KV_BINDING) {
+        Ok(kv) => {
+            webhooks::notify(
+                &KvWebhookStore::new(kv),
+                &FetchTransport,
+                &changes,
+                &config.brand_name,
+                now,
+                |package_name, channel, version_code| {
+                    let client_registry = client_registry.clone();
+                    async move {
+                        let download_info = client_registry
+                            .get_download_info(&package_name, &channel, Some(version_code))
+                            .await;
+                        match download_info {
+                            Ok(download_info) => download_info.map(|(_, info)| info),
+                            Err(e) => {
+                                console_log!(
+                                    "Failed to fetch download info of {} {}: {}",
+                                    package_name,
+                                    version_code,
+                                    e
+                                );
+                                None
+                            }
+                        }
+                    }
+                },
+                Delay::from,
+            )
+            .await
+        }
+        Err(_) => changes.iter().collect(),
+    };
+    // Only now, so changes a cut short run did not get to are delivered by the next one
+    for change in done {
+        watcher::remember(&store, change).await;
+    }
 }
 
 #[event(fetch)]
-async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+async fn fetch(req: Request, env: Env, context: Context) -> Result<Response> {
     console_error_panic_hook::set_once();
 
-    let client_registry = create_registry(env.clone()).await;
-    let state = AppState { client_registry, env: env.clone() };
+    let admin_token = config::admin_token(&env);
+    let config = Config::from_env(&env);
+    // Answered before routing, since it has to work while the configuration is invalid
+    if req.method() == Method::Get && req.path() == "/v1/config/check" {
+        let authorization = req.headers().get("Authorization")?;
+        return handlers::check_config(authorization, admin_token, &config);
+    }
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            console_error!("{}", e);
+            return handlers::misconfigured();
+        }
+    };
+
+    let client_registry = create_registry(&env, &config).await;
+    let state = AppState {
+        client_registry,
+        config,
+        admin_token,
+        context,
+    };
 
     let router = Router::with_data(state);
 
@@ -36,90 +184,264 @@ async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
 
             Ok(Response::ok(&spec)?.with_headers(headers))
         })
-        .get_async("/v1/details/:package_name", |_req, ctx| async move {
+        .get_async("/v1/search", |req, ctx| async move {
+            let params = req.url()?.query_pairs().into_owned().collect();
+            handlers::search(params, ctx.data.client_registry.clone()).await
+        })
+        .get_async("/v1/categories", |_req, ctx| async move {
+            handlers::get_categories(ctx.data.client_registry.clone()).await
+        })
+        .get_async("/v1/charts/:chart", |req, ctx| async move {
+            let chart = ctx.param("chart").unwrap().to_string();
+            let params = req.url()?.query_pairs().into_owned().collect();
+            handlers::get_chart(chart, params, ctx.data.client_registry.clone()).await
+        })
+        .get_async("/v1/reviews/:package_name", |req, ctx| async move {
             let package_name = ctx.param("package_name").unwrap().to_string();
-            handlers::get_details_multi(package_name, ctx.data.client_registry.clone()).await
+            let params = req.url()?.query_pairs().into_owned().collect();
+            handlers::get_reviews(package_name, params, ctx.data.client_registry.clone()).await
         })
         .get_async(
-            "/v1/details/:package_name/:channel",
-            |_req, ctx| async move {
+            "/v1/reviews/:package_name/:channel",
+            |req, ctx| async move {
                 let package_name = ctx.param("package_name").unwrap().to_string();
                 let channel = ctx.param("channel").unwrap().to_string();
-                handlers::get_details_single(
+                let params = req.url()?.query_pairs().into_owned().collect();
+                handlers::get_reviews_single(
                     package_name,
                     channel,
+                    params,
                     ctx.data.client_registry.clone(),
                 )
                 .await
             },
         )
+        .get_async("/v1/history/:package_name", |req, ctx| async move {
+            let package_name = ctx.param("package_name").unwrap().to_string();
+            let params = req.url()?.query_pairs().into_owned().collect();
+            handlers::get_history(package_name, params, ctx.data.client_registry.clone()).await
+        })
+        // The format is the channel's extension, e.g. /v1/feed/com.discord/beta.atom
+        .get_async("/v1/feed/:package_name/:feed", |req, ctx| async move {
+            let package_name = ctx.param("package_name").unwrap().to_string();
+            let feed = ctx.param("feed").unwrap().to_string();
+            let base_url = req.url()?.origin().ascii_serialization();
+            handlers::get_feed(
+                package_name,
+                feed,
+                base_url,
+                ctx.data.client_registry.clone(),
+            )
+            .await
+        })
+        .get_async("/v1/details/:package_name", |req, ctx| async move {
+            let package_name = ctx.param("package_name").unwrap().to_string();
+            let if_none_match = req.headers().get("If-None-Match")?;
+            handlers::get_details_multi(
+                package_name,
+                if_none_match,
+                ctx.data.client_registry.clone(),
+                &ctx.data.context,
+            )
+            .await
+        })
+        .post_async("/v1/details/bulk", |mut req, ctx| async move {
+            let body = req.text().await?;
+            handlers::get_details_bulk(body, ctx.data.client_registry.clone()).await
+        })
         .get_async(
-            "/v1/download/:package_name/:channel/:version_code",
-            |_req, ctx| async move {
-                let package_name = ctx.param("package_name").unwrap().to_string();
-                let channel = ctx.param("channel").unwrap().to_string();
-                let version_code: i32 = ctx.param("version_code").unwrap().parse().unwrap_or(0);
-                let brand_name = ctx.data.env.var("BRAND_NAME").map(|v| v.to_string()).unwrap_or_else(|_| "Sniff".to_string());
-                handlers::get_download_info(
-                    package_name,
-                    channel,
-                    Some(version_code),
-                    ctx.data.client_registry.clone(),
-                    brand_name,
-                )
-                .await
-            },
-        )
-        .get_async(
-            "/v1/download/:package_name/:channel",
-            |_req, ctx| async move {
+            "/v1/details/:package_name/:channel",
+            |req, ctx| async move {
                 let package_name = ctx.param("package_name").unwrap().to_string();
                 let channel = ctx.param("channel").unwrap().to_string();
-                let brand_name = ctx.data.env.var("BRAND_NAME").map(|v| v.to_string()).unwrap_or_else(|_| "Sniff".to_string());
-                handlers::get_download_info(
+                let if_none_match = req.headers().get("If-None-Match")?;
+                handlers::get_details_single(
                     package_name,
                     channel,
-                    None,
+                    if_none_match,
                     ctx.data.client_registry.clone(),
-                    brand_name,
+                    &ctx.data.context,
                 )
                 .await
             },
         )
+        .get_async(DOWNLOAD_ROUTE, download_route)
+        .get_async(DOWNLOAD_VERSION_ROUTE, download_route)
+        .post_async(DOWNLOAD_ROUTE, download_route)
+        .post_async(DOWNLOAD_VERSION_ROUTE, download_route)
         // Proxy download endpoints - directly downloads APK with correct filename
-        .get_async(
-            "/v1/apk/:package_name/:channel/:version_code",
-            |_req, ctx| async move {
-                let package_name = ctx.param("package_name").unwrap().to_string();
-                let channel = ctx.param("channel").unwrap().to_string();
-                let version_code: i32 = ctx.param("version_code").unwrap().parse().unwrap_or(0);
-                let brand_name = ctx.data.env.var("BRAND_NAME").map(|v| v.to_string()).unwrap_or_else(|_| "Sniff".to_string());
-                handlers::proxy_download(
-                    package_name,
-                    channel,
-                    Some(version_code),
-                    ctx.data.client_registry.clone(),
-                    brand_name,
-                )
-                .await
-            },
-        )
-        .get_async(
-            "/v1/apk/:package_name/:channel",
-            |_req, ctx| async move {
-                let package_name = ctx.param("package_name").unwrap().to_string();
-                let channel = ctx.param("channel").unwrap().to_string();
-                let brand_name = ctx.data.env.var("BRAND_NAME").map(|v| v.to_string()).unwrap_or_else(|_| "Sniff".to_string());
-                handlers::proxy_download(
-                    package_name,
-                    channel,
-                    None,
-                    ctx.data.client_registry.clone(),
-                    brand_name,
-                )
-                .await
-            },
-        )
+        .get_async(APK_ROUTE, apk_route)
+        .get_async(APK_VERSION_ROUTE, apk_route)
+        .head_async(APK_ROUTE, apk_route)
+        .head_async(APK_VERSION_ROUTE, apk_route)
+        .post_async("/v1/webhooks", |mut req, ctx| async move {
+            let authorization = req.headers().get("Authorization")?;
+            let body = req.text().await?;
+            let store = ctx.kv(WEBHOOKS_KV_BINDING).ok().map(KvWebhookStore::new);
+            let watch_store = ctx.kv(WATCHLIST_KV_BINDING).ok().map(KvWatchStore::new);
+            handlers::create_webhook(
+                body,
+                authorization,
+                ctx.data.admin_token.clone(),
+                store.as_ref().map(|store| store as &dyn WebhookStore),
+                watch_store.as_ref().map(|store| store as &dyn WatchStore),
+                &ctx.data.config,
+            )
+            .await
+        })
+        .get_async("/v1/webhooks", |req, ctx| async move {
+            let params = req.url()?.query_pairs().into_owned().collect();
+            let authorization = req.headers().get("Authorization")?;
+            let store = ctx.kv(WEBHOOKS_KV_BINDING).ok().map(KvWebhookStore::new);
+            handlers::list_webhooks(
+                params,
+                authorization,
+                ctx.data.admin_token.clone(),
+                store.as_ref().map(|store| store as &dyn WebhookStore),
+            )
+            .await
+        })
+        .delete_async("/v1/webhooks/:id", |req, ctx| async move {
+            let id = ctx.param("id").unwrap().to_string();
+            let authorization = req.headers().get("Authorization")?;
+            let store = ctx.kv(WEBHOOKS_KV_BINDING).ok().map(KvWebhookStore::new);
+            handlers::delete_webhook(
+                id,
+                authorization,
+                ctx.data.admin_token.clone(),
+                store.as_ref().map(|store| store as &dyn WebhookStore),
+            )
+            .await
+        })
+        .get_async(BUNDLE_ROUTE, bundle_route)
+        .get_async(BUNDLE_VERSION_ROUTE, bundle_route)
+        .post_async(BUNDLE_ROUTE, bundle_route)
+        .post_async(BUNDLE_VERSION_ROUTE, bundle_route)
+        .get_async(ADMIN_TESTING_ROUTE, testing_program_route)
+        .post_async(ADMIN_TESTING_ROUTE, testing_program_route)
+        .delete_async(ADMIN_TESTING_ROUTE, testing_program_route)
         .run(req, env)
         .await
 }
+
+const DOWNLOAD_ROUTE: &str = "/v1/download/:package_name/:channel";
+const DOWNLOAD_VERSION_ROUTE: &str = "/v1/download/:package_name/:channel/:version_code";
+const APK_ROUTE: &str = "/v1/apk/:package_name/:channel";
+const APK_VERSION_ROUTE: &str = "/v1/apk/:package_name/:channel/:version_code";
+const BUNDLE_ROUTE: &str = "/v1/bundle/:package_name/:channel";
+const BUNDLE_VERSION_ROUTE: &str = "/v1/bundle/:package_name/:channel/:version_code";
+
+/// What the download, APK and bundle routes ask for. The version code is only in some of their
+/// paths, and the body of a POST is the device spec to select splits for.
+struct DownloadRoute {
+    package_name: String,
+    channel: String,
+    version_code: Option<i32>,
+    params: HashMap<String, String>,
+    device_spec: Option<String>,
+}
+
+impl DownloadRoute {
+    async fn read(req: &mut Request, ctx: &RouteContext<AppState>) -> Result<Self> {
+        Ok(DownloadRoute {
+            package_name: ctx.param("package_name").unwrap().to_string(),
+            channel: ctx.param("channel").unwrap().to_string(),
+            version_code: ctx
+                .param("version_code")
+                .map(|version_code| version_code.parse().unwrap_or(0)),
+            params: req.url()?.query_pairs().into_owned().collect(),
+            device_spec: match req.method() {
+                Method::Post => Some(req.text().await?),
+                _ => None,
+            },
+        })
+    }
+}
+
+/// Download info (GET, or POST with a device spec).
+async fn download_route(mut req: Request, ctx: RouteContext<AppState>) -> Result<Response> {
+    let route = DownloadRoute::read(&mut req, &ctx).await?;
+    handlers::get_download_info(
+        route.package_name,
+        route.channel,
+        route.version_code,
+        route.params,
+        route.device_spec,
+        ctx.data.client_registry.clone(),
+        ctx.data.config.brand_name.clone(),
+    )
+    .await
+}
+
+/// The APK (GET) or its headers (HEAD), streamed from Google Play.
+async fn apk_route(mut req: Request, ctx: RouteContext<AppState>) -> Result<Response> {
+    let route = DownloadRoute::read(&mut req, &ctx).await?;
+    handlers::proxy_download(
+        route.package_name,
+        route.channel,
+        route.version_code,
+        req.method(),
+        req.headers(),
+        ctx.data.client_registry.clone(),
+        ctx.data.config.brand_name.clone(),
+    )
+    .await
+}
+
+/// The split APK archive (GET, or POST with a device spec).
+async fn bundle_route(mut req: Request, ctx: RouteContext<AppState>) -> Result<Response> {
+    let route = DownloadRoute::read(&mut req, &ctx).await?;
+    handlers::get_bundle(
+        route.package_name,
+        route.channel,
+        route.version_code,
+        route.params,
+        route.device_spec,
+        ctx.data.client_registry.clone(),
+        ctx.data.config.brand_name.clone(),
+    )
+    .await
+}
+
+const ADMIN_TESTING_ROUTE: &str = "/v1/admin/testing/:package_name/:channel";
+
+/// Read (GET), join (POST) or leave (DELETE) the testing program of a channel's account.
+async fn testing_program_route(req: Request, ctx: RouteContext<AppState>) -> Result<Response> {
+    let package_name = ctx.param("package_name").unwrap().to_string();
+    let channel = ctx.param("channel").unwrap().to_string();
+    let authorization = req.headers().get("Authorization")?;
+    let admin_token = ctx.data.admin_token.clone();
+    let client_registry = ctx.data.client_registry.clone();
+    match req.method() {
+        Method::Post => {
+            handlers::join_testing_program(
+                package_name,
+                channel,
+                authorization,
+                admin_token,
+                client_registry,
+            )
+            .await
+        }
+        Method::Delete => {
+            handlers::leave_testing_program(
+                package_name,
+                channel,
+                authorization,
+                admin_token,
+                client_registry,
+            )
+            .await
+        }
+        _ => {
+            handlers::get_testing_program(
+                package_name,
+                channel,
+                authorization,
+                admin_token,
+                client_registry,
+            )
+            .await
+        }
+    }
+}
@@ -1,24 +1,68 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use utoipa::{OpenApi, ToSchema};
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi, ToSchema};
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
         crate::handlers::get_details_multi,
         crate::handlers::get_details_single,
+        crate::handlers::get_details_bulk,
         crate::handlers::get_download_info,
         crate::handlers::proxy_download,
+        crate::handlers::get_bundle,
+        crate::handlers::search,
+        crate::handlers::get_chart,
+        crate::handlers::get_categories,
+        crate::handlers::get_reviews,
+        crate::handlers::get_reviews_single,
+        crate::handlers::get_history,
+        crate::handlers::get_feed,
+        crate::handlers::create_webhook,
+        crate::handlers::list_webhooks,
+        crate::handlers::delete_webhook,
+        crate::handlers::get_testing_program,
+        crate::handlers::join_testing_program,
+        crate::handlers::leave_testing_program,
+        crate::handlers::check_config,
     ),
     components(
         schemas(
             ApiResponse<SerializableDetailsResponse>,
             MultiChannelApiResponse<SerializableDetailsResponse>,
+            BulkDetailsRequest,
+            DeviceSpecRequest,
+            BulkDetailsResponse,
             ApiResponse<DownloadInfo>,
             SerializableDetailsResponse,
             DownloadInfo,
             SplitFile,
             AdditionalFile,
+            DownloadAuthCookie,
+            ApiResponse<AppPage>,
+            AppPage,
+            AppSummary,
+            ApiResponse<Vec<Category>>,
+            Category,
+            ApiResponse<ReviewPage>,
+            ReviewPage,
+            Review,
+            DeveloperReply,
+            ApiResponse<VersionHistory>,
+            VersionHistory,
+            HistoryEntry,
+            WebhookRequest,
+            ApiResponse<WebhookInfo>,
+            ApiResponse<Vec<WebhookInfo>>,
+            WebhookInfo,
+            ApiResponse<TestingProgram>,
+            TestingProgram,
+            ApiResponse<TestingProgramEnrollment>,
+            TestingProgramEnrollment,
+            ApiResponse<ConfigCheck>,
+            ConfigCheck,
+            ChannelCheck,
             Item,
             DocumentDetails,
             AppDetails,
@@ -31,7 +75,13 @@ use utoipa::{OpenApi, ToSchema};
     tags(
         (name = "App Details", description = "Get Google Play Store app details"),
         (name = "Downloads", description = "Get app download information and URLs"),
-        (name = "Direct APK Download", description = "Stream APK files directly with custom filenames")
+        (name = "Direct APK Download", description = "Stream APK files directly with custom filenames"),
+        (name = "Search", description = "Search the Google Play Store for apps"),
+        (name = "Browse", description = "Browse Google Play Store categories and top charts"),
+        (name = "Reviews", description = "Get user reviews, per release channel"),
+        (name = "History", description = "Versions and changelogs seen so far, per release channel, as JSON or feeds"),
+        (name = "Webhooks", description = "Get notified of new versions (requires ADMIN_TOKEN)"),
+        (name = "Admin", description = "Manage the beta and alpha accounts (requires ADMIN_TOKEN)")
     ),
     info(
         title = "Sniff API",
@@ -42,12 +92,27 @@ use utoipa::{OpenApi, ToSchema};
             url = "https://xhyrom.dev/docs/sniff"
         )
     ),
+    modifiers(&AdminAuth),
     servers(
         (url = "/", description = "Current server")
     )
 )]
 pub struct ApiDoc;
 
+/// Documents the bearer token the admin routes expect.
+struct AdminAuth;
+
+impl Modify for AdminAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "admin_token",
+                SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+            );
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, ToSchema)]
 pub struct ApiResponse<T> {
     pub success: bool,
@@ -62,6 +127,39 @@ pub struct MultiChannelApiResponse<T> {
     pub error: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct BulkDetailsRequest {
+    /// Up to 200 package names
+    #[schema(example = json!(["com.discord", "com.spotify.music"]))]
+    pub package_names: Vec<String>,
+    /// The channel whose account fetches the details, `stable` by default
+    #[schema(example = "stable")]
+    pub channel: Option<String>,
+}
+
+/// Details of every package of a bulk request, keyed by package name. A package that failed has
+/// its own `success` and `error`; the response as a whole fails only when the request does.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct BulkDetailsResponse {
+    pub success: bool,
+    pub data: Option<HashMap<String, ApiResponse<SerializableDetailsResponse>>>,
+    pub error: Option<String>,
+}
+
+/// The fields of a bundletool `device-spec.json` that splits are selected by; others are ignored.
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceSpecRequest {
+    /// Most preferred first
+    #[schema(example = json!(["arm64-v8a", "armeabi-v7a"]))]
+    pub supported_abis: Vec<String>,
+    /// In dpi
+    #[schema(example = 480)]
+    pub screen_density: Option<u32>,
+    #[schema(example = json!(["en-US", "de-DE"]))]
+    pub supported_locales: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize, ToSchema)]
 #[schema(example = json!({
     "item": {
@@ -138,17 +236,32 @@ pub struct SerializableDetailsResponse {
     "version_code": 289020,
     "channel": "stable",
     "main_apk_url": "https://play.googleapis.com/download/by-token/download?token=AOTCm0Q...",
+    "main_apk_size": 45328112,
+    "main_apk_gzipped_url": "https://play.googleapis.com/download/by-token/download?token=AOTCm0Q...&gzip=1",
+    "main_apk_gzipped_size": 41870233,
+    "main_apk_sha1": "OTDbM9WmG2gCD2n9pOYIuBJDGI0",
+    "main_apk_sha256": "pBTQ8pgDqkRgRK8NE4rDOIE9jxD1W8qUyAbpqJ7dRNU",
     "splits": [
         {
             "name": "config.arm64_v8a",
-            "download_url": "https://play.googleapis.com/download/by-token/download?token=AOTCm0R..."
+            "download_url": "https://play.googleapis.com/download/by-token/download?token=AOTCm0R...",
+            "size": 31201420,
+            "sha1": "7GkuSgRd4K1QBiYjHb6IejfgAyo",
+            "sha256": "gk2j1FGXlsWz7Q0UXlQ7mAV3gzmiKlBd8dAN7oHeMtU"
         },
         {
             "name": "config.en",
-            "download_url": "https://play.googleapis.com/download/by-token/download?token=AOTCm0S..."
+            "download_url": "https://play.googleapis.com/download/by-token/download?token=AOTCm0S...",
+            "size": 112322
         }
     ],
-    "additional_files": []
+    "additional_files": [],
+    "download_auth_cookies": [
+        {
+            "name": "MarketDA",
+            "value": "03456789012345678901"
+        }
+    ]
 }))]
 pub struct DownloadInfo {
     #[schema(example = "Sniff_Discord_Stable_289.20.apk")]
@@ -163,8 +276,18 @@ pub struct DownloadInfo {
     pub channel: Option<String>,
     #[schema(example = "https://play.googleapis.com/download/by-token/download?token=AOTCm0Q...")]
     pub main_apk_url: Option<String>,
+    #[schema(example = 45328112)]
+    pub main_apk_size: Option<i64>,
+    pub main_apk_gzipped_url: Option<String>,
+    pub main_apk_gzipped_size: Option<i64>,
+    #[schema(example = "OTDbM9WmG2gCD2n9pOYIuBJDGI0")]
+    pub main_apk_sha1: Option<String>,
+    #[schema(example = "pBTQ8pgDqkRgRK8NE4rDOIE9jxD1W8qUyAbpqJ7dRNU")]
+    pub main_apk_sha256: Option<String>,
     pub splits: Vec<SplitFile>,
     pub additional_files: Vec<AdditionalFile>,
+    /// Cookies to send with every download request
+    pub download_auth_cookies: Vec<DownloadAuthCookie>,
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
@@ -173,6 +296,14 @@ pub struct SplitFile {
     pub name: Option<String>,
     #[schema(example = "https://play.googleapis.com/download/by-token/download?token=AOTCm0R...")]
     pub download_url: Option<String>,
+    #[schema(example = 31201420)]
+    pub size: Option<i64>,
+    pub gzipped_download_url: Option<String>,
+    pub gzipped_size: Option<i64>,
+    #[schema(example = "7GkuSgRd4K1QBiYjHb6IejfgAyo")]
+    pub sha1: Option<String>,
+    #[schema(example = "gk2j1FGXlsWz7Q0UXlQ7mAV3gzmiKlBd8dAN7oHeMtU")]
+    pub sha256: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
@@ -181,6 +312,313 @@ pub struct AdditionalFile {
     pub filename: Option<String>,
     #[schema(example = "https://play.googleapis.com/download/by-token/download?token=AOTCm0T...")]
     pub download_url: Option<String>,
+    /// `main` or `patch`
+    #[schema(example = "main")]
+    pub file_type: String,
+    #[schema(example = 1234)]
+    pub version_code: i32,
+    pub size: Option<i64>,
+    pub gzipped_download_url: Option<String>,
+    pub gzipped_size: Option<i64>,
+    pub sha1: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct DownloadAuthCookie {
+    #[schema(example = "MarketDA")]
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AppPage {
+    pub results: Vec<AppSummary>,
+    /// Pass as `page` to fetch the next page; absent on the last page
+    #[schema(example = "search?c=3&q=discord&o=20&ctntkn=-p6BnQMCCBQ%3D")]
+    pub next_page: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AppSummary {
+    #[schema(example = "com.discord")]
+    pub package_name: String,
+    #[schema(example = "Discord - Talk, Play, Hang Out")]
+    pub title: Option<String>,
+    #[schema(example = "Discord Inc.")]
+    pub creator: Option<String>,
+    pub icon_url: Option<String>,
+    #[schema(example = 4.3)]
+    pub star_rating: Option<f32>,
+    #[schema(example = 6512083)]
+    pub ratings_count: Option<u64>,
+    #[schema(example = 289020)]
+    pub version_code: Option<i32>,
+    #[schema(example = "289.20")]
+    pub version_string: Option<String>,
+    #[schema(example = true)]
+    pub free: bool,
+    /// Localized price, only set for paid apps
+    pub formatted_price: Option<String>,
+}
+
+impl From<gpapi::Page> for AppPage {
+    fn from(page: gpapi::Page) -> Self {
+        AppPage {
+            results: page.results.into_iter().map(AppSummary::from).collect(),
+            next_page: page.next_page,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Category {
+    /// Pass as `category` to /v1/charts
+    #[schema(example = "GAME_PUZZLE")]
+    pub id: String,
+    #[schema(example = "Puzzle")]
+    pub name: String,
+    pub icon_url: Option<String>,
+}
+
+impl From<gpapi::Category> for Category {
+    fn from(category: gpapi::Category) -> Self {
+        Category {
+            id: category.id,
+            name: category.name,
+            icon_url: category.icon_url,
+        }
+    }
+}
+
+impl From<gpapi::AppSummary> for AppSummary {
+    fn from(app: gpapi::AppSummary) -> Self {
+        AppSummary {
+            package_name: app.package_name,
+            title: app.title,
+            creator: app.creator,
+            icon_url: app.icon_url,
+            star_rating: app.star_rating,
+            ratings_count: app.ratings_count,
+            version_code: app.version_code,
+            version_string: app.version_string,
+            free: app.free,
+            formatted_price: app.formatted_price,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ReviewPage {
+    pub reviews: Vec<Review>,
+    /// Total number of reviews matching the filters
+    #[schema(example = 1204)]
+    pub matching_count: Option<i64>,
+    /// Pass as `offset` to fetch the next page; absent on the last page
+    #[schema(example = 20)]
+    pub next_offset: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct Review {
+    #[schema(example = "gp:AOqpTOE...")]
+    pub id: Option<String>,
+    #[schema(example = "Jane Doe")]
+    pub author: Option<String>,
+    #[schema(example = 4)]
+    pub rating: Option<i32>,
+    pub title: Option<String>,
+    #[schema(example = "Voice chat works great, but the app takes a while to start.")]
+    pub text: Option<String>,
+    #[schema(example = "289.20")]
+    pub version: Option<String>,
+    pub device_name: Option<String>,
+    /// Milliseconds since the Unix epoch
+    #[schema(example = 1700000000000_i64)]
+    pub timestamp: Option<i64>,
+    pub thumbs_up_count: Option<i64>,
+    pub developer_reply: Option<DeveloperReply>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct DeveloperReply {
+    #[schema(example = "Thanks for the feedback! We're working on startup times.")]
+    pub text: String,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: Option<i64>,
+}
+
+impl From<gpapi::ReviewPage> for ReviewPage {
+    fn from(page: gpapi::ReviewPage) -> Self {
+        ReviewPage {
+            reviews: page.reviews.into_iter().map(Review::from).collect(),
+            matching_count: page.matching_count,
+            next_offset: page.next_offset,
+        }
+    }
+}
+
+impl From<gpapi::Review> for Review {
+    fn from(review: gpapi::Review) -> Self {
+        Review {
+            id: review.id,
+            author: review.author,
+            rating: review.rating,
+            title: review.title,
+            text: review.text,
+            version: review.version,
+            device_name: review.device_name,
+            timestamp: review.timestamp,
+            thumbs_up_count: review.thumbs_up_count,
+            developer_reply: review.developer_reply.map(|reply| DeveloperReply {
+                text: reply.text,
+                timestamp: reply.timestamp,
+            }),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct VersionHistory {
+    #[schema(example = "com.discord")]
+    pub package_name: String,
+    /// Newest first
+    pub versions: Vec<HistoryEntry>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct HistoryEntry {
+    #[schema(example = "stable")]
+    pub channel: String,
+    #[schema(example = 289020)]
+    pub version_code: i32,
+    #[schema(example = "289.20 - Stable")]
+    pub version_string: Option<String>,
+    /// The "What's new" text of the version
+    pub recent_changes_html: Option<String>,
+    #[schema(example = "Jul 21, 2025")]
+    pub info_updated_on: Option<String>,
+    /// Download size in bytes
+    #[schema(example = 104857600_i64)]
+    pub download_size: Option<i64>,
+    /// When Sniff first saw this version, in seconds since the Unix epoch
+    #[schema(example = 1753056000_u64)]
+    pub first_seen: u64,
+}
+
+impl From<crate::version_history::VersionRecord> for HistoryEntry {
+    fn from(record: crate::version_history::VersionRecord) -> Self {
+        HistoryEntry {
+            channel: record.channel,
+            version_code: record.version_code,
+            version_string: record.version_string,
+            recent_changes_html: record.recent_changes_html,
+            info_updated_on: record.info_updated_on,
+            download_size: record.download_size,
+            first_seen: record.first_seen,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct WebhookRequest {
+    /// Receives a signed POST for every new version
+    #[schema(example = "https://example.com/sniff")]
+    pub url: String,
+    #[schema(example = "com.discord")]
+    pub package_name: String,
+    #[schema(example = "beta")]
+    pub channel: String,
+    /// Key of the `X-Sniff-Signature` HMAC, at least 16 characters
+    pub secret: String,
+}
+
+/// A webhook subscription, without its secret.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct WebhookInfo {
+    #[schema(example = "3f2a9c41d07b5e68")]
+    pub id: String,
+    #[schema(example = "https://example.com/sniff")]
+    pub url: String,
+    #[schema(example = "com.discord")]
+    pub package_name: String,
+    #[schema(example = "beta")]
+    pub channel: String,
+}
+
+impl From<&crate::webhooks::Webhook> for WebhookInfo {
+    fn from(webhook: &crate::webhooks::Webhook) -> Self {
+        WebhookInfo {
+            id: webhook.id.clone(),
+            url: webhook.url.clone(),
+            package_name: webhook.package_name.clone(),
+            channel: webhook.channel.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct TestingProgram {
+    #[schema(example = "beta")]
+    pub channel: String,
+    /// The app runs a testing program open to the channel's account
+    #[schema(example = true)]
+    pub available: bool,
+    /// The channel's account is enrolled in it
+    #[schema(example = true)]
+    pub subscribed: bool,
+    pub subscribed_and_installed: bool,
+    /// Feedback address of the testing program
+    #[schema(example = "beta@discord.com")]
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+}
+
+impl TestingProgram {
+    pub fn new(channel: String, status: gpapi::TestingProgramStatus) -> Self {
+        TestingProgram {
+            channel,
+            available: status.available,
+            subscribed: status.subscribed,
+            subscribed_and_installed: status.subscribed_and_installed,
+            email: status.email,
+            display_name: status.display_name,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct TestingProgramEnrollment {
+    #[schema(example = "beta")]
+    pub channel: String,
+    /// Whether the channel's account is now enrolled
+    #[schema(example = true)]
+    pub subscribed: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ConfigCheck {
+    #[schema(example = false)]
+    pub valid: bool,
+    /// Everything that has to be fixed, empty when the configuration is valid
+    #[schema(example = json!(["BETA_AAS_TOKEN (AAS token of channel 'beta') is not set, add it with `wrangler secret put BETA_AAS_TOKEN`"]))]
+    pub problems: Vec<String>,
+    /// The configured channels, listed when the configuration is valid
+    pub channels: Vec<ChannelCheck>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ChannelCheck {
+    #[schema(example = "beta")]
+    pub name: String,
+    #[schema(example = "px_7a")]
+    pub device: String,
+    pub locale: Option<String>,
+    /// Name of the secret holding the account's email
+    #[schema(example = "BETA_EMAIL")]
+    pub email_secret: String,
+    /// Name of the secret holding the account's AAS token
+    #[schema(example = "BETA_AAS_TOKEN")]
+    pub token_secret: String,
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
@@ -272,5 +710,3 @@ pub struct AppInfoContainer {
     #[schema(example = "€1.99 - €274.99 if billed through Play")]
     pub description: Option<String>,
 }
-
-
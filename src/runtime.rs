@@ -0,0 +1,28 @@
+//! What the Workers runtime provides, with stand-ins off wasm so the code using it also runs in
+//! native tests, where calling into JavaScript panics.
+
+/// Seconds since the Unix epoch.
+#[cfg(target_arch = "wasm32")]
+pub fn now_secs() -> u64 {
+    worker::Date::now().as_millis() / 1000
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// `console_log!` on the Workers runtime, stderr elsewhere.
+macro_rules! log {
+    ($($t:tt)*) => {{
+        #[cfg(target_arch = "wasm32")]
+        worker::console_log!($($t)*);
+        #[cfg(not(target_arch = "wasm32"))]
+        eprintln!($($t)*);
+    }};
+}
+
+pub(crate) use log;
@@ -0,0 +1,137 @@
+use gpapi::Session;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use worker::async_trait::async_trait;
+use worker::KvStore;
+
+use crate::google_play_client::Channel;
+
+/// KV namespace binding used to persist sessions between Worker invocations.
+pub const SESSIONS_KV_BINDING: &str = "SESSIONS";
+
+/// How long a stored session is reused before logging in again. Play auth tokens are valid for
+/// about an hour, so this leaves some headroom.
+pub const SESSION_TTL_SECS: u64 = 45 * 60;
+
+/// Storage for logged-in `Gpapi` sessions, keyed by channel.
+#[async_trait(?Send)]
+pub trait SessionStore: Send + Sync {
+    async fn load(&self, channel: &Channel) -> Option<Session>;
+    async fn save(&self, channel: &Channel, session: &Session) -> Result<(), String>;
+    async fn remove(&self, channel: &Channel) -> Result<(), String>;
+}
+
+fn session_key(channel: &Channel) -> String {
+    format!("session:{}", channel)
+}
+
+/// Sessions stored in Workers KV. Entries expire on their own after `SESSION_TTL_SECS`.
+pub struct KvSessionStore {
+    kv: KvStore,
+}
+
+impl KvSessionStore {
+    pub fn new(kv: KvStore) -> Self {
+        Self { kv }
+    }
+}
+
+#[async_trait(?Send)]
+impl SessionStore for KvSessionStore {
+    async fn load(&self, channel: &Channel) -> Option<Session> {
+        // A missing or undecodable entry just means we log in again
+        self.kv
+            .get(&session_key(channel))
+            .json::<Session>()
+            .await
+            .ok()
+            .flatten()
+    }
+
+    async fn save(&self, channel: &Channel, session: &Session) -> Result<(), String> {
+        let value = serde_json::to_string(session).map_err(|e| e.to_string())?;
+        self.kv
+            .put(&session_key(channel), value)
+            .map_err(|e| e.to_string())?
+            .expiration_ttl(SESSION_TTL_SECS)
+            .execute()
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn remove(&self, channel: &Channel) -> Result<(), String> {
+        self.kv
+            .delete(&session_key(channel))
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Sessions kept for the lifetime of the store only. Used when no KV namespace is bound, and in
+/// tests.
+#[derive(Default)]
+pub struct MemorySessionStore {
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+#[async_trait(?Send)]
+impl SessionStore for MemorySessionStore {
+    async fn load(&self, channel: &Channel) -> Option<Session> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(&session_key(channel))
+            .cloned()
+    }
+
+    async fn save(&self, channel: &Channel, session: &Session) -> Result<(), String> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(session_key(channel), session.clone());
+        Ok(())
+    }
+
+    async fn remove(&self, channel: &Channel) -> Result<(), String> {
+        self.sessions.lock().unwrap().remove(&session_key(channel));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn session(auth_token: &str) -> Session {
+        Session {
+            version: Session::VERSION,
+            email: String::from("user@example.com"),
+            auth_token: String::from(auth_token),
+            auth_token_expiry: None,
+            gsf_id: 1,
+            device_config_token: None,
+            device_checkin_consistency_token: None,
+            dfe_cookie: None,
+        }
+    }
+
+    #[test]
+    fn memory_store_is_keyed_by_channel() {
+        let store = MemorySessionStore::default();
+        let stable = Channel::stable();
+        let beta = Channel::from_str("beta").unwrap();
+        block_on(async {
+            assert!(store.load(&stable).await.is_none());
+
+            store.save(&stable, &session("stable")).await.unwrap();
+            store.save(&beta, &session("beta")).await.unwrap();
+            assert_eq!(store.load(&stable).await, Some(session("stable")));
+            assert_eq!(store.load(&beta).await, Some(session("beta")));
+
+            store.remove(&stable).await.unwrap();
+            assert!(store.load(&stable).await.is_none());
+            assert!(store.load(&beta).await.is_some());
+        });
+    }
+}
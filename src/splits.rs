@@ -0,0 +1,427 @@
+//! Split APK names, and picking the splits a device needs, like bundletool does when it
+//! installs an APK set.
+
+use gpapi::SplitApk;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+const ABIS: [&str; 7] = [
+    "armeabi",
+    "armeabi_v7a",
+    "arm64_v8a",
+    "x86",
+    "x86_64",
+    "mips",
+    "mips64",
+];
+
+/// The language kept when a module has none of the device's, if the module has it. Play Console
+/// apps default to English; other modules keep all their languages instead.
+const DEFAULT_LANGUAGE: &str = "en";
+
+const DENSITIES: [(&str, u32); 7] = [
+    ("ldpi", 120),
+    ("mdpi", 160),
+    ("tvdpi", 213),
+    ("hdpi", 240),
+    ("xhdpi", 320),
+    ("xxhdpi", 480),
+    ("xxxhdpi", 640),
+];
+
+/// Texture compression formats, whose short names would otherwise pass for languages.
+const TEXTURE_FORMATS: [&str; 9] = [
+    "atc", "astc", "dxt1", "etc1", "etc2", "paletted", "pvrtc", "s3tc", "3dc",
+];
+
+/// The module a split belongs to, and its config within the module, as bundletool names them:
+/// `config.arm64_v8a` is the `arm64_v8a` config of `base`, `camera.config.en` the `en`
+/// config of the `camera` feature, and `camera` the feature's master split.
+pub fn split_module(name: &str) -> (&str, Option<&str>) {
+    match name.split_once("config.") {
+        Some(("", config)) => ("base", Some(config)),
+        Some((module, config)) if module.ends_with('.') => {
+            (module.trim_end_matches('.'), Some(config))
+        }
+        _ => (name, None),
+    }
+}
+
+/// What a config split targets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SplitTarget {
+    /// Native code for an ABI, e.g. `arm64_v8a`
+    Abi(String),
+    /// Resources for a screen density, in dpi
+    Density(u32),
+    /// Resources for a language, e.g. `en`
+    Language(String),
+    /// A feature module's master split, or a config Sniff does not know
+    Other,
+}
+
+impl SplitTarget {
+    pub fn parse(name: &str) -> Self {
+        let Some(config) = split_module(name).1 else {
+            return SplitTarget::Other;
+        };
+        if ABIS.contains(&config) {
+            return SplitTarget::Abi(config.to_string());
+        }
+        if let Some(dpi) = density(config) {
+            return SplitTarget::Density(dpi);
+        }
+        if TEXTURE_FORMATS.contains(&config) {
+            return SplitTarget::Other;
+        }
+        let language = config.split(['_', '-']).next().unwrap_or(config);
+        if (2..=3).contains(&language.len()) && language.chars().all(|c| c.is_ascii_lowercase()) {
+            return SplitTarget::Language(language.to_string());
+        }
+        SplitTarget::Other
+    }
+}
+
+/// A density as a name like `xxhdpi`, or in dpi.
+fn density(value: &str) -> Option<u32> {
+    DENSITIES
+        .iter()
+        .find(|(name, _)| *name == value)
+        .map(|(_, dpi)| *dpi)
+        .or_else(|| value.parse().ok().filter(|dpi| *dpi > 0))
+}
+
+/// `arm64-v8a`, as Android and bundletool spell it, is the `arm64_v8a` of split names.
+fn normalize_abi(abi: &str) -> String {
+    abi.trim().to_ascii_lowercase().replace('-', "_")
+}
+
+/// `en-US` and `en_US` are `en`, as language splits only carry the language.
+fn normalize_language(locale: &str) -> String {
+    let locale = locale.trim();
+    locale
+        .split(['_', '-'])
+        .next()
+        .unwrap_or(locale)
+        .to_ascii_lowercase()
+}
+
+/// The device to select splits for. Empty dimensions are not filtered.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceSpec {
+    /// Most preferred first
+    pub abis: Vec<String>,
+    pub density: Option<u32>,
+    pub languages: Vec<String>,
+}
+
+/// The fields of bundletool's `device-spec.json` that splits are selected by.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeviceSpecJson {
+    #[serde(default)]
+    supported_abis: Vec<String>,
+    screen_density: Option<u32>,
+    #[serde(default)]
+    supported_locales: Vec<String>,
+}
+
+impl DeviceSpec {
+    /// From the `abi`, `dpi` and `lang` query parameters, e.g.
+    /// `?abi=arm64_v8a,armeabi_v7a&dpi=xxhdpi&lang=en,de`. `None` when none of them is set.
+    pub fn from_query(params: &HashMap<String, String>) -> Result<Option<Self>, String> {
+        let list = |key: &str| -> Vec<String> {
+            params
+                .get(key)
+                .map(|value| {
+                    value
+                        .split(',')
+                        .filter(|item| !item.trim().is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let density = match params.get("dpi").filter(|dpi| !dpi.is_empty()) {
+            Some(dpi) => Some(density(&dpi.to_ascii_lowercase()).ok_or_else(|| {
+                format!(
+                    "Invalid dpi '{}', expected a density like xxhdpi or 480",
+                    dpi
+                )
+            })?),
+            None => None,
+        };
+        let spec = DeviceSpec {
+            abis: list("abi").iter().map(|abi| normalize_abi(abi)).collect(),
+            density,
+            languages: list("lang")
+                .iter()
+                .map(|language| normalize_language(language))
+                .collect(),
+        };
+        Ok((spec != DeviceSpec::default()).then_some(spec))
+    }
+
+    /// From a bundletool `device-spec.json`, as written by `bundletool get-device-spec`.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let spec: DeviceSpecJson =
+            serde_json::from_str(json).map_err(|e| format!("Invalid device spec: {}", e))?;
+        Ok(DeviceSpec {
+            abis: spec
+                .supported_abis
+                .iter()
+                .map(|abi| normalize_abi(abi))
+                .collect(),
+            density: spec.screen_density.filter(|dpi| *dpi > 0),
+            languages: spec
+                .supported_locales
+                .iter()
+                .map(|locale| normalize_language(locale))
+                .collect(),
+        })
+    }
+
+    /// The splits this device installs: per module, the ABI it prefers most, the density
+    /// closest to its own (the smallest one at least as dense, else the densest), and its
+    /// languages, else the default language. Fails when a module has native code, but not for
+    /// any of the device's ABIs.
+    pub fn select(&self, splits: Vec<SplitApk>) -> Result<Vec<SplitApk>, String> {
+        let targets: Vec<(String, SplitTarget)> = splits
+            .iter()
+            .map(|split| {
+                let name = split.name.as_deref().unwrap_or_default();
+                (split_module(name).0.to_string(), SplitTarget::parse(name))
+            })
+            .collect();
+
+        let mut abis: HashMap<&str, Vec<&str>> = HashMap::new();
+        let mut densities: HashMap<&str, Vec<u32>> = HashMap::new();
+        let mut languages: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (module, target) in &targets {
+            match target {
+                SplitTarget::Abi(abi) => abis.entry(module).or_default().push(abi),
+                SplitTarget::Density(dpi) => densities.entry(module).or_default().push(*dpi),
+                SplitTarget::Language(language) => {
+                    languages.entry(module).or_default().push(language)
+                }
+                SplitTarget::Other => {}
+            }
+        }
+
+        let mut chosen_abis = HashMap::new();
+        if !self.abis.is_empty() {
+            for (module, available) in &abis {
+                let abi = self
+                    .abis
+                    .iter()
+                    .find(|abi| available.contains(&abi.as_str()))
+                    .ok_or_else(|| {
+                        format!(
+                            "Module '{}' has no native code for {}, only for {}",
+                            module,
+                            self.abis.join(", "),
+                            available.join(", ")
+                        )
+                    })?;
+                chosen_abis.insert(*module, abi.as_str());
+            }
+        }
+        let mut chosen_densities = HashMap::new();
+        if let Some(device) = self.density {
+            for (module, available) in &densities {
+                let at_least = available.iter().filter(|dpi| **dpi >= device).min();
+                let dpi = at_least.or_else(|| available.iter().max());
+                chosen_densities.insert(*module, dpi.copied());
+            }
+        }
+
+        // Without any of the device's languages, a module would be left with no strings
+        let mut fallback_languages = HashMap::new();
+        if !self.languages.is_empty() {
+            for (module, available) in &languages {
+                if available
+                    .iter()
+                    .any(|language| self.languages.iter().any(|l| l == language))
+                {
+                    continue;
+                }
+                let fallback = available
+                    .contains(&DEFAULT_LANGUAGE)
+                    .then_some(DEFAULT_LANGUAGE);
+                fallback_languages.insert(*module, fallback);
+            }
+        }
+
+        let keep: Vec<bool> = targets
+            .iter()
+            .map(|(module, target)| match target {
+                SplitTarget::Abi(abi) => chosen_abis
+                    .get(module.as_str())
+                    .is_none_or(|chosen| chosen == abi),
+                SplitTarget::Density(dpi) => chosen_densities
+                    .get(module.as_str())
+                    .is_none_or(|chosen| *chosen == Some(*dpi)),
+                SplitTarget::Language(language) => match fallback_languages.get(module.as_str()) {
+                    Some(fallback) => fallback.is_none_or(|fallback| fallback == language),
+                    None => self.languages.is_empty() || self.languages.contains(language),
+                },
+                SplitTarget::Other => true,
+            })
+            .collect();
+        Ok(splits
+            .into_iter()
+            .zip(keep)
+            .filter_map(|(split, keep)| keep.then_some(split))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn splits(names: &[&str]) -> Vec<SplitApk> {
+        names
+            .iter()
+            .map(|name| SplitApk {
+                name: Some(name.to_string()),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn names(splits: &[SplitApk]) -> Vec<&str> {
+        splits
+            .iter()
+            .map(|split| split.name.as_deref().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn parses_split_names() {
+        assert_eq!(split_module("config.en"), ("base", Some("en")));
+        assert_eq!(split_module("camera.config.en"), ("camera", Some("en")));
+        assert_eq!(split_module("camera"), ("camera", None));
+        assert_eq!(
+            SplitTarget::parse("config.arm64_v8a"),
+            SplitTarget::Abi(String::from("arm64_v8a"))
+        );
+        assert_eq!(
+            SplitTarget::parse("config.x86_64"),
+            SplitTarget::Abi(String::from("x86_64"))
+        );
+        assert_eq!(
+            SplitTarget::parse("config.xxhdpi"),
+            SplitTarget::Density(480)
+        );
+        assert_eq!(
+            SplitTarget::parse("camera.config.de"),
+            SplitTarget::Language(String::from("de"))
+        );
+        assert_eq!(SplitTarget::parse("camera"), SplitTarget::Other);
+        assert_eq!(SplitTarget::parse("config.astc"), SplitTarget::Other);
+        assert_eq!(SplitTarget::parse("config.atc"), SplitTarget::Other);
+    }
+
+    #[test]
+    fn selects_device_splits() {
+        let available = splits(&[
+            "config.arm64_v8a",
+            "config.armeabi_v7a",
+            "config.x86_64",
+            "config.hdpi",
+            "config.xxhdpi",
+            "config.xxxhdpi",
+            "config.en",
+            "config.de",
+            "config.fr",
+            "camera",
+            "camera.config.mdpi",
+        ]);
+        let params = HashMap::from([
+            (
+                String::from("abi"),
+                String::from("x86,arm64-v8a,armeabi_v7a"),
+            ),
+            (String::from("dpi"), String::from("420")),
+            (String::from("lang"), String::from("en-US,de")),
+        ]);
+        let spec = DeviceSpec::from_query(&params).unwrap().unwrap();
+        assert_eq!(
+            names(&spec.select(available.clone()).unwrap()),
+            [
+                "config.arm64_v8a",
+                "config.xxhdpi",
+                "config.en",
+                "config.de",
+                "camera",
+                // The only density of the module, even if below the device's
+                "camera.config.mdpi",
+            ]
+        );
+
+        // Unfiltered dimensions keep every split
+        let spec = DeviceSpec {
+            density: Some(640),
+            ..Default::default()
+        };
+        assert_eq!(spec.select(available.clone()).unwrap().len(), 9);
+
+        let spec = DeviceSpec {
+            abis: vec![String::from("mips")],
+            ..Default::default()
+        };
+        assert!(spec.select(available).is_err());
+    }
+
+    #[test]
+    fn falls_back_to_default_language() {
+        let available = splits(&[
+            "config.en",
+            "config.de",
+            "camera",
+            "camera.config.de",
+            "camera.config.fr",
+            "maps",
+            "maps.config.ja",
+        ]);
+        let spec = DeviceSpec {
+            languages: vec![String::from("ja")],
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&spec.select(available).unwrap()),
+            [
+                // English for the base, which has no Japanese
+                "config.en",
+                // Every language of a module without English either
+                "camera",
+                "camera.config.de",
+                "camera.config.fr",
+                "maps",
+                "maps.config.ja",
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_device_specs() {
+        let spec = DeviceSpec::from_json(
+            r#"{
+                "supportedAbis": ["arm64-v8a", "armeabi-v7a"],
+                "supportedLocales": ["en-US", "de-DE"],
+                "screenDensity": 480,
+                "sdkVersion": 34
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(spec.abis, ["arm64_v8a", "armeabi_v7a"]);
+        assert_eq!(spec.languages, ["en", "de"]);
+        assert_eq!(spec.density, Some(480));
+
+        assert_eq!(DeviceSpec::from_query(&HashMap::new()), Ok(None));
+        let params = HashMap::from([(String::from("dpi"), String::from("huge"))]);
+        assert!(DeviceSpec::from_query(&params).is_err());
+        assert!(DeviceSpec::from_json("[]").is_err());
+    }
+}
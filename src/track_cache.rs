@@ -0,0 +1,177 @@
+use googleplay_protobuf::DetailsResponse;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use worker::async_trait::async_trait;
+use worker::KvStore;
+
+use crate::google_play_client::Channel;
+
+/// KV namespace binding used to remember which channels carry a track for a package.
+pub const TRACKS_KV_BINDING: &str = "TRACKS";
+
+/// How long a detected track is trusted before it is checked again. Apps join and leave testing
+/// programs rarely, so this can be generous.
+pub const TRACK_TTL_SECS: u64 = 6 * 60 * 60;
+
+/// Remembers whether a beta/alpha channel has its own track for a package, so detection does
+/// not cost two details requests every time.
+#[async_trait(?Send)]
+pub trait TrackCache: Send + Sync {
+    async fn get(&self, package_name: &str, channel: &Channel) -> Option<bool>;
+    async fn put(
+        &self,
+        package_name: &str,
+        channel: &Channel,
+        available: bool,
+    ) -> Result<(), String>;
+    /// Forget what was detected, e.g. after the channel account joined or left the app's
+    /// testing program.
+    async fn remove(&self, package_name: &str, channel: &Channel) -> Result<(), String>;
+}
+
+fn track_key(package_name: &str, channel: &Channel) -> String {
+    format!("track:{}:{}", channel, package_name)
+}
+
+/// Whether the channel account sees its own track of an app, given the details the stable and
+/// the channel account got for it. Either the account is enrolled in the app's testing program,
+/// or it is served a different build than stable.
+pub fn has_own_track(stable: &DetailsResponse, channel: &DetailsResponse) -> bool {
+    let app_details = |details: &DetailsResponse| {
+        details
+            .item
+            .as_ref()
+            .and_then(|item| item.details.as_ref())
+            .and_then(|details| details.app_details.clone())
+    };
+    let Some(channel_app) = app_details(channel) else {
+        return false;
+    };
+    let subscribed = channel_app
+        .testing_program_info
+        .as_ref()
+        .and_then(|info| info.subscribed)
+        .unwrap_or(false);
+    let stable_version = app_details(stable).and_then(|app| app.version_code);
+    subscribed || (channel_app.version_code.is_some() && channel_app.version_code != stable_version)
+}
+
+/// Tracks stored in Workers KV. Entries expire on their own after `TRACK_TTL_SECS`.
+pub struct KvTrackCache {
+    kv: KvStore,
+}
+
+impl KvTrackCache {
+    pub fn new(kv: KvStore) -> Self {
+        Self { kv }
+    }
+}
+
+#[async_trait(?Send)]
+impl TrackCache for KvTrackCache {
+    async fn get(&self, package_name: &str, channel: &Channel) -> Option<bool> {
+        self.kv
+            .get(&track_key(package_name, channel))
+            .json::<bool>()
+            .await
+            .ok()
+            .flatten()
+    }
+
+    async fn put(
+        &self,
+        package_name: &str,
+        channel: &Channel,
+        available: bool,
+    ) -> Result<(), String> {
+        self.kv
+            .put(&track_key(package_name, channel), available)
+            .map_err(|e| e.to_string())?
+            .expiration_ttl(TRACK_TTL_SECS)
+            .execute()
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn remove(&self, package_name: &str, channel: &Channel) -> Result<(), String> {
+        self.kv
+            .delete(&track_key(package_name, channel))
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Tracks kept for the lifetime of the cache only. Used when no KV namespace is bound.
+#[derive(Default)]
+pub struct MemoryTrackCache {
+    tracks: Mutex<HashMap<String, bool>>,
+}
+
+#[async_trait(?Send)]
+impl TrackCache for MemoryTrackCache {
+    async fn get(&self, package_name: &str, channel: &Channel) -> Option<bool> {
+        self.tracks
+            .lock()
+            .unwrap()
+            .get(&track_key(package_name, channel))
+            .copied()
+    }
+
+    async fn put(
+        &self,
+        package_name: &str,
+        channel: &Channel,
+        available: bool,
+    ) -> Result<(), String> {
+        self.tracks
+            .lock()
+            .unwrap()
+            .insert(track_key(package_name, channel), available);
+        Ok(())
+    }
+
+    async fn remove(&self, package_name: &str, channel: &Channel) -> Result<(), String> {
+        self.tracks
+            .lock()
+            .unwrap()
+            .remove(&track_key(package_name, channel));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googleplay_protobuf::{AppDetails, DocumentDetails, Item, TestingProgramInfo};
+
+    fn details(version_code: i32, subscribed: Option<bool>) -> DetailsResponse {
+        DetailsResponse {
+            item: Some(Item {
+                details: Some(DocumentDetails {
+                    app_details: Some(AppDetails {
+                        version_code: Some(version_code),
+                        testing_program_info: subscribed.map(|subscribed| TestingProgramInfo {
+                            subscribed: Some(subscribed),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn detects_own_tracks() {
+        let stable = details(100, None);
+        assert!(!has_own_track(&stable, &details(100, None)));
+        assert!(!has_own_track(&stable, &details(100, Some(false))));
+        assert!(has_own_track(&stable, &details(101, None)));
+        // Enrolled testers may be served the stable build when no test build is live
+        assert!(has_own_track(&stable, &details(100, Some(true))));
+        assert!(!has_own_track(&stable, &DetailsResponse::default()));
+    }
+}
@@ -0,0 +1,265 @@
+use googleplay_protobuf::DetailsResponse;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::sync::{Arc, Mutex, OnceLock};
+use worker::async_trait::async_trait;
+use worker::d1::D1Database;
+use worker::query;
+
+use crate::google_play_client::Channel;
+
+/// D1 database binding holding the `versions` table, see `migrations/`.
+pub const HISTORY_D1_BINDING: &str = "HISTORY";
+
+/// A version of an app as first observed on a channel.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct VersionRecord {
+    pub package_name: String,
+    pub channel: String,
+    pub version_code: i32,
+    pub version_string: Option<String>,
+    pub recent_changes_html: Option<String>,
+    pub info_updated_on: Option<String>,
+    pub download_size: Option<i64>,
+    /// When the version was first seen, in seconds since the epoch.
+    pub first_seen: u64,
+}
+
+impl VersionRecord {
+    /// The version a details response describes, if it has a version code.
+    pub fn from_details(
+        package_name: &str,
+        channel: &Channel,
+        details: &DetailsResponse,
+        seen_at: u64,
+    ) -> Option<Self> {
+        let app = details
+            .item
+            .as_ref()
+            .and_then(|item| item.details.as_ref())
+            .and_then(|details| details.app_details.as_ref())?;
+        Some(VersionRecord {
+            package_name: package_name.to_string(),
+            channel: channel.to_string(),
+            version_code: app.version_code?,
+            version_string: app.version_string.clone(),
+            recent_changes_html: app.recent_changes_html.clone(),
+            info_updated_on: app.info_updated_on.clone(),
+            download_size: app.info_download_size,
+            first_seen: seen_at,
+        })
+    }
+}
+
+/// Every version observed per package and channel. Recording a version that is already known
+/// keeps the first observation.
+#[async_trait(?Send)]
+pub trait HistoryStore: Send + Sync {
+    async fn record(&self, record: &VersionRecord) -> Result<(), String>;
+    /// The versions of a package, newest first, on one channel or on all of them.
+    async fn history(
+        &self,
+        package_name: &str,
+        channel: Option<&Channel>,
+    ) -> Result<Vec<VersionRecord>, String>;
+}
+
+/// History stored in D1.
+pub struct D1HistoryStore {
+    db: D1Database,
+}
+
+impl D1HistoryStore {
+    pub fn new(db: D1Database) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait(?Send)]
+impl HistoryStore for D1HistoryStore {
+    async fn record(&self, record: &VersionRecord) -> Result<(), String> {
+        query!(
+            &self.db,
+            "INSERT OR IGNORE INTO versions (package_name, channel, version_code, version_string, \
+             recent_changes_html, info_updated_on, download_size, first_seen) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            &record.package_name,
+            &record.channel,
+            &record.version_code,
+            &record.version_string,
+            &record.recent_changes_html,
+            &record.info_updated_on,
+            &record.download_size,
+            &record.first_seen,
+        )
+        .map_err(|e| e.to_string())?
+        .run()
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+    }
+
+    async fn history(
+        &self,
+        package_name: &str,
+        channel: Option<&Channel>,
+    ) -> Result<Vec<VersionRecord>, String> {
+        let channel = channel.map(|channel| channel.to_string());
+        query!(
+            &self.db,
+            "SELECT * FROM versions WHERE package_name = ?1 AND (?2 IS NULL OR channel = ?2) \
+             ORDER BY first_seen DESC, version_code DESC",
+            &package_name,
+            &channel,
+        )
+        .map_err(|e| e.to_string())?
+        .all()
+        .await
+        .map_err(|e| e.to_string())?
+        .results::<VersionRecord>()
+        .map_err(|e| e.to_string())
+    }
+}
+
+/// History kept in memory. Used when no D1 database is bound, and in tests.
+#[derive(Default)]
+pub struct MemoryHistoryStore {
+    records: Mutex<Vec<VersionRecord>>,
+}
+
+impl MemoryHistoryStore {
+    /// The history of this isolate, kept across the requests it serves.
+    pub fn shared() -> Arc<MemoryHistoryStore> {
+        static STORE: OnceLock<Arc<MemoryHistoryStore>> = OnceLock::new();
+        STORE.get_or_init(Default::default).clone()
+    }
+}
+
+#[async_trait(?Send)]
+impl HistoryStore for MemoryHistoryStore {
+    async fn record(&self, record: &VersionRecord) -> Result<(), String> {
+        let mut records = self.records.lock().unwrap();
+        let known = records.iter().any(|known| {
+            known.package_name == record.package_name
+                && known.channel == record.channel
+                && known.version_code == record.version_code
+        });
+        if !known {
+            records.push(record.clone());
+        }
+        Ok(())
+    }
+
+    async fn history(
+        &self,
+        package_name: &str,
+        channel: Option<&Channel>,
+    ) -> Result<Vec<VersionRecord>, String> {
+        let channel = channel.map(|channel| channel.to_string());
+        let mut history: Vec<VersionRecord> = self
+            .records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|record| record.package_name == package_name)
+            .filter(|record| {
+                channel
+                    .as_ref()
+                    .is_none_or(|channel| record.channel == *channel)
+            })
+            .cloned()
+            .collect();
+        history.sort_by_key(|record| Reverse((record.first_seen, record.version_code)));
+        Ok(history)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use googleplay_protobuf::{AppDetails, DocumentDetails, Item};
+
+    fn details(version_code: i32, version_string: &str) -> DetailsResponse {
+        DetailsResponse {
+            item: Some(Item {
+                details: Some(DocumentDetails {
+                    app_details: Some(AppDetails {
+                        version_code: Some(version_code),
+                        version_string: Some(version_string.to_string()),
+                        recent_changes_html: Some(format!("Changes in {}", version_string)),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn records_timeline() {
+        let store = MemoryHistoryStore::default();
+        let stable = Channel::stable();
+        let beta = Channel::from_str("beta").unwrap();
+        let record = |channel: &Channel, version_code, version_string, seen_at| {
+            VersionRecord::from_details(
+                "com.example",
+                channel,
+                &details(version_code, version_string),
+                seen_at,
+            )
+            .unwrap()
+        };
+
+        block_on(async {
+            store
+                .record(&record(&stable, 100, "1.0", 10))
+                .await
+                .unwrap();
+            store
+                .record(&record(&beta, 101, "1.1-beta", 20))
+                .await
+                .unwrap();
+            store
+                .record(&record(&stable, 101, "1.1", 30))
+                .await
+                .unwrap();
+            // Seen again later, the first observation stays
+            store
+                .record(&record(&stable, 100, "1.0", 40))
+                .await
+                .unwrap();
+
+            let history = store.history("com.example", None).await.unwrap();
+            let timeline: Vec<(&str, i32, u64)> = history
+                .iter()
+                .map(|record| {
+                    (
+                        record.channel.as_str(),
+                        record.version_code,
+                        record.first_seen,
+                    )
+                })
+                .collect();
+            assert_eq!(
+                timeline,
+                [("stable", 101, 30), ("beta", 101, 20), ("stable", 100, 10)]
+            );
+            assert_eq!(
+                history[0].recent_changes_html.as_deref(),
+                Some("Changes in 1.1")
+            );
+
+            let beta_history = store.history("com.example", Some(&beta)).await.unwrap();
+            assert_eq!(beta_history.len(), 1);
+            assert!(store.history("com.other", None).await.unwrap().is_empty());
+        });
+
+        assert_eq!(
+            VersionRecord::from_details("com.example", &stable, &DetailsResponse::default(), 0),
+            None
+        );
+    }
+}
@@ -0,0 +1,435 @@
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use worker::async_trait::async_trait;
+use worker::KvStore;
+
+use crate::details_cache::FetchedDetails;
+use crate::filename::app_name;
+use crate::google_play_client::{Channel, PlayError};
+use crate::runtime::log;
+use crate::version_history::VersionRecord;
+
+/// KV namespace binding holding the watchlist and the last version seen of each entry.
+pub const WATCHLIST_KV_BINDING: &str = "WATCHLIST";
+
+/// Key of the watchlist JSON in `WATCHLIST`, see `WatchEntry`.
+pub const WATCHLIST_KEY: &str = "watchlist";
+
+/// How many channels are polled at once, to stay clear of Google Play's rate limits.
+const POLL_CONCURRENCY: usize = 4;
+
+/// A package to poll, on the given channels or on every configured channel.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WatchEntry {
+    pub package_name: String,
+    #[serde(default)]
+    pub channels: Vec<String>,
+}
+
+/// A watched package that moved to another version since the last poll.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionChange {
+    /// The listing's title without its subtitle, e.g. `Discord` of `Discord - Talk & Chat`.
+    pub app_name: Option<String>,
+    pub previous_version_code: i32,
+    pub version: VersionRecord,
+}
+
+/// The watchlist and what was seen of it.
+#[async_trait(?Send)]
+pub trait WatchStore: Send + Sync {
+    async fn watchlist(&self) -> Result<Vec<WatchEntry>, String>;
+    async fn last_seen(&self, package_name: &str, channel: &Channel) -> Option<i32>;
+    async fn set_last_seen(
+        &self,
+        package_name: &str,
+        channel: &Channel,
+        version_code: i32,
+    ) -> Result<(), String>;
+    /// Add a package and channel to the watchlist, unless they are watched already.
+    async fn watch(&self, package_name: &str, channel: &Channel) -> Result<(), String>;
+}
+
+/// Add `channel` of `package_name` to `watchlist`, returning whether it changed. An entry
+/// without channels already watches every channel.
+pub fn add_to_watchlist(
+    watchlist: &mut Vec<WatchEntry>,
+    package_name: &str,
+    channel: &Channel,
+) -> bool {
+    let Some(entry) = watchlist
+        .iter_mut()
+        .find(|entry| entry.package_name == package_name)
+    else {
+        watchlist.push(WatchEntry {
+            package_name: package_name.to_string(),
+            channels: vec![channel.to_string()],
+        });
+        return true;
+    };
+    let watched = entry.channels.is_empty()
+        || entry
+            .channels
+            .iter()
+            .any(|watched| Channel::from_str(watched).is_ok_and(|watched| watched == *channel));
+    if !watched {
+        entry.channels.push(channel.to_string());
+    }
+    !watched
+}
+
+fn seen_key(package_name: &str, channel: &Channel) -> String {
+    format!("seen:{}:{}", channel, package_name)
+}
+
+/// Watchlist and last seen versions stored in Workers KV. The watchlist is edited by hand, e.g.
+/// with `wrangler kv key put`.
+pub struct KvWatchStore {
+    kv: KvStore,
+}
+
+impl KvWatchStore {
+    pub fn new(kv: KvStore) -> Self {
+        Self { kv }
+    }
+}
+
+#[async_trait(?Send)]
+impl WatchStore for KvWatchStore {
+    async fn watchlist(&self) -> Result<Vec<WatchEntry>, String> {
+        let watchlist = self
+            .kv
+            .get(WATCHLIST_KEY)
+            .text()
+            .await
+            .map_err(|e| e.to_string())?;
+        match watchlist {
+            Some(json) => serde_json::from_str(&json)
+                .map_err(|e| format!("Invalid '{}' key: {}", WATCHLIST_KEY, e)),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn last_seen(&self, package_name: &str, channel: &Channel) -> Option<i32> {
+        self.kv
+            .get(&seen_key(package_name, channel))
+            .json::<i32>()
+            .await
+            .ok()
+            .flatten()
+    }
+
+    async fn set_last_seen(
+        &self,
+        package_name: &str,
+        channel: &Channel,
+        version_code: i32,
+    ) -> Result<(), String> {
+        self.kv
+            .put(&seen_key(package_name, channel), version_code)
+            .map_err(|e| e.to_string())?
+            .execute()
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Not atomic: KV has no transactions, so two subscriptions at the same moment can lose
+    /// one of the additions.
+    async fn watch(&self, package_name: &str, channel: &Channel) -> Result<(), String> {
+        let mut watchlist = self.watchlist().await?;
+        if !add_to_watchlist(&mut watchlist, package_name, channel) {
+            return Ok(());
+        }
+        self.kv
+            .put(WATCHLIST_KEY, &watchlist)
+            .map_err(|e| e.to_string())?
+            .execute()
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Fetch every watched package and channel with `fetch`, and return the ones whose version
+/// code differs from the last one remembered. The first poll of an entry only remembers its
+/// version; a change is remembered by `remember` once it was handled, so a run that is cut
+/// short finds it again.
+///
+/// `fetch` is expected to check the channel's track like the details endpoints do: channels
+/// without a track of their own (`ChannelUnavailable`) are skipped, and so are stale copies,
+/// which say nothing about the current version.
+pub async fn poll<F, Fut>(
+    store: &dyn WatchStore,
+    channels: &[Channel],
+    now: u64,
+    fetch: F,
+) -> Result<Vec<VersionChange>, String>
+where
+    F: Fn(String, Channel) -> Fut,
+    Fut: Future<Output = Result<Option<FetchedDetails>, PlayError>>,
+{
+    let mut targets = Vec::new();
+    for entry in store.watchlist().await? {
+        if entry.channels.is_empty() {
+            targets.extend(
+                channels
+                    .iter()
+                    .map(|channel| (entry.package_name.clone(), channel.clone())),
+            );
+            continue;
+        }
+        for channel in &entry.channels {
+            match Channel::from_str(channel) {
+                Ok(channel) => targets.push((entry.package_name.clone(), channel)),
+                Err(e) => log!("Skipping watch of {}: {}", entry.package_name, e),
+            }
+        }
+    }
+
+    let changes = stream::iter(targets)
+        .map(|(package_name, channel)| {
+            let details = fetch(package_name.clone(), channel.clone());
+            async move {
+                let details = match details.await {
+                    Ok(Some(fetched)) if !fetched.is_stale() => fetched.details,
+                    Ok(_) | Err(PlayError::ChannelUnavailable { .. }) => return None,
+                    Err(e) => {
+                        log!("Failed to poll {} on {}: {}", package_name, channel, e);
+                        return None;
+                    }
+                };
+                let version = VersionRecord::from_details(&package_name, &channel, &details, now)?;
+                let previous = store.last_seen(&package_name, &channel).await;
+                if previous == Some(version.version_code) {
+                    return None;
+                }
+                if previous.is_none() {
+                    remember_version(store, &package_name, &channel, version.version_code).await;
+                    return None;
+                }
+                let title = details.item.and_then(|item| item.title);
+                Some(VersionChange {
+                    app_name: title.map(|title| app_name(&title).to_string()),
+                    previous_version_code: previous?,
+                    version,
+                })
+            }
+        })
+        .buffer_unordered(POLL_CONCURRENCY)
+        .filter_map(|change| async move { change })
+        .collect()
+        .await;
+    Ok(changes)
+}
+
+/// Remember the new version of a change, so the next poll does not report it again.
+pub async fn remember(store: &dyn WatchStore, change: &VersionChange) {
+    let version = &change.version;
+    match Channel::from_str(&version.channel) {
+        Ok(channel) => {
+            remember_version(store, &version.package_name, &channel, version.version_code).await
+        }
+        Err(e) => log!("Failed to remember {}: {}", version.package_name, e),
+    }
+}
+
+async fn remember_version(
+    store: &dyn WatchStore,
+    package_name: &str,
+    channel: &Channel,
+    version_code: i32,
+) {
+    if let Err(e) = store
+        .set_last_seen(package_name, channel, version_code)
+        .await
+    {
+        log!(
+            "Failed to remember {} version of {}: {}",
+            channel,
+            package_name,
+            e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use googleplay_protobuf::{AppDetails, DetailsResponse, DocumentDetails, Item};
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// A watchlist and last seen versions kept for the lifetime of the store.
+    #[derive(Default)]
+    struct MemoryWatchStore {
+        entries: Mutex<Vec<WatchEntry>>,
+        seen: Mutex<HashMap<String, i32>>,
+    }
+
+    #[async_trait(?Send)]
+    impl WatchStore for MemoryWatchStore {
+        async fn watchlist(&self) -> Result<Vec<WatchEntry>, String> {
+            Ok(self.entries.lock().unwrap().clone())
+        }
+
+        async fn last_seen(&self, package_name: &str, channel: &Channel) -> Option<i32> {
+            self.seen
+                .lock()
+                .unwrap()
+                .get(&seen_key(package_name, channel))
+                .copied()
+        }
+
+        async fn set_last_seen(
+            &self,
+            package_name: &str,
+            channel: &Channel,
+            version_code: i32,
+        ) -> Result<(), String> {
+            self.seen
+                .lock()
+                .unwrap()
+                .insert(seen_key(package_name, channel), version_code);
+            Ok(())
+        }
+
+        async fn watch(&self, package_name: &str, channel: &Channel) -> Result<(), String> {
+            add_to_watchlist(&mut self.entries.lock().unwrap(), package_name, channel);
+            Ok(())
+        }
+    }
+
+    fn details(version_code: i32) -> DetailsResponse {
+        DetailsResponse {
+            item: Some(Item {
+                details: Some(DocumentDetails {
+                    app_details: Some(AppDetails {
+                        version_code: Some(version_code),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn detects_new_versions() {
+        let store = MemoryWatchStore {
+            entries: Mutex::new(vec![
+                WatchEntry {
+                    package_name: String::from("com.discord"),
+                    channels: vec![String::from("beta")],
+                },
+                WatchEntry {
+                    package_name: String::from("com.example"),
+                    channels: Vec::new(),
+                },
+            ]),
+            ..Default::default()
+        };
+        let channels = [Channel::stable(), Channel::from_str("beta").unwrap()];
+        let versions = RefCell::new(HashMap::from([
+            ("com.discord:beta", 100),
+            ("com.example:stable", 7),
+            ("com.example:beta", 8),
+        ]));
+        let poll = |now| {
+            poll(&store, &channels, now, |package_name, channel| {
+                let version = versions
+                    .borrow()
+                    .get(format!("{}:{}", package_name, channel).as_str())
+                    .copied();
+                async move { Ok(version.map(|version| FetchedDetails::fresh(details(version)))) }
+            })
+        };
+
+        block_on(async {
+            // The first poll only remembers what it saw
+            assert!(poll(1).await.unwrap().is_empty());
+            assert!(poll(2).await.unwrap().is_empty());
+
+            versions.borrow_mut().insert("com.discord:beta", 101);
+            let changes = poll(3).await.unwrap();
+            assert_eq!(changes.len(), 1);
+            assert_eq!(changes[0].previous_version_code, 100);
+            assert_eq!(changes[0].version.version_code, 101);
+            assert_eq!(changes[0].version.channel, "beta");
+            assert_eq!(changes[0].version.first_seen, 3);
+
+            // Until it is handled, the change is reported again
+            let again = poll(4).await.unwrap();
+            assert_eq!(again.len(), 1);
+            assert_eq!(again[0].version.version_code, 101);
+            remember(&store, &changes[0]).await;
+            assert!(poll(5).await.unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn skips_unavailable_and_stale_details() {
+        let store = MemoryWatchStore {
+            entries: Mutex::new(vec![WatchEntry {
+                package_name: String::from("com.example"),
+                channels: Vec::new(),
+            }]),
+            ..Default::default()
+        };
+        let beta = Channel::from_str("beta").unwrap();
+        let channels = [Channel::stable(), beta.clone()];
+        let stable_details = RefCell::new(FetchedDetails::fresh(details(7)));
+        let poll = |now| {
+            poll(&store, &channels, now, |package_name, channel| {
+                let result = match channel.is_stable() {
+                    true => Ok(Some(stable_details.borrow().clone())),
+                    false => Err(PlayError::ChannelUnavailable {
+                        channel,
+                        package_name,
+                    }),
+                };
+                async move { result }
+            })
+        };
+
+        block_on(async {
+            assert!(poll(1).await.unwrap().is_empty());
+            assert_eq!(store.last_seen("com.example", &beta).await, None);
+
+            // An old copy served while Google Play fails is not a new version
+            *stable_details.borrow_mut() = FetchedDetails {
+                details: details(6),
+                stale_age: Some(600),
+            };
+            assert!(poll(2).await.unwrap().is_empty());
+            assert_eq!(
+                store.last_seen("com.example", &Channel::stable()).await,
+                Some(7)
+            );
+        });
+    }
+
+    #[test]
+    fn adds_to_watchlist() {
+        let stable = Channel::stable();
+        let beta = Channel::from_str("beta").unwrap();
+        let mut watchlist = vec![WatchEntry {
+            package_name: String::from("com.discord"),
+            channels: vec![String::from("Beta")],
+        }];
+
+        assert!(!add_to_watchlist(&mut watchlist, "com.discord", &beta));
+        assert!(add_to_watchlist(&mut watchlist, "com.discord", &stable));
+        assert_eq!(watchlist[0].channels, ["Beta", "stable"]);
+        assert!(add_to_watchlist(&mut watchlist, "com.example", &beta));
+        assert_eq!(watchlist[1].channels, ["beta"]);
+
+        // Watched on every channel already
+        watchlist[1].channels.clear();
+        assert!(!add_to_watchlist(&mut watchlist, "com.example", &stable));
+        assert_eq!(watchlist.len(), 2);
+    }
+}
@@ -0,0 +1,722 @@
+use futures::future::join_all;
+use gpapi::transport::{HttpRequest, Method, Transport};
+use gpapi::DownloadInfo;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::Duration;
+use worker::async_trait::async_trait;
+use worker::KvStore;
+
+use crate::filename::build_suggested_filename;
+use crate::google_play_client::Channel;
+use crate::runtime::log;
+use crate::signature::{sha256, sign, to_hex};
+use crate::watcher::VersionChange;
+
+/// KV namespace binding holding the webhook subscriptions.
+pub const WEBHOOKS_KV_BINDING: &str = "WEBHOOKS";
+
+/// Event of a delivery, sent as `X-Sniff-Event`.
+pub const VERSION_RELEASED: &str = "version.released";
+
+pub const MIN_SECRET_LEN: usize = 16;
+
+/// A URL subscribed to new versions of a package on a channel. Deliveries are signed with
+/// `secret`, see `signature::sign`. Deliberately not `Debug`, so the secret cannot end up in a
+/// log line.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    pub package_name: String,
+    pub channel: String,
+    pub secret: String,
+}
+
+impl Webhook {
+    /// Validate a subscription. The id is derived from the package, channel and URL, so
+    /// subscribing the same URL again replaces its secret instead of adding a duplicate.
+    pub fn new(url: &str, package_name: &str, channel: &str, secret: &str) -> Result<Self, String> {
+        if !url.starts_with("https://") || url.len() <= "https://".len() {
+            return Err("'url' must be an https:// URL".to_string());
+        }
+        if package_name.is_empty() || package_name.contains(':') {
+            return Err("'package_name' is not a valid package name".to_string());
+        }
+        let channel = Channel::from_str(channel)?;
+        if secret.len() < MIN_SECRET_LEN {
+            return Err(format!(
+                "'secret' must be at least {} characters",
+                MIN_SECRET_LEN
+            ));
+        }
+
+        let target = format!("{}\n{}\n{}", package_name, channel, url);
+        Ok(Webhook {
+            id: to_hex(&sha256(target.as_bytes()))[..16].to_string(),
+            url: url.to_string(),
+            package_name: package_name.to_string(),
+            channel: channel.to_string(),
+            secret: secret.to_string(),
+        })
+    }
+}
+
+/// Webhook subscriptions, listed by package and channel when delivering.
+#[async_trait(?Send)]
+pub trait WebhookStore: Send + Sync {
+    async fn save(&self, webhook: &Webhook) -> Result<(), String>;
+    /// The webhooks subscribed to a package and channel, or all of them.
+    async fn list(&self, target: Option<(&str, &Channel)>) -> Result<Vec<Webhook>, String>;
+    /// Unsubscribe a webhook, returning it if it existed.
+    async fn remove(&self, id: &str) -> Result<Option<Webhook>, String>;
+}
+
+const WEBHOOK_PREFIX: &str = "webhook:";
+
+fn webhook_key(webhook: &Webhook) -> String {
+    format!(
+        "{}{}:{}:{}",
+        WEBHOOK_PREFIX, webhook.package_name, webhook.channel, webhook.id
+    )
+}
+
+/// Subscriptions stored in Workers KV, one key per webhook, prefixed by package and channel.
+pub struct KvWebhookStore {
+    kv: KvStore,
+}
+
+impl KvWebhookStore {
+    pub fn new(kv: KvStore) -> Self {
+        Self { kv }
+    }
+
+    async fn keys(&self, prefix: String) -> Result<Vec<String>, String> {
+        let mut keys = Vec::new();
+        let mut cursor = None;
+        loop {
+            let mut list = self.kv.list().prefix(prefix.clone());
+            if let Some(cursor) = cursor {
+                list = list.cursor(cursor);
+            }
+            let page = list.execute().await.map_err(|e| e.to_string())?;
+            keys.extend(page.keys.into_iter().map(|key| key.name));
+            if page.list_complete || page.cursor.is_none() {
+                return Ok(keys);
+            }
+            cursor = page.cursor;
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Webhook>, String> {
+        self.kv
+            .get(key)
+            .json::<Webhook>()
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[async_trait(?Send)]
+impl WebhookStore for KvWebhookStore {
+    async fn save(&self, webhook: &Webhook) -> Result<(), String> {
+        self.kv
+            .put(&webhook_key(webhook), webhook)
+            .map_err(|e| e.to_string())?
+            .execute()
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn list(&self, target: Option<(&str, &Channel)>) -> Result<Vec<Webhook>, String> {
+        let prefix = match target {
+            Some((package_name, channel)) => {
+                format!("{}{}:{}:", WEBHOOK_PREFIX, package_name, channel)
+            }
+            None => WEBHOOK_PREFIX.to_string(),
+        };
+        let keys = self.keys(prefix).await?;
+        let webhooks = join_all(keys.iter().map(|key| self.get(key))).await;
+        webhooks.into_iter().filter_map(Result::transpose).collect()
+    }
+
+    async fn remove(&self, id: &str) -> Result<Option<Webhook>, String> {
+        let suffix = format!(":{}", id);
+        let keys = self.keys(WEBHOOK_PREFIX.to_string()).await?;
+        let Some(key) = keys.into_iter().find(|key| key.ends_with(&suffix)) else {
+            return Ok(None);
+        };
+        let webhook = self.get(&key).await?;
+        self.kv.delete(&key).await.map_err(|e| e.to_string())?;
+        Ok(webhook)
+    }
+}
+
+/// The JSON body of a `version.released` delivery. Delivery is at least once: until a
+/// subscriber accepts a change, it is delivered again on every poll, so receivers may see a
+/// version more than once.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WebhookPayload {
+    pub event: String,
+    pub package_name: String,
+    pub channel: String,
+    pub app_name: Option<String>,
+    pub version_code: i32,
+    pub version_string: Option<String>,
+    pub previous_version_code: i32,
+    pub suggested_filename: String,
+    pub download_size: Option<i64>,
+    pub info_updated_on: Option<String>,
+    pub recent_changes_html: Option<String>,
+    /// Paths of the version's download info and APK on this Sniff instance.
+    pub download_info_path: String,
+    pub apk_path: String,
+    /// When the new version was detected, in seconds since the epoch.
+    pub detected_at: u64,
+    /// Sizes and hashes of the version's APKs, unless Google Play failed to deliver them.
+    pub download: Option<DownloadMetadata>,
+}
+
+/// What `/v1/download` reports of a version's APKs, without the download URLs and cookies,
+/// which expire long before most receivers act on a delivery.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DownloadMetadata {
+    pub main_apk_size: Option<i64>,
+    pub main_apk_sha1: Option<String>,
+    pub main_apk_sha256: Option<String>,
+    pub splits: Vec<SplitMetadata>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SplitMetadata {
+    pub name: Option<String>,
+    pub size: Option<i64>,
+    pub sha1: Option<String>,
+    pub sha256: Option<String>,
+}
+
+impl From<&DownloadInfo> for DownloadMetadata {
+    fn from(info: &DownloadInfo) -> Self {
+        DownloadMetadata {
+            main_apk_size: info.main_apk.size,
+            main_apk_sha1: info.main_apk.sha1.clone(),
+            main_apk_sha256: info.main_apk.sha256.clone(),
+            splits: info
+                .splits
+                .iter()
+                .map(|split| SplitMetadata {
+                    name: split.name.clone(),
+                    size: split.file.size,
+                    sha1: split.file.sha1.clone(),
+                    sha256: split.file.sha256.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl WebhookPayload {
+    pub fn new(
+        change: &VersionChange,
+        brand_name: &str,
+        download: Option<DownloadMetadata>,
+    ) -> Self {
+        let version = &change.version;
+        let channel = Channel::from_str(&version.channel)
+            .map(|channel| channel.display_name())
+            .unwrap_or_else(|_| version.channel.clone());
+        WebhookPayload {
+            event: VERSION_RELEASED.to_string(),
+            package_name: version.package_name.clone(),
+            channel: version.channel.clone(),
+            app_name: change.app_name.clone(),
+            version_code: version.version_code,
+            version_string: version.version_string.clone(),
+            previous_version_code: change.previous_version_code,
+            suggested_filename: build_suggested_filename(
+                brand_name,
+                change.app_name.as_deref(),
+                &channel,
+                version.version_string.as_deref(),
+            ),
+            download_size: version.download_size,
+            info_updated_on: version.info_updated_on.clone(),
+            recent_changes_html: version.recent_changes_html.clone(),
+            download_info_path: format!(
+                "/v1/download/{}/{}/{}",
+                version.package_name, version.channel, version.version_code
+            ),
+            apk_path: format!(
+                "/v1/apk/{}/{}/{}",
+                version.package_name, version.channel, version.version_code
+            ),
+            detected_at: version.first_seen,
+            download,
+        }
+    }
+}
+
+/// How often a delivery is attempted. The delay doubles after every failed attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub first_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 4,
+            first_delay: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before retry number `retry`, starting at 0.
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.first_delay * 2u32.saturating_pow(retry)
+    }
+}
+
+/// Whether a failed delivery is worth retrying: the receiver is overloaded or broken, rather
+/// than rejecting the payload.
+fn is_retryable(status: u16) -> bool {
+    status == 408 || status == 429 || status >= 500
+}
+
+/// POST a signed payload to a webhook, retrying failures with exponential backoff. `sleep`
+/// waits between attempts. Returns the number of attempts it took.
+pub async fn deliver<S, Fut>(
+    transport: &dyn Transport,
+    webhook: &Webhook,
+    body: &[u8],
+    timestamp: u64,
+    policy: RetryPolicy,
+    sleep: S,
+) -> Result<u32, String>
+where
+    S: Fn(Duration) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let headers = vec![
+        ("Content-Type".to_string(), "application/json".to_string()),
+        ("User-Agent".to_string(), "Sniff-Webhook".to_string()),
+        ("X-Sniff-Event".to_string(), VERSION_RELEASED.to_string()),
+        ("X-Sniff-Webhook".to_string(), webhook.id.clone()),
+        ("X-Sniff-Timestamp".to_string(), timestamp.to_string()),
+        (
+            "X-Sniff-Signature".to_string(),
+            sign(&webhook.secret, timestamp, body),
+        ),
+    ];
+
+    let mut last_error = String::new();
+    for attempt in 1..=policy.attempts {
+        if attempt > 1 {
+            sleep(policy.delay(attempt - 2)).await;
+        }
+        let request = HttpRequest {
+            method: Method::Post,
+            url: webhook.url.clone(),
+            headers: headers.clone(),
+            body: Some(body.to_vec()),
+        };
+        match transport.send(request).await {
+            Ok(response) if (200..300).contains(&response.status) => return Ok(attempt),
+            Ok(response) if !is_retryable(response.status) => {
+                return Err(format!("{} answered {}", webhook.url, response.status));
+            }
+            Ok(response) => last_error = format!("answered {}", response.status),
+            Err(e) => last_error = e.to_string(),
+        }
+    }
+    Err(format!(
+        "Gave up on {} after {} attempts: {}",
+        webhook.url, policy.attempts, last_error
+    ))
+}
+
+/// Deliver every change to the webhooks subscribed to its package and channel. `download`
+/// fetches the download info of a package, channel and version code for the payload, and is
+/// only called for changes with subscribers.
+///
+/// Returns the changes that are done with: those without subscribers, and those at least one
+/// subscriber accepted. The others are meant to be delivered again on the next poll.
+#[allow(clippy::too_many_arguments)]
+pub async fn notify<'a, D, DFut, S, Fut>(
+    store: &dyn WebhookStore,
+    transport: &dyn Transport,
+    changes: &'a [VersionChange],
+    brand_name: &str,
+    timestamp: u64,
+    download: D,
+    sleep: S,
+) -> Vec<&'a VersionChange>
+where
+    D: Fn(String, Channel, i32) -> DFut,
+    DFut: Future<Output = Option<DownloadInfo>>,
+    S: Fn(Duration) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut done = Vec::new();
+    for change in changes {
+        let version = &change.version;
+        let Ok(channel) = Channel::from_str(&version.channel) else {
+            done.push(change);
+            continue;
+        };
+        let webhooks = match store.list(Some((&version.package_name, &channel))).await {
+            Ok(webhooks) => webhooks,
+            Err(e) => {
+                log!("Failed to list webhooks of {}: {}", version.package_name, e);
+                continue;
+            }
+        };
+        if webhooks.is_empty() {
+            done.push(change);
+            continue;
+        }
+
+        let download = download(version.package_name.clone(), channel, version.version_code)
+            .await
+            .as_ref()
+            .map(DownloadMetadata::from);
+        let payload = WebhookPayload::new(change, brand_name, download);
+        let body = match serde_json::to_vec(&payload) {
+            Ok(body) => body,
+            Err(e) => {
+                log!("Failed to encode webhook payload: {}", e);
+                done.push(change);
+                continue;
+            }
+        };
+        let deliveries = webhooks.iter().map(|webhook| {
+            deliver(
+                transport,
+                webhook,
+                &body,
+                timestamp,
+                RetryPolicy::default(),
+                &sleep,
+            )
+        });
+        let mut delivered = false;
+        for (webhook, result) in webhooks.iter().zip(join_all(deliveries).await) {
+            match result {
+                Ok(_) => delivered = true,
+                Err(e) => log!("Webhook {} failed: {}", webhook.id, e),
+            }
+        }
+        if delivered {
+            done.push(change);
+        }
+    }
+    done
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::version_history::VersionRecord;
+    use gpapi::transport::ReqwestTransport;
+    use gpapi::{ApkFile, SplitApk};
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    struct Received {
+        headers: HashMap<String, String>,
+        body: Vec<u8>,
+    }
+
+    /// A local HTTP server standing in for a webhook receiver. It answers one request per
+    /// status in `statuses`, and hands the requests it received back when joined.
+    fn stand_in(statuses: Vec<u16>) -> (String, thread::JoinHandle<Vec<Received>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            statuses
+                .into_iter()
+                .map(|status| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(&stream);
+                    let mut headers = HashMap::new();
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    loop {
+                        line.clear();
+                        reader.read_line(&mut line).unwrap();
+                        let Some((name, value)) = line.trim_end().split_once(": ") else {
+                            break;
+                        };
+                        headers.insert(name.to_ascii_lowercase(), value.to_string());
+                    }
+                    let length = headers["content-length"].parse().unwrap();
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body).unwrap();
+                    write!(
+                        stream,
+                        "HTTP/1.1 {} Stand-in\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        status
+                    )
+                    .unwrap();
+                    Received { headers, body }
+                })
+                .collect()
+        });
+        (url, server)
+    }
+
+    fn webhook(url: String) -> Webhook {
+        Webhook {
+            url,
+            ..Webhook::new(
+                "https://example.com/hook",
+                "com.discord",
+                "beta",
+                "0123456789abcdef",
+            )
+            .unwrap()
+        }
+    }
+
+    /// Subscriptions that never change.
+    struct Subscriptions(Vec<Webhook>);
+
+    #[async_trait(?Send)]
+    impl WebhookStore for Subscriptions {
+        async fn save(&self, _webhook: &Webhook) -> Result<(), String> {
+            Err(String::from("read-only"))
+        }
+
+        async fn list(&self, target: Option<(&str, &Channel)>) -> Result<Vec<Webhook>, String> {
+            Ok(self
+                .0
+                .iter()
+                .filter(|webhook| {
+                    target.is_none_or(|(package_name, channel)| {
+                        webhook.package_name == package_name
+                            && webhook.channel == channel.to_string()
+                    })
+                })
+                .cloned()
+                .collect())
+        }
+
+        async fn remove(&self, _id: &str) -> Result<Option<Webhook>, String> {
+            Err(String::from("read-only"))
+        }
+    }
+
+    fn change() -> VersionChange {
+        VersionChange {
+            app_name: Some(String::from("Discord")),
+            previous_version_code: 100,
+            version: VersionRecord {
+                package_name: String::from("com.discord"),
+                channel: String::from("beta"),
+                version_code: 101,
+                version_string: Some(String::from("101.0 - Beta")),
+                recent_changes_html: Some(String::from("Bug fixes")),
+                info_updated_on: None,
+                download_size: Some(1024),
+                first_seen: 1700000000,
+            },
+        }
+    }
+
+    #[test]
+    fn validates_subscriptions() {
+        let webhook = Webhook::new(
+            "https://example.com/hook",
+            "com.discord",
+            "Beta",
+            "0123456789abcdef",
+        )
+        .unwrap();
+        assert_eq!(webhook.channel, "beta");
+        assert_eq!(webhook.id.len(), 16);
+        // Subscribing again keeps the id
+        let again = Webhook::new(
+            "https://example.com/hook",
+            "com.discord",
+            "beta",
+            "fedcba9876543210",
+        )
+        .unwrap();
+        assert_eq!(webhook.id, again.id);
+
+        let secret = "0123456789abcdef";
+        assert!(Webhook::new("http://example.com/hook", "com.discord", "beta", secret).is_err());
+        assert!(Webhook::new("https://example.com", "", "beta", secret).is_err());
+        assert!(Webhook::new("https://example.com", "com.discord", "a b", secret).is_err());
+        assert!(Webhook::new("https://example.com", "com.discord", "beta", "short").is_err());
+    }
+
+    #[tokio::test]
+    async fn delivers_signed_payload() {
+        let (url, server) = stand_in(vec![204]);
+        let webhook = webhook(url);
+        let info = DownloadInfo {
+            main_apk: ApkFile {
+                download_url: Some(String::from("https://play.googleapis.com/download/1")),
+                size: Some(1024),
+                sha256: Some(String::from("main-sha256")),
+                ..Default::default()
+            },
+            splits: vec![SplitApk {
+                name: Some(String::from("config.arm64_v8a")),
+                file: ApkFile {
+                    size: Some(512),
+                    sha1: Some(String::from("split-sha1")),
+                    ..Default::default()
+                },
+            }],
+            ..Default::default()
+        };
+        let payload = WebhookPayload::new(&change(), "Sniff", Some(DownloadMetadata::from(&info)));
+        let body = serde_json::to_vec(&payload).unwrap();
+
+        let attempts = deliver(
+            &ReqwestTransport::default(),
+            &webhook,
+            &body,
+            1700000042,
+            RetryPolicy::default(),
+            |_| async {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(attempts, 1);
+
+        let received = server.join().unwrap();
+        let headers = &received[0].headers;
+        assert_eq!(headers["x-sniff-event"], "version.released");
+        assert_eq!(headers["x-sniff-timestamp"], "1700000042");
+        assert_eq!(
+            headers["x-sniff-signature"],
+            sign("0123456789abcdef", 1700000042, &received[0].body)
+        );
+        let payload: WebhookPayload = serde_json::from_slice(&received[0].body).unwrap();
+        assert_eq!(payload.version_code, 101);
+        assert_eq!(payload.previous_version_code, 100);
+        assert_eq!(payload.suggested_filename, "Sniff_Discord_Beta_101.0.apk");
+        assert_eq!(payload.apk_path, "/v1/apk/com.discord/beta/101");
+        assert_eq!(payload.recent_changes_html.as_deref(), Some("Bug fixes"));
+        let download = payload.download.unwrap();
+        assert_eq!(download.main_apk_size, Some(1024));
+        assert_eq!(download.main_apk_sha256.as_deref(), Some("main-sha256"));
+        assert_eq!(download.splits[0].name.as_deref(), Some("config.arm64_v8a"));
+        assert_eq!(download.splits[0].size, Some(512));
+        assert_eq!(download.splits[0].sha1.as_deref(), Some("split-sha1"));
+        // Download URLs expire, so they are left out
+        assert!(!String::from_utf8(received[0].body.clone())
+            .unwrap()
+            .contains("play.googleapis.com"));
+    }
+
+    #[tokio::test]
+    async fn retries_with_backoff() {
+        let (url, server) = stand_in(vec![503, 500, 200]);
+        let delays = RefCell::new(Vec::new());
+
+        let attempts = deliver(
+            &ReqwestTransport::default(),
+            &webhook(url),
+            b"{}",
+            1700000042,
+            RetryPolicy::default(),
+            |delay| {
+                delays.borrow_mut().push(delay);
+                async {}
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(attempts, 3);
+        assert_eq!(
+            *delays.borrow(),
+            [Duration::from_secs(2), Duration::from_secs(4)]
+        );
+        assert_eq!(server.join().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up() {
+        // Rejected payloads are not retried
+        let (url, server) = stand_in(vec![410]);
+        let result = deliver(
+            &ReqwestTransport::default(),
+            &webhook(url),
+            b"{}",
+            1700000042,
+            RetryPolicy::default(),
+            |_| async {},
+        )
+        .await;
+        assert!(result.unwrap_err().ends_with("answered 410"));
+        assert_eq!(server.join().unwrap().len(), 1);
+
+        let policy = RetryPolicy {
+            attempts: 2,
+            first_delay: Duration::from_millis(1),
+        };
+        let (url, server) = stand_in(vec![502, 502]);
+        let result = deliver(
+            &ReqwestTransport::default(),
+            &webhook(url),
+            b"{}",
+            1700000042,
+            policy,
+            |_| async {},
+        )
+        .await;
+        assert!(result.unwrap_err().starts_with("Gave up"));
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn reports_changes_done_with() {
+        let (accepting, accepted) = stand_in(vec![204]);
+        let (rejecting, rejected) = stand_in(vec![410]);
+        let store = Subscriptions(vec![
+            webhook(accepting),
+            Webhook {
+                package_name: String::from("com.example"),
+                ..webhook(rejecting)
+            },
+        ]);
+        let for_package = |package_name: &str| {
+            let mut change = change();
+            change.version.package_name = package_name.to_string();
+            change
+        };
+        let changes = [
+            change(),
+            for_package("com.example"),
+            for_package("com.unsubscribed"),
+        ];
+
+        let done = notify(
+            &store,
+            &ReqwestTransport::default(),
+            &changes,
+            "Sniff",
+            1700000042,
+            |_, _, _| async { None },
+            |_| async {},
+        )
+        .await;
+        // Rejected by its only subscriber, so it is delivered again next time
+        let done: Vec<&str> = done
+            .iter()
+            .map(|change| change.version.package_name.as_str())
+            .collect();
+        assert_eq!(done, ["com.discord", "com.unsubscribed"]);
+        assert_eq!(accepted.join().unwrap().len(), 1);
+        assert_eq!(rejected.join().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,542 @@
+//! Zip archives written on the fly, for bundling an app's APKs without buffering them.
+//!
+//! APKs and OBBs are compressed already, so entries are not compressed again. They still use the
+//! deflate method, with the data framed as deflate's stored blocks: `java.util.zip.ZipInputStream`
+//! cannot find the end of a stored entry whose sizes follow in a data descriptor, and they have
+//! to, so an entry can be streamed before its CRC is known. Archives past 4 GiB switch to the
+//! Zip64 records, as do the local headers of entries that may be larger than that.
+
+use futures::stream::{self, Stream, StreamExt};
+use std::future::Future;
+use std::pin::Pin;
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const DATA_DESCRIPTOR: u32 = 0x08074b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x06064b50;
+const ZIP64_END_LOCATOR: u32 = 0x07064b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+
+/// Sizes and CRC follow in a data descriptor; names are UTF-8.
+const FLAGS: u16 = 1 << 3 | 1 << 11;
+const DEFLATE: u16 = 8;
+/// The most data a stored deflate block holds.
+const STORED_BLOCK: usize = 0xFFFF;
+/// 4.5, the version that introduced Zip64, needed to extract any of the archives.
+const VERSION_ZIP64: u16 = 45;
+/// 1980-01-01 00:00, the earliest DOS date, so archives do not depend on the time they are built.
+const DOS_DATE: u16 = 0x0021;
+
+/// CRC-32 as used by zip, with the reflected 0xEDB88320 polynomial.
+pub struct Crc32 {
+    table: [u32; 256],
+    value: u32,
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut crc = i as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 {
+                    0xEDB88320 ^ (crc >> 1)
+                } else {
+                    crc >> 1
+                };
+            }
+            *entry = crc;
+        }
+        Crc32 {
+            table,
+            value: 0xFFFFFFFF,
+        }
+    }
+}
+
+impl Crc32 {
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.value =
+                self.table[((self.value ^ *byte as u32) & 0xFF) as usize] ^ (self.value >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.value ^ 0xFFFFFFFF
+    }
+}
+
+/// Frames data as deflate stored blocks of `STORED_BLOCK` bytes, holding back the rest until
+/// more data or the end of the entry arrives, so the framing does not depend on how the data
+/// was chunked.
+#[derive(Default)]
+pub struct StoredBlocks {
+    pending: Vec<u8>,
+    crc: Crc32,
+    size: u64,
+    compressed_size: u64,
+}
+
+impl StoredBlocks {
+    /// The complete blocks `data` fills, possibly none.
+    pub fn update(&mut self, data: &[u8]) -> Vec<u8> {
+        self.crc.update(data);
+        self.size += data.len() as u64;
+        self.pending.extend_from_slice(data);
+        let full = self.pending.len() / STORED_BLOCK * STORED_BLOCK;
+        let mut blocks = Vec::with_capacity(full + full / STORED_BLOCK * 5);
+        for block in self.pending[..full].chunks(STORED_BLOCK) {
+            put_stored_block(&mut blocks, block, false);
+        }
+        self.pending.drain(..full);
+        self.compressed_size += blocks.len() as u64;
+        blocks
+    }
+
+    /// The last block, with whatever data is left.
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut block = Vec::with_capacity(self.pending.len() + 5);
+        put_stored_block(&mut block, &self.pending, true);
+        self.pending.clear();
+        self.compressed_size += block.len() as u64;
+        block
+    }
+}
+
+fn put_stored_block(buffer: &mut Vec<u8>, data: &[u8], last: bool) {
+    // BFINAL and BTYPE 00, padded to the byte boundary
+    buffer.push(last as u8);
+    put_u16(buffer, data.len() as u16);
+    put_u16(buffer, !(data.len() as u16));
+    buffer.extend_from_slice(data);
+}
+
+/// What `StoredBlocks` turns `size` bytes into.
+fn stored_size(size: u64) -> u64 {
+    size + (size / STORED_BLOCK as u64 + 1) * 5
+}
+
+struct CentralEntry {
+    name: String,
+    crc: u32,
+    size: u64,
+    compressed_size: u64,
+    offset: u64,
+    zip64: bool,
+}
+
+/// Writes the records around the entries' data, and tracks where everything ends up. Every
+/// method returns bytes to append to the archive; the caller appends the data of an entry
+/// between `begin_entry` and `end_entry`.
+#[derive(Default)]
+pub struct ZipWriter {
+    offset: u64,
+    entries: Vec<CentralEntry>,
+    current: Option<CentralEntry>,
+}
+
+impl ZipWriter {
+    /// Open an entry of `size` bytes, if known. Unless it is known to fit in 4 GiB, the local
+    /// header gets a Zip64 extra field, which tells readers the data descriptor has 8 byte sizes.
+    /// `ZipInputStream` before Java 21 ignores the field and goes by the size it read, so entries
+    /// of unknown size only stream there when they do turn out larger.
+    pub fn begin_entry(&mut self, name: &str, size: Option<u64>) -> Vec<u8> {
+        let zip64 = size.is_none_or(|size| stored_size(size) >= u32::MAX as u64);
+        let mut header = Vec::with_capacity(50 + name.len());
+        put_u32(&mut header, LOCAL_HEADER);
+        put_u16(&mut header, VERSION_ZIP64);
+        put_u16(&mut header, FLAGS);
+        put_u16(&mut header, DEFLATE);
+        put_u16(&mut header, 0); // time
+        put_u16(&mut header, DOS_DATE);
+        // CRC and sizes are in the data descriptor
+        put_u32(&mut header, 0);
+        put_u32(&mut header, 0);
+        put_u32(&mut header, 0);
+        put_u16(&mut header, name.len() as u16);
+        put_u16(&mut header, if zip64 { 20 } else { 0 });
+        header.extend_from_slice(name.as_bytes());
+        if zip64 {
+            put_u16(&mut header, 0x0001);
+            put_u16(&mut header, 16);
+            put_u64(&mut header, 0);
+            put_u64(&mut header, 0);
+        }
+
+        self.current = Some(CentralEntry {
+            name: name.to_string(),
+            crc: 0,
+            size: 0,
+            compressed_size: 0,
+            offset: self.offset,
+            zip64,
+        });
+        self.offset += header.len() as u64;
+        header
+    }
+
+    /// Close the current entry, after the blocks of its data have been written.
+    pub fn end_entry(&mut self, blocks: &StoredBlocks) -> Vec<u8> {
+        let mut entry = self.current.take().expect("end_entry without begin_entry");
+        entry.crc = blocks.crc.finish();
+        entry.size = blocks.size;
+        entry.compressed_size = blocks.compressed_size;
+
+        let mut descriptor = Vec::with_capacity(24);
+        put_u32(&mut descriptor, DATA_DESCRIPTOR);
+        put_u32(&mut descriptor, entry.crc);
+        if entry.zip64 || entry.compressed_size >= u32::MAX as u64 {
+            put_u64(&mut descriptor, entry.compressed_size);
+            put_u64(&mut descriptor, entry.size);
+        } else {
+            put_u32(&mut descriptor, entry.compressed_size as u32);
+            put_u32(&mut descriptor, entry.size as u32);
+        }
+        self.offset += entry.compressed_size + descriptor.len() as u64;
+        self.entries.push(entry);
+        descriptor
+    }
+
+    /// The central directory and end records.
+    pub fn finish(self) -> Vec<u8> {
+        let mut directory = Vec::new();
+        for entry in &self.entries {
+            let large_size = entry.size >= u32::MAX as u64;
+            let large_compressed_size = entry.compressed_size >= u32::MAX as u64;
+            let large_offset = entry.offset >= u32::MAX as u64;
+            let mut extra = Vec::new();
+            if large_size || large_compressed_size || large_offset {
+                let mut fields = Vec::new();
+                if large_size {
+                    put_u64(&mut fields, entry.size);
+                }
+                if large_compressed_size {
+                    put_u64(&mut fields, entry.compressed_size);
+                }
+                if large_offset {
+                    put_u64(&mut fields, entry.offset);
+                }
+                put_u16(&mut extra, 0x0001);
+                put_u16(&mut extra, fields.len() as u16);
+                extra.extend_from_slice(&fields);
+            }
+
+            put_u32(&mut directory, CENTRAL_HEADER);
+            put_u16(&mut directory, VERSION_ZIP64);
+            put_u16(&mut directory, VERSION_ZIP64);
+            put_u16(&mut directory, FLAGS);
+            put_u16(&mut directory, DEFLATE);
+            put_u16(&mut directory, 0);
+            put_u16(&mut directory, DOS_DATE);
+            put_u32(&mut directory, entry.crc);
+            put_u32(&mut directory, clamp_u32(entry.compressed_size));
+            put_u32(&mut directory, clamp_u32(entry.size));
+            put_u16(&mut directory, entry.name.len() as u16);
+            put_u16(&mut directory, extra.len() as u16);
+            put_u16(&mut directory, 0); // comment
+            put_u16(&mut directory, 0); // disk
+            put_u16(&mut directory, 0); // internal attributes
+            put_u32(&mut directory, 0); // external attributes
+            put_u32(&mut directory, clamp_u32(entry.offset));
+            directory.extend_from_slice(entry.name.as_bytes());
+            directory.extend_from_slice(&extra);
+        }
+
+        let directory_offset = self.offset;
+        let directory_size = directory.len() as u64;
+        let count = self.entries.len() as u64;
+        let zip64 = directory_offset >= u32::MAX as u64 || count >= u16::MAX as u64;
+        let mut end = directory;
+        if zip64 {
+            let record_offset = directory_offset + directory_size;
+            put_u32(&mut end, ZIP64_END_OF_CENTRAL_DIRECTORY);
+            put_u64(&mut end, 44);
+            put_u16(&mut end, VERSION_ZIP64);
+            put_u16(&mut end, VERSION_ZIP64);
+            put_u32(&mut end, 0);
+            put_u32(&mut end, 0);
+            put_u64(&mut end, count);
+            put_u64(&mut end, count);
+            put_u64(&mut end, directory_size);
+            put_u64(&mut end, directory_offset);
+
+            put_u32(&mut end, ZIP64_END_LOCATOR);
+            put_u32(&mut end, 0);
+            put_u64(&mut end, record_offset);
+            put_u32(&mut end, 1);
+        }
+        put_u32(&mut end, END_OF_CENTRAL_DIRECTORY);
+        put_u16(&mut end, 0);
+        put_u16(&mut end, 0);
+        put_u16(&mut end, count.min(u16::MAX as u64) as u16);
+        put_u16(&mut end, count.min(u16::MAX as u64) as u16);
+        put_u32(&mut end, clamp_u32(directory_size));
+        put_u32(&mut end, clamp_u32(directory_offset));
+        put_u16(&mut end, 0);
+        end
+    }
+}
+
+fn clamp_u32(value: u64) -> u32 {
+    value.min(u32::MAX as u64) as u32
+}
+
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+/// A file of the archive. `source` is handed to the opener once the archive reaches the entry.
+pub struct ZipEntry<T> {
+    pub name: String,
+    /// The size of the data, if known, so entries that fit in 4 GiB skip the Zip64 records.
+    pub size: Option<u64>,
+    pub source: T,
+}
+
+pub type ByteChunks<E> = Pin<Box<dyn Stream<Item = Result<Vec<u8>, E>>>>;
+
+struct Body<E> {
+    chunks: ByteChunks<E>,
+    blocks: StoredBlocks,
+}
+
+struct State<I, O, E> {
+    entries: I,
+    open: O,
+    writer: Option<ZipWriter>,
+    body: Option<Body<E>>,
+}
+
+/// Stream a zip archive of `entries`. Each entry's data is requested from `open` only after
+/// the previous entry was streamed, so at most one upstream body is open at a time. An error
+/// ends the stream, leaving a truncated archive.
+pub fn zip_stream<T, O, Fut, E>(
+    entries: Vec<ZipEntry<T>>,
+    open: O,
+) -> impl Stream<Item = Result<Vec<u8>, E>>
+where
+    O: FnMut(T) -> Fut,
+    Fut: Future<Output = Result<ByteChunks<E>, E>>,
+{
+    let state = State {
+        entries: entries.into_iter(),
+        open,
+        writer: Some(ZipWriter::default()),
+        body: None,
+    };
+    stream::try_unfold(state, |mut state| async move {
+        while let Some(body) = state.body.as_mut() {
+            match body.chunks.next().await {
+                Some(chunk) => {
+                    let blocks = body.blocks.update(&chunk?);
+                    if !blocks.is_empty() {
+                        return Ok(Some((blocks, state)));
+                    }
+                }
+                None => {
+                    let mut body = state.body.take().unwrap();
+                    let mut end = body.blocks.finish();
+                    let writer = state.writer.as_mut().unwrap();
+                    end.extend_from_slice(&writer.end_entry(&body.blocks));
+                    return Ok(Some((end, state)));
+                }
+            }
+        }
+
+        let Some(writer) = state.writer.as_mut() else {
+            return Ok(None);
+        };
+        match state.entries.next() {
+            Some(entry) => {
+                let chunks = (state.open)(entry.source).await?;
+                let header = writer.begin_entry(&entry.name, entry.size);
+                state.body = Some(Body {
+                    chunks,
+                    blocks: StoredBlocks::default(),
+                });
+                Ok(Some((header, state)))
+            }
+            None => {
+                let end = state.writer.take().unwrap().finish();
+                Ok(Some((end, state)))
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::TryStreamExt;
+
+    fn read_u16(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([bytes[at], bytes[at + 1]])
+    }
+
+    fn read_u32(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn computes_crc32() {
+        let mut crc = Crc32::default();
+        assert_eq!(crc.finish(), 0);
+        crc.update(b"The quick brown fox ");
+        crc.update(b"jumps over the lazy dog");
+        assert_eq!(crc.finish(), 0x414FA339);
+    }
+
+    /// The data of a deflate stream made of stored blocks.
+    fn unframe(mut blocks: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        loop {
+            let last = blocks[0] == 1;
+            let len = read_u16(blocks, 1) as usize;
+            assert_eq!(read_u16(blocks, 3), !(len as u16));
+            data.extend_from_slice(&blocks[5..5 + len]);
+            blocks = &blocks[5 + len..];
+            if last {
+                assert!(blocks.is_empty());
+                return data;
+            }
+        }
+    }
+
+    #[test]
+    fn frames_stored_blocks() {
+        let data: Vec<u8> = (0..150_000u32).map(|i| i as u8).collect();
+        let mut blocks = StoredBlocks::default();
+        let mut framed = blocks.update(&data[..1000]);
+        // Nothing until a block is full
+        assert!(framed.is_empty());
+        framed.extend(blocks.update(&data[1000..]));
+        assert_eq!(framed.len(), 2 * (STORED_BLOCK + 5));
+        framed.extend(blocks.finish());
+
+        assert_eq!(blocks.size, data.len() as u64);
+        assert_eq!(blocks.compressed_size, framed.len() as u64);
+        assert_eq!(blocks.compressed_size, stored_size(data.len() as u64));
+        assert_eq!(unframe(&framed), data);
+    }
+
+    #[test]
+    fn streams_archive() {
+        let entries = vec![
+            ZipEntry {
+                name: String::from("base.apk"),
+                size: Some(11),
+                source: vec![b"hello ".to_vec(), b"world".to_vec()],
+            },
+            ZipEntry {
+                name: String::from("manifest.json"),
+                size: Some(2),
+                source: vec![b"{}".to_vec()],
+            },
+        ];
+        let archive: Vec<Vec<u8>> = block_on(
+            zip_stream(entries, |chunks: Vec<Vec<u8>>| async move {
+                let chunks: ByteChunks<String> = Box::pin(stream::iter(chunks.into_iter().map(Ok)));
+                Ok::<_, String>(chunks)
+            })
+            .try_collect(),
+        )
+        .unwrap();
+        let archive = archive.concat();
+
+        // The end record points at the central directory, which points at the local headers
+        let end = archive.len() - 22;
+        assert_eq!(read_u32(&archive, end), END_OF_CENTRAL_DIRECTORY);
+        assert_eq!(read_u16(&archive, end + 10), 2);
+        let mut at = read_u32(&archive, end + 16) as usize;
+        let mut entries = Vec::new();
+        for _ in 0..2 {
+            assert_eq!(read_u32(&archive, at), CENTRAL_HEADER);
+            assert_eq!(read_u16(&archive, at + 10), DEFLATE);
+            let crc = read_u32(&archive, at + 16);
+            let compressed_size = read_u32(&archive, at + 20) as usize;
+            let size = read_u32(&archive, at + 24) as usize;
+            let name_len = read_u16(&archive, at + 28) as usize;
+            let offset = read_u32(&archive, at + 42) as usize;
+            let name = String::from_utf8(archive[at + 46..at + 46 + name_len].to_vec()).unwrap();
+
+            assert_eq!(read_u32(&archive, offset), LOCAL_HEADER);
+            assert_eq!(read_u16(&archive, offset + 8), DEFLATE);
+            // Small entries have no Zip64 extra field
+            assert_eq!(read_u16(&archive, offset + 28), 0);
+            let start = offset + 30 + name_len;
+            let end = start + compressed_size;
+            let data = unframe(&archive[start..end]);
+            assert_eq!(data.len(), size);
+            let mut check = Crc32::default();
+            check.update(&data);
+            assert_eq!(check.finish(), crc);
+            assert_eq!(read_u32(&archive, end), DATA_DESCRIPTOR);
+            assert_eq!(read_u32(&archive, end + 4), crc);
+            assert_eq!(read_u32(&archive, end + 8) as usize, compressed_size);
+            assert_eq!(read_u32(&archive, end + 12) as usize, size);
+
+            entries.push((name, data));
+            at += 46 + name_len;
+        }
+        assert_eq!(
+            entries,
+            [
+                (String::from("base.apk"), b"hello world".to_vec()),
+                (String::from("manifest.json"), b"{}".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn marks_large_entries_as_zip64() {
+        let mut writer = ZipWriter::default();
+        for size in [Some(5 << 30), None] {
+            let header = writer.begin_entry("main.obb", size);
+            // Zip64 extra field with zero sizes, since they are in the data descriptor
+            assert_eq!(read_u16(&header, 28), 20);
+            assert_eq!(read_u16(&header, 38), 0x0001);
+            assert_eq!(read_u16(&header, 40), 16);
+            assert_eq!(&header[42..], [0; 16]);
+            let mut blocks = StoredBlocks::default();
+            blocks.finish();
+            // Which have 8 bytes each, even when the entry turned out small
+            assert_eq!(writer.end_entry(&blocks).len(), 24);
+        }
+    }
+
+    #[test]
+    fn switches_to_zip64() {
+        let mut writer = ZipWriter::default();
+        writer.begin_entry("main.obb", Some(5 << 30));
+        let large = StoredBlocks {
+            size: 5 << 30,
+            compressed_size: stored_size(5 << 30),
+            ..Default::default()
+        };
+        // Zip64 data descriptors carry 8 byte sizes
+        assert_eq!(writer.end_entry(&large).len(), 24);
+        writer.begin_entry("base.apk", Some(10));
+        let small = StoredBlocks {
+            size: 10,
+            compressed_size: stored_size(10),
+            ..Default::default()
+        };
+        assert_eq!(writer.end_entry(&small).len(), 16);
+
+        let end = writer.finish();
+        let locator = end.len() - 22 - 20;
+        assert_eq!(read_u32(&end, locator), ZIP64_END_LOCATOR);
+        assert_eq!(read_u32(&end, locator - 56), ZIP64_END_OF_CENTRAL_DIRECTORY);
+        assert_eq!(read_u32(&end, end.len() - 6), u32::MAX);
+    }
+}